@@ -0,0 +1,74 @@
+//! Sets the environment variables `src/abi.rs` builds the ABI fingerprint from.
+//! `new-plugin` copies this file into every plugin it creates.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let rustc = env::var("RUSTC").unwrap_or(String::from("rustc"));
+    let rustc_version = Command::new(&rustc)
+        .arg("-V")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or(String::from("unknown"));
+
+    println!("cargo:rustc-env=BEST_BOT_ABI_RUSTC={}", rustc_version);
+    println!("cargo:rustc-env=BEST_BOT_ABI_TARGET={}", env::var("TARGET").unwrap_or(String::from("unknown")));
+
+    let lock = read_lock();
+    let package = |name: &str, with_source: bool| lock.as_ref().and_then(|lock| find_package(lock, name, with_source));
+    println!("cargo:rustc-env=BEST_BOT_ABI_TEMPLATE={}", package("template", true).unwrap_or(String::from("unknown")));
+
+    // Only plugins using `best_bot_extension` have it, `abi.rs` then adds its types to the fingerprint.
+    // It is a path dependency in BEST-Bot, so only the version is compared
+    println!("cargo:rustc-check-cfg=cfg(best_bot_extension)");
+    match package("best-bot-extension", false) {
+        Some(version) => {
+            println!("cargo:rustc-cfg=best_bot_extension");
+            println!("cargo:rustc-env=BEST_BOT_ABI_EXTENSION={}", version);
+        },
+        None => println!("cargo:rustc-env=BEST_BOT_ABI_EXTENSION=none"),
+    }
+}
+
+/// The nearest Cargo.lock
+fn read_lock() -> Option<String> {
+    let mut dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    loop {
+        let lock = dir.join("Cargo.lock");
+        if lock.is_file() {
+            println!("cargo:rerun-if-changed={}", lock.display());
+            let mut data = String::new();
+            return File::open(&lock).and_then(|mut file| file.read_to_string(&mut data)).ok().map(|_| data);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+/// The version of a package in the Cargo.lock, with its source if `with_source`. The source of a git dependency includes the commit
+fn find_package(lock: &str, name: &str, with_source: bool) -> Option<String> {
+    for package in lock.split("[[package]]").skip(1) {
+        let value = |key: &str| {
+            package.lines()
+                .find(|line| line.starts_with(key) && line[key.len()..].trim_start().starts_with('='))
+                .map(|line| line.splitn(2, '=').nth(1).unwrap_or("").trim().trim_matches('"').to_string())
+        };
+        if value("name").as_ref().map(|n| n.as_str()) == Some(name) {
+            let version = value("version").unwrap_or(String::from("unknown"));
+            return Some(match value("source") {
+                Some(ref source) if with_source => format!("{} {}", version, source),
+                _ => version,
+            });
+        }
+    }
+    None
+}
@@ -0,0 +1,173 @@
+//! What BEST-Bot offers plugins using api v2 on top of `template::plugin_api_v2`: posting messages,
+//! a bus the plugins use to share data with each other, and an event when the config is reloaded.
+//!
+//! A plugin using it exports `load_extended` instead of `load`, returning a `Plugin` from this crate:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub fn load_extended() -> *mut best_bot_extension::Plugin {
+//!     Box::into_raw(Box::new(MyPlugin::default()))
+//! }
+//! ```
+//!
+//! BEST-Bot calls `on_extension_load` right after `on_plugin_load`, with the `Sender` the plugin
+//! sends the requests in this crate with. The events in this crate come to `extension_event`,
+//! messages on the bus for the topics the plugin subscribed to and the events in `extension_subscript`.
+
+extern crate serde_json;
+extern crate template;
+
+use serde_json::Value;
+
+use template::plugin_api_v2;
+
+use std::sync::mpsc;
+
+/// A message published on the bus
+#[derive(Clone, Debug, PartialEq)]
+pub struct BusMessage {
+    pub topic: String,
+    /// The name of the plugin that published it
+    pub publisher: String,
+    pub payload: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    /// Sends `payload` to every plugin subscribed to `topic`, except the plugin itself
+    Publish { topic: String, payload: Value },
+    /// Sends the messages published on the topic to the plugin
+    Subscribe(String),
+    Unsubscribe(String),
+    /// Posts `text` in the channel with the bot's token
+    PostMessage { channel: String, text: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    /// How many plugins the message is sent to
+    Published(usize),
+    Subscribed,
+    Unsubscribed,
+    /// Why the message was not posted, if it was not
+    MessagePosted(Result<(), String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventSubscribe {
+    ConfigChanged,
+}
+
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A message on a topic the plugin is subscribed to
+    BusMessage(&'a BusMessage),
+    /// The config file of BEST-Bot was reloaded and has changed
+    ConfigChanged,
+}
+
+/// The signature of `Plugin`, BEST-Bot and the plugins add it to their ABI fingerprint.
+/// Change it together with the trait
+pub static PLUGIN_SIGNATURE: &'static str = "\
+trait Plugin: plugin_api_v2::Plugin + Send + Sync {
+    fn on_extension_load(&mut self, sender: Sender);
+    fn extension_subscript(&self) -> Vec<EventSubscribe>;
+    fn extension_event(&self, event: Event);
+}";
+
+/// A plugin using api v2 and this crate
+pub trait Plugin: plugin_api_v2::Plugin + Send + Sync {
+    /// Called once right after `on_plugin_load`, the plugin can still change itself
+    fn on_extension_load(&mut self, sender: Sender);
+
+    /// The events in this crate the plugin wants, bus messages are subscribed to with `Request::Subscribe`
+    fn extension_subscript(&self) -> Vec<EventSubscribe>;
+
+    fn extension_event(&self, event: Event);
+}
+
+/// A request on its way to BEST-Bot, with the plugin that sent it and where the reply goes
+pub struct Envelope {
+    pub plugin: String,
+    pub request: Request,
+    pub reply: mpsc::Sender<Reply>,
+}
+
+/// What BEST-Bot receives the requests of every plugin on
+pub type Receiver = mpsc::Receiver<Envelope>;
+
+/// What a plugin sends its requests with. Every plugin gets its own,
+/// so BEST-Bot knows which plugin sent a request
+#[derive(Clone)]
+pub struct Sender {
+    plugin: String,
+    channel: mpsc::Sender<Envelope>,
+}
+
+impl Sender {
+    /// Sends the request and waits for the reply
+    pub fn send(&self, request: Request) -> Result<Reply, String> {
+        let (reply, answer) = mpsc::channel();
+        self.channel.send(Envelope {
+            plugin: self.plugin.clone(),
+            request: request,
+            reply: reply,
+        }).map_err(|_| String::from("BEST-Bot does not take requests any more"))?;
+        answer.recv().map_err(|_| String::from("BEST-Bot did not answer the request"))
+    }
+
+    /// The plugin the requests are sent for
+    pub fn plugin(&self) -> &str {
+        &self.plugin
+    }
+}
+
+/// Makes the `Sender` of every plugin, the requests from all of them end up in the same `Receiver`
+#[derive(Clone)]
+pub struct Host {
+    channel: mpsc::Sender<Envelope>,
+}
+
+impl Host {
+    pub fn sender(&self, plugin: &str) -> Sender {
+        Sender {
+            plugin: plugin.to_string(),
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+pub fn channel() -> (Host, Receiver) {
+    let (sender, receiver) = mpsc::channel();
+    (Host { channel: sender }, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn requests_carry_the_plugin_name() {
+        let (host, receiver) = channel();
+        let sender = host.sender("calendar");
+
+        let answering = thread::spawn(move || {
+            let envelope = receiver.recv().unwrap();
+            assert_eq!(envelope.plugin, "calendar");
+            assert_eq!(envelope.request, Request::Subscribe(String::from("events")));
+            envelope.reply.send(Reply::Subscribed).unwrap();
+        });
+
+        assert_eq!(sender.send(Request::Subscribe(String::from("events"))), Ok(Reply::Subscribed));
+        answering.join().unwrap();
+    }
+
+    #[test]
+    fn fails_when_the_host_is_gone() {
+        let (host, receiver) = channel();
+        let sender = host.sender("calendar");
+        drop(receiver);
+        assert!(sender.send(Request::Unsubscribe(String::from("events"))).is_err());
+    }
+}
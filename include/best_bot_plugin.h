@@ -0,0 +1,107 @@
+/*
+ * Plugin api v3 for BEST-Bot: a plain C interface, so a plugin can be written in any language
+ * and built with any compiler. Build the plugin as a shared library (.so), put it in the plugin
+ * folder and export the functions at the end of this file.
+ *
+ * Strings are NUL terminated UTF-8. A string BEST-Bot gives the plugin is only valid during the
+ * call it is given in, copy it to keep it.
+ */
+
+#ifndef BEST_BOT_PLUGIN_H
+#define BEST_BOT_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BEST_BOT_PLUGIN_API 3
+
+/* The events, subscribe with a bit per event: (1 << BEST_BOT_EVENT_STANDARD_MESSAGE) */
+#define BEST_BOT_EVENT_STANDARD_MESSAGE 1
+#define BEST_BOT_EVENT_CONFIG_CHANGED   2
+
+#define BEST_BOT_LOG_ERROR 1
+#define BEST_BOT_LOG_WARN  2
+#define BEST_BOT_LOG_INFO  3
+#define BEST_BOT_LOG_DEBUG 4
+#define BEST_BOT_LOG_TRACE 5
+
+/* The requests for BestBotHostApi.request */
+#define BEST_BOT_REQUEST_API_TOKEN               1
+#define BEST_BOT_REQUEST_ADMIN_API_TOKEN         2
+#define BEST_BOT_REQUEST_WEBHOOKS_INCOMING_TOKEN 3
+#define BEST_BOT_REQUEST_WEBHOOKS_OUTGOING_TOKEN 4
+#define BEST_BOT_REQUEST_CHANNEL_NAME            5 /* the argument is the channel id */
+#define BEST_BOT_REQUEST_CONFIG_PATH             6 /* the folder for the plugin's config file */
+
+/* Opaque, only passed back to the host functions */
+typedef struct BestBotHost BestBotHost;
+
+/* A message in a channel, a field is NULL if Slack did not send it */
+typedef struct {
+    const char *channel;
+    const char *user;
+    const char *text;
+    const char *ts;
+    const char *thread_ts;
+} BestBotMessage;
+
+typedef struct {
+    uint32_t kind;                  /* BEST_BOT_EVENT_... */
+    const BestBotMessage *message;  /* only for BEST_BOT_EVENT_STANDARD_MESSAGE, otherwise NULL */
+} BestBotEvent;
+
+/*
+ * What BEST-Bot offers the plugin, valid until best_bot_plugin_free returns.
+ * The functions can be called from any thread.
+ */
+typedef struct {
+    /* sizeof(BestBotHostApi) in BEST-Bot, fields are only ever added at the end */
+    uint32_t size;
+    const BestBotHost *host;
+
+    void (*log)(const BestBotHost *host, uint32_t level, const char *message);
+
+    /*
+     * Asks BEST-Bot for a value. The answer is written to buffer with a NUL after it, cut to
+     * fit in len bytes. Returns the length of the whole answer, so a larger buffer can be used
+     * if it is len or more, or -1 if there is no answer (e.g. a token that is not configured).
+     * BEST-Bot answers once it is connected to Slack, in best_bot_plugin_new it returns -1.
+     */
+    int64_t (*request)(const BestBotHost *host, uint32_t request, const char *argument, char *buffer, size_t len);
+
+    /* Posts text in the channel with the bot's token, returns 0 if it was posted */
+    int32_t (*post_message)(const BestBotHost *host, const char *channel, const char *text);
+} BestBotHostApi;
+
+/* Exported by the plugin */
+
+/* Returns BEST_BOT_PLUGIN_API */
+uint32_t api_version(void);
+
+/* The name of the plugin, a static string */
+const char *best_bot_plugin_name(void);
+
+/* The events the plugin wants, a bit per event */
+uint32_t best_bot_plugin_subscriptions(void);
+
+/*
+ * Creates the plugin, the returned pointer is passed to the other functions. NULL is a failure.
+ * log and post_message work here, request does not: ask for values in best_bot_plugin_event.
+ */
+void *best_bot_plugin_new(const BestBotHostApi *host);
+
+/* Handles an event. It can be called from several threads at the same time */
+void best_bot_plugin_event(void *plugin, const BestBotEvent *event);
+
+/* Frees the plugin, no other function is called with it afterwards */
+void best_bot_plugin_free(void *plugin);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BEST_BOT_PLUGIN_H */
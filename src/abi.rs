@@ -0,0 +1,185 @@
+//! The ABI fingerprint BEST-Bot compares with a plugin's before calling its `load`.
+//!
+//! `new-plugin` copies this file and `build.rs` into every plugin it creates,
+//! so it must only depend on `template`, the environment variables set by `build.rs`
+//! and, when `build.rs` finds it in Cargo.lock, `best_bot_extension` imported as `extension`.
+
+use template::plugin_api_v1;
+use template::plugin_api_v2;
+use template::slack::api::MessageStandard;
+
+#[cfg(best_bot_extension)]
+use extension;
+
+use std::mem::{align_of, size_of};
+
+/// The name of the function a plugin exports its fingerprint with:
+/// `extern "C" fn(buffer: *mut u8, len: usize) -> usize` writes up to `len` bytes and returns the full length
+pub static SYMBOL: &'static [u8] = b"abi_fingerprint\0";
+
+/// The signatures of the traits in `template` the plugins implement. They cannot be read from
+/// the crate, so they are written by hand: change them when `template` changes the traits
+static TEMPLATE_SIGNATURES: &'static str = "\
+trait Name {
+    fn name(&self) -> String;
+}
+trait plugin_api_v1::Plugin: Name {
+    fn on_plugin_load(&mut self, slack: Slack, config_path: PathBuf);
+    fn event_subscript(&self) -> Vec<EventSubscribe>;
+    fn event(&self, event: Event);
+}
+trait plugin_api_v2::Plugin: Name {
+    fn on_plugin_load(&mut self, logger: LoggerSender, sender: Sender);
+    fn event_subscript(&self) -> Vec<EventSubscribe>;
+    fn event(&self, event: Event);
+}";
+
+/// `template=<version and source>;rustc=<version>;target=<triple>;layout=<hash>;extension=<version or none>;extension_layout=<hash>`
+pub fn fingerprint() -> String {
+    format!(
+        "template={};rustc={};target={};layout={:016x};extension={};extension_layout={:016x}",
+        env!("BEST_BOT_ABI_TEMPLATE"),
+        env!("BEST_BOT_ABI_RUSTC"),
+        env!("BEST_BOT_ABI_TARGET"),
+        layout(),
+        env!("BEST_BOT_ABI_EXTENSION"),
+        extension_layout()
+    )
+}
+
+/// A hash of the size and alignment of the types passed between BEST-Bot and the plugins, and of the traits' signatures
+fn layout() -> u64 {
+    let layouts = [
+        (size_of::<plugin_api_v1::Event>(), align_of::<plugin_api_v1::Event>()),
+        (size_of::<plugin_api_v1::EventSubscribe>(), align_of::<plugin_api_v1::EventSubscribe>()),
+        (size_of::<plugin_api_v1::Slack>(), align_of::<plugin_api_v1::Slack>()),
+        (size_of::<plugin_api_v2::Event>(), align_of::<plugin_api_v2::Event>()),
+        (size_of::<plugin_api_v2::EventSubscribe>(), align_of::<plugin_api_v2::EventSubscribe>()),
+        (size_of::<plugin_api_v2::Request>(), align_of::<plugin_api_v2::Request>()),
+        (size_of::<plugin_api_v2::Reply>(), align_of::<plugin_api_v2::Reply>()),
+        (size_of::<plugin_api_v2::Sender>(), align_of::<plugin_api_v2::Sender>()),
+        (size_of::<MessageStandard>(), align_of::<MessageStandard>()),
+    ];
+    hash(&layouts, TEMPLATE_SIGNATURES)
+}
+
+/// `layout` for the types and the trait in `best_bot_extension`
+#[cfg(best_bot_extension)]
+fn extension_layout() -> u64 {
+    let layouts = [
+        (size_of::<extension::BusMessage>(), align_of::<extension::BusMessage>()),
+        (size_of::<extension::Request>(), align_of::<extension::Request>()),
+        (size_of::<extension::Reply>(), align_of::<extension::Reply>()),
+        (size_of::<extension::EventSubscribe>(), align_of::<extension::EventSubscribe>()),
+        (size_of::<extension::Event>(), align_of::<extension::Event>()),
+        (size_of::<extension::Sender>(), align_of::<extension::Sender>()),
+    ];
+    hash(&layouts, extension::PLUGIN_SIGNATURE)
+}
+
+#[cfg(not(best_bot_extension))]
+fn extension_layout() -> u64 {
+    0
+}
+
+/// FNV-1a, the hash has to be the same in every build
+fn hash(layouts: &[(usize, usize)], signatures: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |byte: u8| {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    };
+    for &(size, align) in layouts {
+        for &byte in (size as u64).to_le_bytes().iter().chain((align as u64).to_le_bytes().iter()) {
+            add(byte);
+        }
+    }
+    for &byte in signatures.as_bytes() {
+        add(byte);
+    }
+    hash
+}
+
+/// The parts of two fingerprints that differ, e.g. `rustc: 1.40.0 here, 1.41.0 in the plugin`.
+/// The `best_bot_extension` parts are skipped for a plugin not using it
+pub fn differences(ours: &str, theirs: &str) -> Vec<String> {
+    let part = |fingerprint: &str, key: &str| {
+        fingerprint.split(';')
+            .find(|part| part.starts_with(key) && part[key.len()..].starts_with('='))
+            .map(|part| part[key.len() + 1..].to_string())
+            .unwrap_or(String::from("missing"))
+    };
+    let uses_extension = part(theirs, "extension") != "none";
+
+    ["template", "rustc", "target", "layout", "extension", "extension_layout"].iter()
+        .filter(|key| uses_extension || !key.starts_with("extension"))
+        .filter_map(|key| {
+            let (ours, theirs) = (part(ours, key), part(theirs, key));
+            if ours == theirs {
+                None
+            } else {
+                Some(format!("{}: {} here, {} in the plugin", key, ours, theirs))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OURS: &'static str = "template=0.1.0 git+https://example.com/template#abc;rustc=rustc 1.40.0;target=x86_64-unknown-linux-gnu;layout=00000000000000aa;extension=0.1.0;extension_layout=00000000000000bb";
+
+    #[test]
+    fn compares_fingerprints() {
+        let ours = fingerprint();
+        assert!(differences(&ours, &ours).is_empty());
+        assert!(ours.starts_with("template="));
+        assert!(ours.contains(";extension=0.1.0;"), "{}", ours);
+        assert!(!ours.ends_with("extension_layout=0000000000000000"));
+
+        let theirs = ours.replace("rustc=", "rustc=old ");
+        assert_eq!(differences(&ours, &theirs).len(), 1);
+        assert!(differences(&ours, &theirs)[0].starts_with("rustc: "));
+    }
+
+    #[test]
+    fn names_every_difference() {
+        let theirs = OURS.replace("#abc", "#def").replace("layout=00000000000000aa", "layout=00000000000000cc");
+        assert_eq!(differences(OURS, &theirs), vec![
+            String::from("template: 0.1.0 git+https://example.com/template#abc here, 0.1.0 git+https://example.com/template#def in the plugin"),
+            String::from("layout: 00000000000000aa here, 00000000000000cc in the plugin"),
+        ]);
+
+        let theirs = OURS.replace("extension=0.1.0", "extension=0.2.0").replace("00000000000000bb", "00000000000000dd");
+        assert_eq!(differences(OURS, &theirs), vec![
+            String::from("extension: 0.1.0 here, 0.2.0 in the plugin"),
+            String::from("extension_layout: 00000000000000bb here, 00000000000000dd in the plugin"),
+        ]);
+    }
+
+    #[test]
+    fn skips_the_extension_for_plugins_not_using_it() {
+        let theirs = OURS.replace("extension=0.1.0", "extension=none").replace("00000000000000bb", "0000000000000000");
+        assert!(differences(OURS, &theirs).is_empty());
+    }
+
+    #[test]
+    fn missing_parts_differ() {
+        assert_eq!(differences(OURS, "something else").len(), 6);
+        // A fingerprint from before the extension crate was in it
+        let old = "template=0.1.0 git+https://example.com/template#abc;rustc=rustc 1.40.0;target=x86_64-unknown-linux-gnu;layout=00000000000000aa";
+        assert_eq!(differences(OURS, old), vec![
+            String::from("extension: 0.1.0 here, missing in the plugin"),
+            String::from("extension_layout: 00000000000000bb here, missing in the plugin"),
+        ]);
+    }
+
+    #[test]
+    fn the_signatures_are_part_of_the_layout() {
+        let layouts = [(8, 8), (24, 8)];
+        assert_eq!(hash(&layouts, TEMPLATE_SIGNATURES), hash(&layouts, TEMPLATE_SIGNATURES));
+        assert!(hash(&layouts, TEMPLATE_SIGNATURES) != hash(&layouts, &TEMPLATE_SIGNATURES.replace("&self", "&mut self")));
+        assert!(hash(&layouts, "") != hash(&[(8, 8), (32, 8)], ""));
+    }
+}
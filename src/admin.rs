@@ -0,0 +1,351 @@
+use template::slack::api::MessageStandard;
+use template::api::Channel;
+
+use config::CONFIG;
+use logger;
+use plugin_bus::PluginBus;
+use plugin_manager::PluginManager;
+use slack_api;
+use slack_bot;
+use stats;
+
+use log::LevelFilter;
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+/// Slack cuts long messages, so the replies are kept below this many characters
+static MAX_REPLY: usize = 3500;
+
+static HELP: &'static str = "\
+```
+plugins                  List the plugins with their api version, status and subscriptions
+reload <plugin>          Unload the plugin and load it again from its file
+disable <plugin>         Stop sending events to the plugin
+enable <plugin>          Send events to the plugin again
+loglevel <filter>        Replace the log filter, e.g. info,plugin:karma=debug
+logs <plugin> [level] [count]
+                         The newest log records of a plugin
+channels refresh         Look up every known channel again
+status                   Uptime, reconnects and event counters
+```";
+
+/// An admin command, see `HELP`
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Help,
+    Plugins,
+    Reload(&'a str),
+    Disable(&'a str),
+    Enable(&'a str),
+    LogLevel(&'a str),
+    Logs(Vec<&'a str>),
+    RefreshChannels,
+    Status,
+}
+
+/// The command in a message, `None` if it is not one
+fn parse(text: &str) -> Option<Command> {
+    let args: Vec<&str> = text.split_whitespace().collect();
+    let command = match (args.first(), args.get(1)) {
+        (Some(&"help"), None) => Command::Help,
+        (Some(&"plugins"), None) => Command::Plugins,
+        (Some(&"reload"), Some(&plugin)) => Command::Reload(plugin),
+        (Some(&"disable"), Some(&plugin)) => Command::Disable(plugin),
+        (Some(&"enable"), Some(&plugin)) => Command::Enable(plugin),
+        (Some(&"loglevel"), Some(&filter)) => Command::LogLevel(filter),
+        (Some(&"logs"), _) => Command::Logs(args[1..].to_vec()),
+        (Some(&"channels"), Some(&"refresh")) => Command::RefreshChannels,
+        (Some(&"status"), None) => Command::Status,
+        _ => return None,
+    };
+    Some(command)
+}
+
+/// What the admin commands work on, owned by the Slack handler
+pub struct Context<'a> {
+    pub plugins: &'a Arc<RwLock<PluginManager>>,
+    pub bus: &'a Arc<PluginBus>,
+    pub conversation: &'a Arc<RwLock<BTreeMap<String, Channel>>>,
+}
+
+/// Handles a direct message to the bot from an admin (see `admins` in the config).
+/// Returns true if the message was an admin command
+pub fn handle(message: &MessageStandard, context: &Context) -> bool {
+    let (user, channel, text) = match (message.user.as_ref(), message.channel.as_ref(), message.text.as_ref()) {
+        (Some(user), Some(channel), Some(text)) => (user, channel, text),
+        _ => return false,
+    };
+
+    // Only direct messages, their channel ids start with a D
+    if !channel.starts_with('D') || !CONFIG.get().is_admin(user) {
+        return false;
+    }
+
+    let command = match parse(text) {
+        Some(command) => command,
+        None => return false,
+    };
+    let reply = match command {
+        Command::Help => HELP.to_string(),
+        Command::Plugins => plugins(context),
+        Command::Reload(plugin) => reload(context, plugin),
+        Command::Disable(plugin) => set_enabled(context, plugin, false),
+        Command::Enable(plugin) => set_enabled(context, plugin, true),
+        Command::LogLevel(filter) => match logger::set_filter(filter) {
+            Ok(_) => format!("The log filter is now `{}`, until the config is reloaded", filter),
+            Err(e) => format!("Invalid filter: {}", e),
+        },
+        Command::Logs(args) => logs(&args),
+        Command::RefreshChannels => {
+            stats::count("admin commands");
+            info!("Admin command from {}: '{}'", user, text);
+            refresh_channels(channel, context.conversation.clone());
+            return true;
+        },
+        Command::Status => status(context),
+    };
+
+    stats::count("admin commands");
+    info!("Admin command from {}: '{}'", user, text);
+    reply_to(channel, reply);
+    true
+}
+
+/// Posts the reply from another thread, so the RTM thread is not kept waiting on the Web API
+fn reply_to(channel: &str, reply: String) {
+    let channel = channel.to_string();
+    thread::spawn(move || {
+        if let Err(e) = slack_bot::post_message(&channel, &reply) {
+            error!("Failed to reply to an admin command in '{}' ({})", channel, e);
+        }
+    });
+}
+
+/// `plugins`: every loaded plugin
+fn plugins(context: &Context) -> String {
+    let plugins = context.plugins.read().unwrap().describe();
+    if plugins.is_empty() {
+        return String::from("No plugins are loaded");
+    }
+
+    let mut lines = vec![format!("{:<25} {:<4} {:<9} {}", "NAME", "API", "STATUS", "SUBSCRIPTIONS")];
+    for plugin in plugins {
+        lines.push(format!(
+            "{:<25} {:<4} {:<9} {}",
+            plugin.name,
+            plugin.api,
+            if plugin.enabled { "enabled" } else { "disabled" },
+            plugin.subscriptions.join(", ")
+        ));
+    }
+    format!("```\n{}\n```", lines.join("\n"))
+}
+
+/// `reload <plugin>`: the plugin is taken off the bus while its library is swapped. The new library
+/// is loaded without holding the lock on the plugins, so the events for the others are not held up
+fn reload(context: &Context, name: &str) -> String {
+    context.bus.unregister(name);
+
+    let (old, loader) = {
+        let mut plugins = context.plugins.write().unwrap();
+        (plugins.take(name), plugins.loader())
+    };
+
+    let result = match old {
+        Ok(old) => {
+            // The old library must be unloaded first, opening the same file again would only return it
+            let path = old.path().to_path_buf();
+            drop(old);
+            info!("Unloaded plugin: {}", name);
+
+            let loaded = loader.open(path.clone());
+            context.plugins.write().unwrap().add(path, loaded)
+        },
+        Err(e) => Err(e),
+    };
+
+    if let Some(plugin) = context.plugins.read().unwrap().extension_plugin(name) {
+        context.bus.register(&plugin);
+    }
+
+    match result {
+        Ok(_) => format!("Reloaded '{}'", name),
+        Err(e) => {
+            warn!("Failed to reload '{}' ({})", name, e);
+            format!("Failed to reload '{}': {}", name, e)
+        },
+    }
+}
+
+/// `enable <plugin>` and `disable <plugin>`
+fn set_enabled(context: &Context, name: &str, enabled: bool) -> String {
+    let plugin = {
+        let mut plugins = context.plugins.write().unwrap();
+        if let Err(e) = plugins.set_enabled(name, enabled) {
+            return e;
+        }
+        plugins.extension_plugin(name)
+    };
+
+    if let Some(plugin) = plugin {
+        if enabled {
+            context.bus.register(&plugin);
+        } else {
+            context.bus.unregister(name);
+        }
+    }
+
+    info!("The plugin '{}' is {}", name, if enabled { "enabled" } else { "disabled" });
+    format!("'{}' is {}", name, if enabled { "enabled" } else { "disabled" })
+}
+
+/// `channels refresh`: runs from its own thread, since every channel is a Web API call
+fn refresh_channels(reply_channel: &str, conversation: Arc<RwLock<BTreeMap<String, Channel>>>) {
+    let reply_channel = reply_channel.to_string();
+    thread::spawn(move || {
+        let ids: Vec<String> = conversation.read().unwrap().keys().cloned().collect();
+        let reply = match slack_api::client() {
+            Ok(client) => {
+                let failed: Vec<String> = ids.iter()
+                    .filter_map(|id| slack_bot::fetch_conversation(&client, &conversation, id).err().map(|e| format!("{}: {}", id, e)))
+                    .collect();
+                if failed.is_empty() {
+                    format!("Refreshed {} channels", ids.len())
+                } else {
+                    format!("Refreshed {} of {} channels, failed:\n{}", ids.len() - failed.len(), ids.len(), failed.join("\n"))
+                }
+            },
+            Err(e) => format!("Cannot connect to Slack ({})", e),
+        };
+
+        if let Err(e) = slack_bot::post_message(&reply_channel, &reply) {
+            error!("Failed to reply to an admin command in '{}' ({})", reply_channel, e);
+        }
+    });
+}
+
+/// `status`: uptime, reconnects and the event counters
+fn status(context: &Context) -> String {
+    let plugins = context.plugins.read().unwrap().describe();
+    let enabled = plugins.iter().filter(|p| p.enabled).count();
+
+    let mut lines = vec![
+        format!("{:<20} {}", "uptime", stats::format_duration(stats::uptime())),
+        format!("{:<20} {}", "reconnects", stats::get("reconnects")),
+        format!("{:<20} {} ({} enabled)", "plugins", plugins.len(), enabled),
+        format!("{:<20} {}", "channels", context.conversation.read().unwrap().len()),
+        format!("{:<20} {}", "dropped log records", logger::dropped()),
+    ];
+    for (name, value) in stats::counters() {
+        if name != "reconnects" {
+            lines.push(format!("{:<20} {}", name, value));
+        }
+    }
+    format!("```\n{}\n```", lines.join("\n"))
+}
+
+/// `logs <plugin> [level] [count]`: the newest records of a plugin from memory
+fn logs(args: &[&str]) -> String {
+    let plugin = match args.first() {
+        Some(plugin) => *plugin,
+        None => return format!("Usage: `logs <plugin> [level] [count]`\nPlugins with records: {}", logger::history_names().join(", ")),
+    };
+
+    let (level, count) = match level_and_count(&args[1..]) {
+        Ok(level_and_count) => level_and_count,
+        Err(e) => return e,
+    };
+
+    let entries = logger::recent(plugin, level, count);
+    if entries.is_empty() {
+        return format!("There are no records for '{}'", plugin);
+    }
+
+    let lines: Vec<String> = entries.iter()
+        .map(|entry| format!("{} {:5} {}", entry.time.format("%Y-%m-%d %H:%M:%S"), entry.level, entry.message))
+        .collect();
+    format!("```\n{}\n```", newest_that_fit(lines).join("\n"))
+}
+
+/// The `[level] [count]` of `logs`, in any order. All levels and 20 records if they are left out
+fn level_and_count(args: &[&str]) -> Result<(LevelFilter, usize), String> {
+    let mut level = LevelFilter::Trace;
+    let mut count = 20;
+    for arg in args {
+        if let Ok(n) = arg.parse::<usize>() {
+            count = n;
+        } else if let Ok(l) = LevelFilter::from_str(arg) {
+            level = l;
+        } else {
+            return Err(format!("'{}' is neither a level nor a count", arg));
+        }
+    }
+    Ok((level, count))
+}
+
+/// Drops the oldest lines until the reply fits in one message, the newest line is always kept
+fn newest_that_fit(mut lines: Vec<String>) -> Vec<String> {
+    let mut length: usize = lines.iter().map(|line| line.len() + 1).sum();
+    while length > MAX_REPLY && lines.len() > 1 {
+        length -= lines.remove(0).len() + 1;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_commands() {
+        assert_eq!(parse("help"), Some(Command::Help));
+        assert_eq!(parse("  plugins "), Some(Command::Plugins));
+        assert_eq!(parse("reload karma"), Some(Command::Reload("karma")));
+        assert_eq!(parse("disable karma"), Some(Command::Disable("karma")));
+        assert_eq!(parse("enable karma"), Some(Command::Enable("karma")));
+        assert_eq!(parse("loglevel info,plugin:karma=debug"), Some(Command::LogLevel("info,plugin:karma=debug")));
+        assert_eq!(parse("logs"), Some(Command::Logs(vec![])));
+        assert_eq!(parse("logs karma warn 5"), Some(Command::Logs(vec!["karma", "warn", "5"])));
+        assert_eq!(parse("channels refresh"), Some(Command::RefreshChannels));
+        assert_eq!(parse("status"), Some(Command::Status));
+    }
+
+    #[test]
+    fn other_messages_are_not_commands() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("hello"), None);
+        assert_eq!(parse("help me"), None);
+        assert_eq!(parse("reload"), None);
+        assert_eq!(parse("loglevel"), None);
+        assert_eq!(parse("channels"), None);
+        assert_eq!(parse("Status"), None);
+    }
+
+    #[test]
+    fn logs_arguments() {
+        assert_eq!(level_and_count(&[]), Ok((LevelFilter::Trace, 20)));
+        assert_eq!(level_and_count(&["warn"]), Ok((LevelFilter::Warn, 20)));
+        assert_eq!(level_and_count(&["5"]), Ok((LevelFilter::Trace, 5)));
+        assert_eq!(level_and_count(&["5", "ERROR"]), Ok((LevelFilter::Error, 5)));
+        assert_eq!(level_and_count(&["loud"]), Err(String::from("'loud' is neither a level nor a count")));
+        assert!(level_and_count(&["-1"]).is_err());
+    }
+
+    #[test]
+    fn keeps_the_newest_lines_that_fit() {
+        let lines: Vec<String> = (0..100).map(|i| format!("{:099}", i)).collect();
+        let kept = newest_that_fit(lines.clone());
+        assert_eq!(kept.len(), MAX_REPLY / 100);
+        assert_eq!(kept[..], lines[100 - kept.len()..]);
+
+        let short = vec![String::from("a"), String::from("b")];
+        assert_eq!(newest_that_fit(short.clone()), short);
+
+        // A line too long on its own is still sent
+        let long = vec![String::from("old"), "x".repeat(MAX_REPLY * 2)];
+        assert_eq!(newest_that_fit(long.clone()), long[1..].to_vec());
+    }
+}
@@ -0,0 +1,375 @@
+use config;
+use log_filter::Filter;
+use logger;
+use misc;
+
+use plugin_manager::PluginManager;
+
+use template::channel_return::unbounded;
+use template::plugin_api_v2;
+
+use extension;
+
+use std::path::PathBuf;
+
+pub static USAGE: &'static str = "\
+Usage: best-bot [OPTIONS] [COMMAND]
+
+Commands:
+    run             Connect to Slack and run the bot (default)
+    check-config    Read the config file and report if it is usable
+    dump-config     Print the effective config, with the tokens redacted
+    list-plugins    Load every plugin and print its name, api version and subscriptions
+    init            Write a template config file
+    replay <file>   Send the events recorded with --record to the plugins again, without Slack
+    new-plugin <name>
+                    Create a plugin crate in the folder <name>, ready to build and test
+
+Options:
+    -c, --config <path>     Use <path> as the config file instead of ~/.config/BEST-Bot/default.toml
+    -l, --log-level <level> Override the log level (off, error, warn, info, debug, trace) or give
+                            filter directives, e.g. info,slack_bot=debug,plugin:karma=trace
+    -f, --force             Let `init` overwrite an existing config file, and `new-plugin` an existing folder
+        --api <version>     The plugin api `new-plugin` uses, v1 or v2 (default v2)
+        --console           Run without Slack: lines typed in the terminal are sent to the plugins
+                            as messages, and what they post is printed
+        --fixture <path>    The JSON file with the fake user, channel and channel names for --console
+                            and replay
+        --record <path>     Append every websocket frame from Slack to <path>, one JSON object per line
+        --speed <factor>    How much faster than recorded `replay` runs, 0 is as fast as possible (default 1)
+    -h, --help              Print this help
+
+Every config value can be overridden from the environment, e.g. BEST_BOT_SLACK_API_TOKEN,
+and every token can be read from a file, e.g. BEST_BOT_SLACK_API_TOKEN_FILE.";
+
+/// The sub commands BEST-Bot understands
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    CheckConfig,
+    DumpConfig,
+    ListPlugins,
+    Init,
+    Replay(PathBuf),
+    NewPlugin(String),
+    Help,
+}
+
+/// The parsed command line
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub log_level: Option<String>,
+    pub force: bool,
+    pub console: bool,
+    pub fixture: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub speed: f64,
+    pub api: Option<u32>,
+}
+
+/// Parses the command line arguments (without the program name)
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut command = None;
+    let mut speed = None;
+    let mut parsed = Args {
+        command: Command::Run,
+        config: None,
+        log_level: None,
+        force: false,
+        console: false,
+        fixture: None,
+        record: None,
+        speed: 1.0,
+        api: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => {
+                let path = args.next().ok_or(format!("'{}' needs a path", arg))?;
+                parsed.config = Some(PathBuf::from(path));
+            },
+            "-l" | "--log-level" => {
+                let level = args.next().ok_or(format!("'{}' needs a level", arg))?;
+                Filter::parse(&level)?;
+                parsed.log_level = Some(level);
+            },
+            "-f" | "--force" => parsed.force = true,
+            "--console" => parsed.console = true,
+            "--fixture" => {
+                let path = args.next().ok_or(format!("'{}' needs a path", arg))?;
+                parsed.fixture = Some(PathBuf::from(path));
+            },
+            "--record" => {
+                let path = args.next().ok_or(format!("'{}' needs a path", arg))?;
+                parsed.record = Some(PathBuf::from(path));
+            },
+            "--speed" => {
+                let factor = args.next().ok_or(format!("'{}' needs a factor", arg))?;
+                speed = match factor.parse::<f64>() {
+                    Ok(factor) if factor >= 0.0 => Some(factor),
+                    _ => return Err(format!("'{}' is not a speed, use e.g. 1, 10 or 0", factor)),
+                };
+            },
+            "--api" => {
+                let api = args.next().ok_or(format!("'{}' needs a version", arg))?;
+                parsed.api = match api.trim_start_matches('v').parse::<u32>() {
+                    Ok(version) if version == 1 || version == 2 => Some(version),
+                    _ => return Err(format!("'{}' is not a plugin api, use v1 or v2", api)),
+                };
+            },
+            "-h" | "--help" => command = Some(Command::Help),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            _ if command.is_some() => return Err(format!("Unexpected argument '{}'", arg)),
+            "help" => command = Some(Command::Help),
+            "run" => command = Some(Command::Run),
+            "check-config" => command = Some(Command::CheckConfig),
+            "dump-config" => command = Some(Command::DumpConfig),
+            "list-plugins" => command = Some(Command::ListPlugins),
+            "init" => command = Some(Command::Init),
+            "replay" => {
+                let path = args.next().ok_or(String::from("'replay' needs the file to replay"))?;
+                command = Some(Command::Replay(PathBuf::from(path)));
+            },
+            "new-plugin" => {
+                let name = args.next().ok_or(String::from("'new-plugin' needs the name of the plugin"))?;
+                command = Some(Command::NewPlugin(name));
+            },
+            _ => return Err(format!("Unknown command '{}'", arg)),
+        }
+    }
+
+    if let Some(command) = command {
+        parsed.command = command;
+    }
+    if parsed.console && parsed.command != Command::Run {
+        return Err(String::from("--console only works with the run command"));
+    }
+    if parsed.record.is_some() && (parsed.command != Command::Run || parsed.console) {
+        return Err(String::from("--record only works with the run command, without --console"));
+    }
+    if parsed.api.is_some() {
+        match parsed.command {
+            Command::NewPlugin(_) => (),
+            _ => return Err(String::from("--api only works with the new-plugin command")),
+        }
+    }
+    let replay = match parsed.command {
+        Command::Replay(_) => true,
+        _ => false,
+    };
+    if parsed.fixture.is_some() && !parsed.console && !replay {
+        return Err(String::from("--fixture only works with --console and the replay command"));
+    }
+    if let Some(speed) = speed {
+        if !replay {
+            return Err(String::from("--speed only works with the replay command"));
+        }
+        parsed.speed = speed;
+    }
+    Ok(parsed)
+}
+
+/// Makes the global options take effect. Has to be called before `CONFIG` is used
+pub fn apply(args: &Args) {
+    if let Some(ref path) = args.config {
+        config::set_config_file(path.clone());
+    }
+    if let Some(ref level) = args.log_level {
+        config::set_log_level(level.clone());
+    }
+    match args.command {
+        Command::Run if args.console => config::set_console(),
+        Command::Replay(_) => config::set_console(),
+        _ => (),
+    }
+}
+
+/// Reads the config file and reports every problem in it
+pub fn check_config() -> i32 {
+    match config::check() {
+        Ok(problems) => {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if problems.iter().any(|p| p.severity == config::Severity::Error) {
+                1
+            } else {
+                println!("The config file '{}' is OK", config::config_file().display());
+                0
+            }
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+/// Prints the config after the environment has been applied, without the secrets.
+/// It is printed before it is validated, so a broken config can be looked at too
+pub fn dump_config() -> i32 {
+    let config = match config::read_unchecked() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        },
+    };
+    println!("# {}", config::config_file().display());
+    print!("{}", config.redacted());
+
+    // The problems go to stderr, so what is printed stays a usable config file
+    match config::check() {
+        Ok(problems) => {
+            for problem in &problems {
+                eprintln!("{}", problem);
+            }
+            if problems.iter().any(|p| p.severity == config::Severity::Error) {
+                1
+            } else {
+                0
+            }
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+/// Loads all the plugins, without connecting to Slack, and prints what they are
+pub fn list_plugins() -> i32 {
+    let logger_sender = logger::init().expect("BEST-Bot failed at starting the logging module");
+    let (plugin_sender, _plugin_receiver) = unbounded::<plugin_api_v2::Channel>();
+    // Nothing answers the requests here, they fail instead of waiting
+    let (extension_host, _) = extension::channel();
+
+    let mut plugin_manager = PluginManager::new(logger_sender, plugin_sender, extension_host);
+    for path in misc::find_plugins() {
+        plugin_manager.load_plugin(path);
+    }
+
+    println!("{:<30} {:<4} {}", "NAME", "API", "SUBSCRIPTIONS");
+    for plugin in plugin_manager.describe() {
+        println!("{:<30} {:<4} {}", plugin.name, plugin.api, plugin.subscriptions.join(", "));
+    }
+    logger::shutdown();
+    0
+}
+
+/// Writes the template config file
+pub fn init(force: bool) -> i32 {
+    match config::write_template(force) {
+        Ok(path) => {
+            println!("Wrote a template config file to '{}', edit it before running BEST-Bot", path.display());
+            0
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Args, String> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parses_every_command() {
+        let commands = [
+            ("", Command::Run),
+            ("run", Command::Run),
+            ("check-config", Command::CheckConfig),
+            ("dump-config", Command::DumpConfig),
+            ("list-plugins", Command::ListPlugins),
+            ("init", Command::Init),
+            ("replay events.jsonl", Command::Replay(PathBuf::from("events.jsonl"))),
+            ("new-plugin karma", Command::NewPlugin(String::from("karma"))),
+            ("help", Command::Help),
+            ("--help", Command::Help),
+            ("-h", Command::Help),
+        ];
+        for &(line, ref command) in commands.iter() {
+            assert_eq!(parse_line(line).map(|args| args.command).as_ref(), Ok(command), "'{}'", line);
+        }
+    }
+
+    #[test]
+    fn parses_the_options() {
+        let args = parse_line("-c bot.toml -l info,plugin:karma=debug --force init").unwrap();
+        assert_eq!(args.command, Command::Init);
+        assert_eq!(args.config, Some(PathBuf::from("bot.toml")));
+        assert_eq!(args.log_level, Some(String::from("info,plugin:karma=debug")));
+        assert!(args.force);
+
+        let args = parse_line("run --console --fixture fixture.json").unwrap();
+        assert!(args.console);
+        assert_eq!(args.fixture, Some(PathBuf::from("fixture.json")));
+
+        let args = parse_line("run --record events.jsonl").unwrap();
+        assert_eq!(args.record, Some(PathBuf::from("events.jsonl")));
+
+        let args = parse_line("replay events.jsonl --speed 0 --fixture fixture.json").unwrap();
+        assert_eq!(args.speed, 0.0);
+        assert_eq!(parse_line("replay events.jsonl").unwrap().speed, 1.0);
+
+        assert_eq!(parse_line("new-plugin karma --api v1").unwrap().api, Some(1));
+        assert_eq!(parse_line("new-plugin karma --api 2").unwrap().api, Some(2));
+        assert_eq!(parse_line("new-plugin karma").unwrap().api, None);
+    }
+
+    #[test]
+    fn reports_missing_arguments() {
+        let errors = [
+            ("--config", "'--config' needs a path"),
+            ("-c", "'-c' needs a path"),
+            ("--log-level", "'--log-level' needs a level"),
+            ("--fixture", "'--fixture' needs a path"),
+            ("--record", "'--record' needs a path"),
+            ("replay events.jsonl --speed", "'--speed' needs a factor"),
+            ("new-plugin karma --api", "'--api' needs a version"),
+            ("replay", "'replay' needs the file to replay"),
+            ("new-plugin", "'new-plugin' needs the name of the plugin"),
+        ];
+        for &(line, error) in errors.iter() {
+            assert_eq!(parse_line(line).err().as_ref().map(|e| e.as_str()), Some(error), "'{}'", line);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        assert!(parse_line("--log-level slack_bot=loud").is_err());
+        assert_eq!(parse_line("replay events.jsonl --speed -1").err(), Some(String::from("'-1' is not a speed, use e.g. 1, 10 or 0")));
+        assert_eq!(parse_line("new-plugin karma --api v3").err(), Some(String::from("'v3' is not a plugin api, use v1 or v2")));
+        assert_eq!(parse_line("--verbose").err(), Some(String::from("Unknown option '--verbose'")));
+        assert_eq!(parse_line("start").err(), Some(String::from("Unknown command 'start'")));
+        assert_eq!(parse_line("run init").err(), Some(String::from("Unexpected argument 'init'")));
+        assert_eq!(parse_line("run help").err(), Some(String::from("Unexpected argument 'help'")));
+        assert_eq!(parse_line("replay help").unwrap().command, Command::Replay(PathBuf::from("help")));
+        assert_eq!(parse_line("run --help").unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn reports_incompatible_options() {
+        let errors = [
+            ("init --console", "--console only works with the run command"),
+            ("replay events.jsonl --console", "--console only works with the run command"),
+            ("init --record events.jsonl", "--record only works with the run command, without --console"),
+            ("run --console --record events.jsonl", "--record only works with the run command, without --console"),
+            ("run --api v1", "--api only works with the new-plugin command"),
+            ("run --fixture fixture.json", "--fixture only works with --console and the replay command"),
+            ("run --speed 2", "--speed only works with the replay command"),
+        ];
+        for &(line, error) in errors.iter() {
+            assert_eq!(parse_line(line).err().as_ref().map(|e| e.as_str()), Some(error), "'{}'", line);
+        }
+    }
+}
@@ -1,91 +1,522 @@
 extern crate easy_toml_config;
 use self::easy_toml_config::*;
 
-use std::path::{PathBuf};
-use std::fs::File;
-use std::env::home_dir;
+extern crate signal_hook;
 
-use log::LevelFilter;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fs::{create_dir_all, File};
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use log::{Level, LevelFilter};
+
+use log_filter::Filter;
+use log_format::{Format, DEFAULT_LINE_FORMAT};
+use misc::die;
+use paths;
+use queue::Overflow;
+use slack_sink;
+
+mod validate;
+pub use self::validate::{validate, Problem, Severity};
 
 static CONFIG_FILE: &'static str = "default.toml";
 
+/// Prefix of the environment variables overriding the config file, e.g. `BEST_BOT_SLACK_API_TOKEN`
+static ENV_PREFIX: &'static str = "BEST_BOT_";
+
 
 lazy_static! {
-    pub static ref CONFIG: Config = {
-        set_config_dir(String::from(".config/BEST-Bot"));
-        read_config(CONFIG_FILE, config_template())
+    pub static ref CONFIG: ConfigHandle = {
+        ConfigHandle {
+            current: RwLock::new(Arc::new(read_config(config_template()))),
+        }
     };
+
+    static ref OVERRIDES: RwLock<Overrides> = RwLock::new(Overrides::default());
+}
+
+/// Holds the config in use, it is swapped when the config file is reloaded
+pub struct ConfigHandle {
+    current: RwLock<Arc<Config>>,
+}
+
+impl ConfigHandle {
+    /// The config in use right now. Keep the returned value only as long as it is needed,
+    /// otherwise a reload will not be seen
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reads the config file again and swaps it in if it is valid.
+    /// Returns the old and the new config
+    pub fn reload(&self) -> Result<(Arc<Config>, Arc<Config>), String> {
+        let path = config_file();
+        let (config, data) = parse_config(&path).map_err(|e| format!("{}, the old config is kept", e))?;
+
+        let problems = validate(&config, &data, &path);
+        for problem in &problems {
+            warn!("{}", problem);
+        }
+        if problems.iter().any(|p| p.severity == Severity::Error) {
+            return Err(format!("The config file '{}' has errors, the old config is kept", path.display()));
+        }
+
+        let new = Arc::new(config);
+        let old = ::std::mem::replace(&mut *self.current.write().unwrap(), new.clone());
+        Ok((old, new))
+    }
+}
+
+/// Reloads the config on SIGHUP or when the config file is changed, and calls `on_change`
+/// with the old and the new config every time the config has changed
+pub fn watch<F>(on_change: F) where F: Fn(&Config, &Config) + Send + 'static {
+    let hangup = Arc::new(AtomicBool::new(false));
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, hangup.clone()) {
+        error!("Cannot listen for SIGHUP, the config is only reloaded when the file changes ({})", e);
+    }
+
+    let path = config_file();
+    let modified = |path: &Path| ::std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    thread::spawn(move || {
+        let mut last_modified = modified(&path);
+        loop {
+            thread::sleep(Duration::from_secs(1));
+
+            let changed = modified(&path);
+            let signaled = hangup.swap(false, Ordering::SeqCst);
+            if !signaled && changed == last_modified {
+                continue;
+            }
+            last_modified = changed;
+
+            info!("Reloading the config file '{}'", path.display());
+            match CONFIG.reload() {
+                Ok((old, new)) => {
+                    if old != new {
+                        on_change(&old, &new);
+                    } else {
+                        debug!("The config has not changed");
+                    }
+                },
+                Err(e) => error!("{}", e),
+            }
+        }
+    });
+}
+
+/// The names of the changed settings that only take effect after a restart
+pub fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if old.plugin_path != new.plugin_path {
+        fields.push("plugin_path");
+    }
+    if old.plugin_config_path != new.plugin_config_path {
+        fields.push("plugin_config_path");
+    }
+    if old.http_listen() != new.http_listen() {
+        fields.push("http.listen");
+    }
+    // Only the plugins loaded from now on are checked with the new value
+    if old.allow_unchecked_plugins() != new.allow_unchecked_plugins() {
+        fields.push("allow_unchecked_plugins");
+    }
+    // The plugins using api v1 are given the tokens when they are loaded
+    if old.slack.api_token != new.slack.api_token {
+        fields.push("slack.api_token");
+    }
+    if old.slack.admin_api_token != new.slack.admin_api_token {
+        fields.push("slack.admin_api_token");
+    }
+    fields
+}
+
+/// Values given on the command line, they take precedence over the config file.
+/// They have to be set before `CONFIG` is used the first time.
+#[derive(Default)]
+struct Overrides {
+    config_file: Option<PathBuf>,
+    log_level: Option<String>,
+    console: bool,
+}
+
+/// Use `path` as the config file instead of `default.toml` in `paths::default_config_dir`
+pub fn set_config_file(path: PathBuf) {
+    OVERRIDES.write().unwrap().config_file = Some(path);
 }
 
-/// Reads the config file
-fn read_config(config_file: &str, config: Config) -> Config {
+/// Override the log level from the config file
+pub fn set_log_level(level: String) {
+    OVERRIDES.write().unwrap().log_level = Some(level);
+}
+
+/// Run without Slack (`--console` and `replay`): the tokens are stubbed, and a missing config file is not an error
+pub fn set_console() {
+    OVERRIDES.write().unwrap().console = true;
+}
+
+/// The path of the config file in use
+pub fn config_file() -> PathBuf {
+    match OVERRIDES.read().unwrap().config_file {
+        Some(ref path) => path.clone(),
+        None => paths::default_config_dir().join(CONFIG_FILE),
+    }
+}
+
+/// What the paths in the config file are resolved against
+fn base() -> paths::Base {
+    paths::Base::new(&config_file())
+}
+
+/// Writes the template config file. An existing file is only replaced if `force` is true
+pub fn write_template(force: bool) -> Result<PathBuf, String> {
+    let path = config_file();
+
+    if path.exists() && !force {
+        return Err(format!("The config file '{}' already exist, use --force to overwrite it", path.display()));
+    }
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(|e| format!("Cannot create the folder '{}' ({})", parent.display(), e))?;
+    }
+
+    config_template().write_to(&path);
+    Ok(path)
+}
+
+/// Reads the config file, if it does not exist a template is written and the program exits
+fn read_config(config: Config) -> Config {
     use std::io::Read;
-    let mut config_file = init(config_file, &config).unwrap();
+
+    let path = config_file();
+    if !path.exists() && OVERRIDES.read().unwrap().console {
+        let mut config = config;
+        if let Err(e) = config.apply_overrides() {
+            eprintln!("{}", e);
+            die(1);
+        }
+        return config;
+    }
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).expect(&format!("Cannot create the folder '{}'", parent.display()));
+        }
+        config.write_to(&path);
+        let data = toml::to_string(&config).unwrap();
+        for problem in validate(&config, &data, &path) {
+            eprintln!("{}", problem);
+        }
+        eprintln!("Edit the config file '{}'", path.display());
+        die(1);
+    }
+    let mut config_file = File::open(&path).expect(&format!("Failed to open the config file '{}'", path.display()));
 
     let mut data = String::new();
-    config_file.read_to_string(&mut data);
-    error_handler(toml::from_str(&data))
+    if let Err(e) = config_file.read_to_string(&mut data) {
+        eprintln!("{}: cannot read the config file ({})", path.display(), e);
+        die(1);
+    }
+    let mut config: Config = error_handler(toml::from_str(&data));
+    if let Err(e) = config.apply_overrides() {
+        eprintln!("{}: {}", path.display(), e);
+        die(1);
+    }
+
+    // The logger is not running yet, so the problems are written directly to stderr
+    let problems = validate(&config, &data, &path);
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if problems.iter().any(|p| p.severity == Severity::Error) {
+        die(1);
+    }
+
+    config
+}
+
+/// Reads and validates the config file without exiting, used by `check-config`
+pub fn check() -> Result<Vec<Problem>, String> {
+    let path = config_file();
+    let (config, data) = parse_config(&path)?;
+    Ok(validate(&config, &data, &path))
+}
+
+/// Reads the config file without validating it, used by `dump-config` to show a broken config
+pub fn read_unchecked() -> Result<Config, String> {
+    parse_config(&config_file()).map(|(config, _)| config)
+}
+
+/// Reads the config file and applies the overrides, returns the config and the content of the file
+fn parse_config(path: &Path) -> Result<(Config, String), String> {
+    use std::io::Read;
+
+    let mut data = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut data))
+        .map_err(|e| format!("{}: cannot read the config file ({})", path.display(), e))?;
+
+    let mut config: Config = toml::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    config.apply_overrides().map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok((config, data))
 }
 
 /// Main struct for config
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Config {
     plugin_path: Option<String>,
     plugin_config_path: Option<String>,
+    /// The Slack user ids allowed to use the admin commands in a direct message to the bot
+    admins: Option<Vec<String>>,
+    /// Loads plugins using api v1 and v2 that have no ABI fingerprint, built before BEST-Bot checked it.
+    /// They are refused by default, since a plugin built with another toolchain or `template` can crash BEST-Bot
+    allow_unchecked_plugins: Option<bool>,
     pub slack: Slack,
     log: Option<Log>,
+    http: Option<Http>,
+    workers: Option<Workers>,
 }
 
-/// Struct for handling Slack keys
-#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Struct for handling Slack keys.
+///
+/// Every token can also be read from a file with the `*_file` key or be given in the environment
+/// (`BEST_BOT_SLACK_API_TOKEN` and `BEST_BOT_SLACK_API_TOKEN_FILE` for `api_token`). The first one set wins:
+///
+/// 1. the environment variable
+/// 2. the environment variable ending in `_FILE`
+/// 3. the `*_file` key in the config file
+/// 4. the key in the config file
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Slack {
     /// The token are meant to be from a Slack Bot, but can also be from a normal user.
     /// Look in to Legacy Tokens on api.slack.com
+    #[serde(default)]
     pub api_token: String,
+    pub api_token_file: Option<String>,
 
     /// The token have to be from a normal user with admin privileges.
     /// Look in to Legacy Tokens on api.slack.com to figure out have to generate the token.
+    #[serde(default)]
     pub admin_api_token: String,
+    pub admin_api_token_file: Option<String>,
 
     /// The token is from the app Incoming WebHooks.
     pub incoming_webhooks_token: Option<String>,
+    pub incoming_webhooks_token_file: Option<String>,
 
     /// The token is from the app Outcoming WebHooks.
     pub outgoing_webhooks_token: Option<String>,
+    pub outgoing_webhooks_token_file: Option<String>,
+
+    /// Where the Slack Web API is, e.g. "http://127.0.0.1:8080/api" for a fake Slack in the tests.
+    /// Slack itself is used if it is not set
+    pub api_url: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+/// The HTTP server for `/metrics`, `/healthz` and `/readyz`, it is only started if `listen` is set
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Http {
+    /// The address to listen on, e.g. "127.0.0.1:9184"
+    listen: Option<String>,
+}
+
+/// Every plugin handles its events on its own thread, from a queue of the events waiting for it
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Workers {
+    /// The events that can wait for a plugin, the overflow policy applies above this
+    pub queue_size: Option<usize>,
+    /// What happens to an event for a plugin with a full queue: drop_oldest, block or sample:<n>.
+    /// With block no event is delivered to any plugin while one waits, until the slow plugin has
+    /// room again or goes over its timeout
+    pub overflow: Option<String>,
+    /// A warning is logged when a plugin takes longer than this on a single event. The events waiting
+    /// for the plugin are then dropped, and so are new ones until it is done with the slow event
+    pub timeout_seconds: Option<u64>,
+    /// `timeout_seconds` for single plugins, by plugin name
+    pub timeouts: Option<BTreeMap<String, u64>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Log {
     level: Option<String>,
     to_file: Option<bool>,
     to_terminal: Option<bool>,
     log_path: Option<String>,
+
+    /// "text" for human readable lines or "json" for one JSON object per line
+    format: Option<String>,
+    /// The layout of a text line, see `log_format::text` for the placeholders
+    line_format: Option<String>,
+    /// Colour the level on the terminal: "auto" (only if it is a terminal), "always" or "never"
+    color: Option<String>,
+
+    /// Send the records to journald with the native protocol
+    to_journald: Option<bool>,
+    /// Send the records as RFC 5424 syslog messages to "unix:/dev/log" or "udp:host:port"
+    syslog: Option<String>,
+    /// The syslog facility, e.g. "daemon" or "local0"
+    syslog_facility: Option<String>,
+    /// The SD-ID the plugin and target are sent under, "name@<private enterprise number>".
+    /// Without it the syslog messages have no structured data
+    syslog_sd_id: Option<String>,
+
+    /// Post the log records to this Slack channel (id or name)
+    slack_channel: Option<String>,
+    /// "error" to post only errors or "warn" to post warnings too
+    slack_level: Option<String>,
+    /// At most this many posts to Slack per minute
+    slack_rate_limit: Option<u32>,
+    /// The same record is only posted once within this many seconds
+    slack_dedup_seconds: Option<u64>,
+    /// If set, the records are collected and posted as a single digest this often (in seconds)
+    slack_digest_seconds: Option<u64>,
+
+    /// How many records are kept in memory per plugin, for the `logs` admin command
+    history_size: Option<usize>,
+
+    /// How many records can wait for the writer thread
+    queue_size: Option<usize>,
+    /// What happens to a record when the queue is full: "drop_oldest", "block" or "sample:<n>"
+    /// (only every n-th record is kept)
+    overflow: Option<String>,
+
+    /// Rotate the log file when it grows beyond this size, e.g. "10M". The suffixes K, M and G are understood
+    max_size: Option<String>,
+    /// Rotate the log file when the day changes
+    rotate_daily: Option<bool>,
+    /// How many rotated log files are kept
+    keep: Option<usize>,
+    /// Gzip the rotated log files
+    compress: Option<bool>,
 }
 
 impl Config {
     /// Get the path for the plugins
     pub fn plugin_path(&self) -> PathBuf {
-        let mut plugin_path = home_dir().unwrap();
-        if self.plugin_path.is_none() {
-            plugin_path.push(get_config_dir().unwrap());
-            plugin_path.push("libs");
-            return plugin_path;
-        } else {
-            plugin_path.push(self.plugin_path.clone().unwrap());
-            return plugin_path;
-        }
+        paths::resolve_or(&self.plugin_path, "libs", &base())
     }
 
     /// Get the path for there to the plugins should store their config file
     pub fn plugin_config_path(&self) -> PathBuf {
-        let mut plugin_config_path = home_dir().unwrap();
-        if self.plugin_config_path.is_none() {
-            plugin_config_path.push(get_config_dir().unwrap());
-            plugin_config_path.push("plugins");
-            return plugin_config_path;
-        } else {
-            plugin_config_path.push(self.plugin_config_path.clone().unwrap());
-            return plugin_config_path;
+        paths::resolve_or(&self.plugin_config_path, "plugins", &base())
+    }
+
+    /// Checks if the Slack user may use the admin commands
+    pub fn is_admin(&self, user: &str) -> bool {
+        self.admins.as_ref().map_or(false, |admins| admins.iter().any(|admin| admin == user))
+    }
+
+    /// Whether plugins without an ABI fingerprint are loaded, see `abi`
+    pub fn allow_unchecked_plugins(&self) -> bool {
+        self.allow_unchecked_plugins.unwrap_or(false)
+    }
+
+    /// Overrides the values from the config file with the ones from the environment and the command line.
+    /// Fails if a secret file cannot be read
+    fn apply_overrides(&mut self) -> Result<(), String> {
+        self.apply_environment()?;
+
+        let overrides = OVERRIDES.read().unwrap();
+        if let Some(ref level) = overrides.log_level {
+            self.log = Some(Log {
+                level: Some(level.clone()),
+                ..self.log()
+            });
+        }
+        if overrides.console {
+            self.slack.api_token = String::from("xoxb-console");
+            self.slack.admin_api_token = String::from("xoxp-console");
+        }
+        Ok(())
+    }
+
+    /// Overrides the values from the config file with the ones from the environment
+    fn apply_environment(&mut self) -> Result<(), String> {
+        override_string(&mut self.plugin_path, "PLUGIN_PATH");
+        override_string(&mut self.plugin_config_path, "PLUGIN_CONFIG_PATH");
+        if let Some(admins) = env_var("ADMINS") {
+            self.admins = Some(admins.split(',').map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect());
+        }
+        override_bool(&mut self.allow_unchecked_plugins, "ALLOW_UNCHECKED_PLUGINS");
+
+        let slack = &mut self.slack;
+        slack.api_token = secret("SLACK_API_TOKEN", &slack.api_token_file, Some(slack.api_token.clone()))?.unwrap_or_default();
+        slack.admin_api_token = secret("SLACK_ADMIN_API_TOKEN", &slack.admin_api_token_file, Some(slack.admin_api_token.clone()))?.unwrap_or_default();
+        slack.incoming_webhooks_token = secret("SLACK_INCOMING_WEBHOOKS_TOKEN", &slack.incoming_webhooks_token_file, slack.incoming_webhooks_token.clone())?;
+        slack.outgoing_webhooks_token = secret("SLACK_OUTGOING_WEBHOOKS_TOKEN", &slack.outgoing_webhooks_token_file, slack.outgoing_webhooks_token.clone())?;
+        override_string(&mut slack.api_url, "SLACK_API_URL");
+
+        let mut log = self.log();
+        override_string(&mut log.level, "LOG_LEVEL");
+        override_bool(&mut log.to_file, "LOG_TO_FILE");
+        override_bool(&mut log.to_terminal, "LOG_TO_TERMINAL");
+        override_string(&mut log.log_path, "LOG_PATH");
+        override_string(&mut log.format, "LOG_FORMAT");
+        override_string(&mut log.line_format, "LOG_LINE_FORMAT");
+        override_string(&mut log.color, "LOG_COLOR");
+        override_bool(&mut log.to_journald, "LOG_TO_JOURNALD");
+        override_string(&mut log.syslog, "LOG_SYSLOG");
+        override_string(&mut log.syslog_facility, "LOG_SYSLOG_FACILITY");
+        override_string(&mut log.syslog_sd_id, "LOG_SYSLOG_SD_ID");
+        override_string(&mut log.slack_channel, "LOG_SLACK_CHANNEL");
+        override_string(&mut log.slack_level, "LOG_SLACK_LEVEL");
+        override_number(&mut log.slack_rate_limit, "LOG_SLACK_RATE_LIMIT");
+        override_number(&mut log.slack_dedup_seconds, "LOG_SLACK_DEDUP_SECONDS");
+        override_number(&mut log.slack_digest_seconds, "LOG_SLACK_DIGEST_SECONDS");
+        override_number(&mut log.history_size, "LOG_HISTORY_SIZE");
+        override_number(&mut log.queue_size, "LOG_QUEUE_SIZE");
+        override_string(&mut log.overflow, "LOG_OVERFLOW");
+        override_string(&mut log.max_size, "LOG_MAX_SIZE");
+        override_bool(&mut log.rotate_daily, "LOG_ROTATE_DAILY");
+        override_number(&mut log.keep, "LOG_KEEP");
+        override_bool(&mut log.compress, "LOG_COMPRESS");
+        self.log = Some(log);
+
+        if let Some(listen) = env_var("HTTP_LISTEN") {
+            self.http = Some(Http { listen: Some(listen) });
+        }
+
+        let mut workers = self.workers();
+        override_number(&mut workers.queue_size, "WORKERS_QUEUE_SIZE");
+        override_string(&mut workers.overflow, "WORKERS_OVERFLOW");
+        override_number(&mut workers.timeout_seconds, "WORKERS_TIMEOUT_SECONDS");
+        self.workers = Some(workers);
+        Ok(())
+    }
+
+    /// The effective config as TOML, with every token replaced by a redacted version
+    pub fn redacted(&self) -> String {
+        let mut config = self.clone();
+        {
+            let slack = &mut config.slack;
+            slack.api_token = redact(&slack.api_token);
+            slack.admin_api_token = redact(&slack.admin_api_token);
+            slack.incoming_webhooks_token = slack.incoming_webhooks_token.as_ref().map(|t| redact(t));
+            slack.outgoing_webhooks_token = slack.outgoing_webhooks_token.as_ref().map(|t| redact(t));
+        }
+        config.log = Some(config.log());
+
+        toml::to_string(&config).unwrap()
+    }
+
+    /// The base of the Slack Web API if it is not Slack itself, without a trailing `/`
+    pub fn slack_api_url(&self) -> Option<String> {
+        self.slack.api_url.as_ref().map(|url| url.trim_end_matches('/').to_string())
+    }
+
+    /// The address of the HTTP server, if it is turned on
+    pub fn http_listen(&self) -> Option<String> {
+        self.http.as_ref().and_then(|http| http.listen.clone())
+    }
+
+    pub fn workers(&self) -> Workers {
+        match self.workers {
+            Some(ref workers) => workers.clone(),
+            None => config_template().workers.expect("the variable 'workers' was not declared in struct 'Config' in the function 'config_template', fix it"),
         }
     }
 
@@ -99,20 +530,37 @@ impl Config {
     }
 }
 
+impl Workers {
+    pub fn queue_size(&self) -> usize {
+        self.queue_size.unwrap_or(100)
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow.as_ref()
+            .and_then(|o| Overflow::parse(o).ok())
+            .unwrap_or(Overflow::DropOldest)
+    }
+
+    /// How long `plugin` may take on an event before a warning is logged
+    pub fn timeout(&self, plugin: &str) -> Duration {
+        let seconds = self.timeouts.as_ref()
+            .and_then(|timeouts| timeouts.get(plugin).cloned())
+            .or(self.timeout_seconds)
+            .unwrap_or(10);
+        Duration::from_secs(seconds)
+    }
+}
+
 impl Log {
+    /// The log filter. `level` is either a single level or filter directives, see `Filter`
+    pub fn filter(&self) -> Filter {
+        let spec = self.level.as_ref().map_or("info", |level| level.as_str());
+        Filter::parse(spec).unwrap_or_else(|_| Filter::parse("info").unwrap())
+    }
+
+    /// The most verbose level any record can be logged at
     pub fn level(&self) -> LevelFilter {
-        match self.level.as_ref() {
-            Some(level) => match level.to_uppercase().as_ref() {
-                "OFF" => LevelFilter::Off,
-                "ERROR" => LevelFilter::Error,
-                "WARN" => LevelFilter::Warn,
-                "INFO" => LevelFilter::Info,
-                "DEBUG" => LevelFilter::Debug,
-                "TRACE" => LevelFilter::Trace,
-                _ => LevelFilter::Info,
-            }
-            None => LevelFilter::Info,
-        }
+        self.filter().max_level()
     }
 
     pub fn to_file(&self) -> bool {
@@ -123,50 +571,402 @@ impl Log {
         self.to_terminal.unwrap_or(true)
     }
 
-    pub fn path(&self) -> PathBuf {
-        let mut log_path = home_dir().unwrap();
-        if self.log_path.is_none() {
-            log_path.push(get_config_dir().unwrap());
-            log_path.push("log");
-            return log_path;
-        } else {
-            log_path.push(self.log_path.clone().unwrap());
-            return log_path;
+    pub fn line_format(&self) -> String {
+        self.line_format.clone().unwrap_or_else(|| String::from(DEFAULT_LINE_FORMAT))
+    }
+
+    /// If the output should be coloured, `is_terminal` tells if the output is a terminal
+    pub fn color(&self, is_terminal: bool) -> bool {
+        match self.color.as_ref().map(|c| c.to_lowercase()) {
+            Some(ref c) if c == "always" => true,
+            Some(ref c) if c == "never" => false,
+            _ => is_terminal,
         }
     }
+
+    pub fn to_journald(&self) -> bool {
+        self.to_journald.unwrap_or(false)
+    }
+
+    pub fn syslog(&self) -> Option<String> {
+        self.syslog.clone()
+    }
+
+    pub fn syslog_facility(&self) -> String {
+        self.syslog_facility.clone().unwrap_or_else(|| String::from("daemon"))
+    }
+
+    pub fn syslog_sd_id(&self) -> Option<String> {
+        self.syslog_sd_id.clone()
+    }
+
+    /// The settings for posting log records to Slack, `None` if no channel is configured
+    pub fn slack_settings(&self) -> Option<slack_sink::Settings> {
+        let level = match self.slack_level.as_ref().map(|l| l.to_lowercase()) {
+            Some(ref l) if l == "warn" => Level::Warn,
+            _ => Level::Error,
+        };
+
+        self.slack_channel.clone().map(|channel| slack_sink::Settings {
+            channel: channel,
+            level: level,
+            rate_limit: self.slack_rate_limit.unwrap_or(10),
+            dedup: Duration::from_secs(self.slack_dedup_seconds.unwrap_or(300)),
+            digest: self.slack_digest_seconds.map(Duration::from_secs),
+        })
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.history_size.unwrap_or(200)
+    }
+
+    pub fn queue_size(&self) -> usize {
+        self.queue_size.unwrap_or(10000)
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow.as_ref()
+            .and_then(|o| Overflow::parse(o).ok())
+            .unwrap_or(Overflow::DropOldest)
+    }
+
+    pub fn format(&self) -> Format {
+        match self.format.as_ref().map(|f| f.to_lowercase()) {
+            Some(ref f) if f == "json" => Format::Json,
+            _ => Format::Text,
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        paths::resolve_or(&self.log_path, "log", &base())
+    }
+
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size.as_ref().and_then(|size| parse_size(size))
+    }
+
+    pub fn rotate_daily(&self) -> bool {
+        self.rotate_daily.unwrap_or(false)
+    }
+
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(5)
+    }
+
+    pub fn compress(&self) -> bool {
+        self.compress.unwrap_or(false)
+    }
 }
 
-/// The setting/config are saved
-impl WriteConfig for Config {
-    fn write(&self) {
+/// Parses a size like "512", "100K", "10M" or "1G" into bytes
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, unit) = match size.char_indices().find(|&(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => (&size[..i], size[i..].trim()),
+        None => (size, ""),
+    };
+    let unit = match unit.to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok().and_then(|n| n.checked_mul(unit))
+}
+
+impl Config {
+    /// Saves the config to `path`
+    fn write_to(&self, path: &Path) {
         use std::io::Write;
-        let path_config_file = path_config_file(CONFIG_FILE).unwrap();
 
-        let mut config_file = File::create(&path_config_file).expect(&format!("Failed at creating a template config file '{}'", &path_config_file.to_str().unwrap()));
+        let mut config_file = File::create(path).expect(&format!("Failed at creating a template config file '{}'", path.to_str().unwrap()));
 
         let toml = toml::to_string(self).unwrap();
         config_file.write_all(toml.as_bytes()).expect(&format!("Failed to create a config file"));
+    }
+}
 
+/// The setting/config are saved
+impl WriteConfig for Config {
+    fn write(&self) {
+        let path_config_file = config_file();
+        self.write_to(&path_config_file);
+
+        let data = toml::to_string(self).unwrap();
+        for problem in validate(self, &data, &path_config_file) {
+            eprintln!("{}", problem);
+        }
         error!("Edit the config file '{}'", &path_config_file.to_str().unwrap());
     }
 }
 
+/// Reads the environment variable `BEST_BOT_<name>`
+fn env_var(name: &str) -> Option<String> {
+    env::var(format!("{}{}", ENV_PREFIX, name)).ok()
+}
+
+fn override_string(value: &mut Option<String>, name: &str) {
+    if let Some(v) = env_var(name) {
+        *value = Some(v);
+    }
+}
+
+fn override_bool(value: &mut Option<bool>, name: &str) {
+    if let Some(v) = env_var(name) {
+        match v.to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => *value = Some(true),
+            "0" | "false" | "no" | "off" => *value = Some(false),
+            _ => error!("The environment variable '{}{}' must be true or false, not '{}'", ENV_PREFIX, name, v),
+        }
+    }
+}
+
+fn override_number<T: ::std::str::FromStr>(value: &mut Option<T>, name: &str) {
+    if let Some(v) = env_var(name) {
+        match v.parse() {
+            Ok(n) => *value = Some(n),
+            Err(_) => error!("The environment variable '{}{}' must be a number, not '{}'", ENV_PREFIX, name, v),
+        }
+    }
+}
+
+/// Finds the value of a secret in order of precedence, see `Slack`
+fn secret(name: &str, file: &Option<String>, value: Option<String>) -> Result<Option<String>, String> {
+    if let Some(v) = env_var(name) {
+        return Ok(Some(v));
+    }
+    if let Some(path) = env_var(&format!("{}_FILE", name)) {
+        return read_secret(&path).map(Some);
+    }
+    if let Some(ref path) = *file {
+        return read_secret(path).map(Some);
+    }
+    Ok(value)
+}
+
+/// Reads a secret from a file, a trailing newline is removed
+fn read_secret(path: &str) -> Result<String, String> {
+    use std::io::Read;
+
+    let mut data = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut data))
+        .map(|_| data.trim_end_matches(|c: char| c == '\n' || c == '\r').to_string())
+        .map_err(|e| format!("cannot read the secret file '{}' ({})", path, e))
+}
+
+/// Keeps the token type (e.g. `xoxb`) and hides the rest
+fn redact(token: &str) -> String {
+    match token.find('-') {
+        Some(i) => format!("{}-<redacted>", &token[..i]),
+        None if token.is_empty() => String::new(),
+        None => String::from("<redacted>"),
+    }
+}
+
 /// Create a example/default configuration
 fn config_template() -> Config {
     Config {
-        plugin_path: Some(String::from(format!("{}/libs", get_config_dir().unwrap()))),
-        plugin_config_path: Some(String::from(format!("{}/plugins", get_config_dir().unwrap()))),
+        plugin_path: Some(String::from("libs")),
+        plugin_config_path: Some(String::from("plugins")),
+        admins: Some(Vec::new()),
+        allow_unchecked_plugins: Some(false),
         slack: Slack {
             api_token: "zzzz-xxxxxxxxxxxx-yyyyyyyyyyyyyyyyyyyyyyyy".to_string(),
+            api_token_file: None,
             admin_api_token: "zzzz-xxxxxxxxxxx-yyyyyyyyyyy-aaaaaaaaaaaa-bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+            admin_api_token_file: None,
             incoming_webhooks_token: None,
+            incoming_webhooks_token_file: None,
             outgoing_webhooks_token: None,
+            outgoing_webhooks_token_file: None,
+            api_url: None,
         },
         log: Some(Log {
             level: Some(String::from("info")),
             to_file: Some(false),
             to_terminal: Some(true),
             log_path: None,
+            format: Some(String::from("text")),
+            line_format: Some(String::from(DEFAULT_LINE_FORMAT)),
+            color: Some(String::from("auto")),
+            to_journald: Some(false),
+            syslog: None,
+            syslog_facility: None,
+            syslog_sd_id: None,
+            slack_channel: None,
+            slack_level: None,
+            slack_rate_limit: None,
+            slack_dedup_seconds: None,
+            slack_digest_seconds: None,
+            history_size: Some(200),
+            queue_size: Some(10000),
+            overflow: Some(String::from("drop_oldest")),
+            max_size: Some(String::from("10M")),
+            rotate_daily: Some(false),
+            keep: Some(5),
+            compress: Some(false),
+        }),
+        http: None,
+        workers: Some(Workers {
+            queue_size: Some(100),
+            overflow: Some(String::from("drop_oldest")),
+            timeout_seconds: Some(10),
+            timeouts: None,
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Writes `content` to a file in the temp folder, `name` has to be unique among the tests
+    fn temp_file(name: &str, content: &str) -> String {
+        let path = env::temp_dir().join(format!("best-bot-config-{}-{}", name, ::std::process::id()));
+        File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn set_env(name: &str, value: &str) {
+        env::set_var(format!("{}{}", ENV_PREFIX, name), value);
+    }
+
+    fn remove_env(name: &str) {
+        env::remove_var(format!("{}{}", ENV_PREFIX, name));
+    }
+
+    #[test]
+    fn secret_precedence() {
+        let name = "TEST_SECRET_PRECEDENCE";
+        let key = Some(String::from("from-key"));
+        let key_file = Some(temp_file("key-file", "from-key-file\n"));
+        let env_file = temp_file("env-file", "from-env-file\r\n");
+
+        assert_eq!(secret(name, &None, None), Ok(None));
+        assert_eq!(secret(name, &None, key.clone()), Ok(Some(String::from("from-key"))));
+        assert_eq!(secret(name, &key_file, key.clone()), Ok(Some(String::from("from-key-file"))));
+
+        set_env(&format!("{}_FILE", name), &env_file);
+        assert_eq!(secret(name, &key_file, key.clone()), Ok(Some(String::from("from-env-file"))));
+
+        set_env(name, "from-env");
+        assert_eq!(secret(name, &key_file, key.clone()), Ok(Some(String::from("from-env"))));
+        assert_eq!(secret(name, &None, None), Ok(Some(String::from("from-env"))));
+
+        remove_env(name);
+        remove_env(&format!("{}_FILE", name));
+        let _ = ::std::fs::remove_file(key_file.unwrap());
+        let _ = ::std::fs::remove_file(env_file);
+    }
+
+    #[test]
+    fn read_secret_keeps_everything_but_the_trailing_newline() {
+        let path = temp_file("read-secret", " xoxb-1 2\n\n");
+        assert_eq!(read_secret(&path), Ok(String::from(" xoxb-1 2")));
+        let _ = ::std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_missing_secret_file_is_an_error() {
+        let missing = env::temp_dir().join(format!("best-bot-config-missing-{}", ::std::process::id()));
+        let missing = Some(missing.to_str().unwrap().to_string());
+        assert!(read_secret(missing.as_ref().unwrap()).is_err());
+        assert!(secret("TEST_SECRET_MISSING", &missing, Some(String::from("from-key"))).is_err());
+    }
+
+    #[test]
+    fn reports_the_settings_that_need_a_restart() {
+        let old = config_template();
+        assert!(restart_required(&old, &old).is_empty());
+
+        let mut new = old.clone();
+        new.allow_unchecked_plugins = Some(true);
+        new.slack.api_token = String::from("xoxb-rotated");
+        new.log = Some(Log { level: Some(String::from("debug")), ..old.log() });
+        assert_eq!(restart_required(&old, &new), vec!["allow_unchecked_plugins", "slack.api_token"]);
+    }
+
+    #[test]
+    fn redacts_tokens() {
+        assert_eq!(redact("xoxb-1234-abcd"), "xoxb-<redacted>");
+        assert_eq!(redact("abcd"), "<redacted>");
+        assert_eq!(redact(""), "");
+
+        let redacted = config_template().redacted();
+        assert!(!redacted.contains("xxxxxxxxxxxx"));
+        assert!(redacted.contains("zzzz-<redacted>"));
+    }
+
+    #[test]
+    fn overrides_bools() {
+        let name = "TEST_OVERRIDE_BOOL";
+        let overridden = |env: Option<&str>, value: Option<bool>| {
+            match env {
+                Some(env) => set_env(name, env),
+                None => remove_env(name),
+            }
+            let mut value = value;
+            override_bool(&mut value, name);
+            value
+        };
+
+        for yes in &["1", "true", "Yes", "ON"] {
+            assert_eq!(overridden(Some(yes), Some(false)), Some(true), "{}", yes);
+        }
+        for no in &["0", "false", "NO", "off"] {
+            assert_eq!(overridden(Some(no), Some(true)), Some(false), "{}", no);
+        }
+        // An invalid value is reported and the value from the config file is kept
+        assert_eq!(overridden(Some("maybe"), Some(true)), Some(true));
+        assert_eq!(overridden(Some("maybe"), None), None);
+        assert_eq!(overridden(None, Some(true)), Some(true));
+        assert_eq!(overridden(None, None), None);
+    }
+
+    #[test]
+    fn refuses_unchecked_plugins_by_default() {
+        let mut config = config_template();
+        assert!(!config.allow_unchecked_plugins());
+        config.allow_unchecked_plugins = None;
+        assert!(!config.allow_unchecked_plugins());
+        config.allow_unchecked_plugins = Some(true);
+        assert!(config.allow_unchecked_plugins());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("100K"), Some(100 * 1024));
+        assert_eq!(parse_size("10M"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size(" 1 gb "), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("18446744073709551615"), Some(u64::max_value()));
+
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("-1M"), None);
+        assert_eq!(parse_size("1.5M"), None);
+        assert_eq!(parse_size("10T"), None);
+        assert_eq!(parse_size("18446744073709551616"), None);
+        assert_eq!(parse_size("17179869184G"), None);
+    }
+
+    #[test]
+    fn reports_missing_and_empty_tokens() {
+        let data = "[slack]\nadmin_api_token = \" \"\n";
+        let config: Config = toml::from_str(data).unwrap();
+        let problems = validate(&config, data, Path::new("test.toml"));
+
+        let api_token = problems.iter().find(|p| p.key == "slack.api_token").unwrap();
+        assert_eq!(api_token.severity, Severity::Error);
+        assert_eq!(api_token.line, None);
+        assert!(api_token.message.contains("BEST_BOT_SLACK_API_TOKEN"));
+
+        let admin_api_token = problems.iter().find(|p| p.key == "slack.admin_api_token").unwrap();
+        assert_eq!(admin_api_token.severity, Severity::Error);
+        assert_eq!(admin_api_token.line, Some(2));
+    }
+}
@@ -0,0 +1,359 @@
+use super::Config;
+use super::toml;
+
+use log_filter::Filter;
+use log_sink::{check_sd_id, parse_facility};
+use queue::Overflow;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// The keys BEST-Bot understands, a key in the config file not listed here is reported as unknown.
+/// Unknown keys are only warnings, so an older BEST-Bot can still read the config of a newer one
+static KNOWN_KEYS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("", &["plugin_path", "plugin_config_path", "admins", "allow_unchecked_plugins", "slack", "log", "http", "workers"]),
+    ("slack", &[
+        "api_token", "api_token_file",
+        "admin_api_token", "admin_api_token_file",
+        "incoming_webhooks_token", "incoming_webhooks_token_file",
+        "outgoing_webhooks_token", "outgoing_webhooks_token_file",
+        "api_url",
+    ]),
+    ("log", &[
+        "level", "to_file", "to_terminal", "log_path", "format", "line_format", "color",
+        "to_journald", "syslog", "syslog_facility", "syslog_sd_id",
+        "slack_channel", "slack_level", "slack_rate_limit", "slack_dedup_seconds", "slack_digest_seconds",
+        "history_size", "queue_size", "overflow",
+        "max_size", "rotate_daily", "keep", "compress",
+    ]),
+    ("http", &["listen"]),
+    ("workers", &["queue_size", "overflow", "timeout_seconds", "timeouts"]),
+];
+
+/// The token values written by `config_template`
+static PLACEHOLDER: &'static str = "zzzz-xxxx";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single problem found in the config
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}: {}: {}", self.file.display(), line, severity, self.key, self.message),
+            None => write!(f, "{}: {}: {}: {}", self.file.display(), severity, self.key, self.message),
+        }
+    }
+}
+
+/// Collects the problems while remembering the file they are found in
+struct Report<'a> {
+    file: &'a Path,
+    data: &'a str,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Report<'a> {
+    fn add(&mut self, severity: Severity, key: &str, message: String) {
+        self.problems.push(Problem {
+            severity: severity,
+            file: self.file.to_path_buf(),
+            line: find_line(self.data, key),
+            key: key.to_string(),
+            message: message,
+        });
+    }
+
+    fn error(&mut self, key: &str, message: String) {
+        self.add(Severity::Error, key, message);
+    }
+
+    fn warning(&mut self, key: &str, message: String) {
+        self.add(Severity::Warning, key, message);
+    }
+}
+
+/// Checks the config read from `data` (the content of `file`) and returns every problem found
+pub fn validate(config: &Config, data: &str, file: &Path) -> Vec<Problem> {
+    let mut report = Report {
+        file: file,
+        data: data,
+        problems: Vec::new(),
+    };
+
+    unknown_keys(&mut report);
+
+    token(&mut report, "slack.api_token", &config.slack.api_token, &["xoxb-", "xoxp-"]);
+    token(&mut report, "slack.admin_api_token", &config.slack.admin_api_token, &["xoxp-"]);
+    if let Some(ref t) = config.slack.incoming_webhooks_token {
+        token(&mut report, "slack.incoming_webhooks_token", t, &[]);
+    }
+    if let Some(ref t) = config.slack.outgoing_webhooks_token {
+        token(&mut report, "slack.outgoing_webhooks_token", t, &[]);
+    }
+
+    if let Some(ref url) = config.slack.api_url {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            report.error("slack.api_url", format!("'{}' must start with http:// or https://", url));
+        }
+    }
+
+    let plugin_path = config.plugin_path();
+    if !plugin_path.is_dir() {
+        report.error("plugin_path", format!("the folder '{}' does not exist", plugin_path.display()));
+    }
+    let plugin_config_path = config.plugin_config_path();
+    if !plugin_config_path.is_dir() {
+        report.warning("plugin_config_path", format!("the folder '{}' does not exist", plugin_config_path.display()));
+    }
+
+    if let Some(ref log) = config.log {
+        if let Some(ref level) = log.level {
+            if let Err(e) = Filter::parse(level) {
+                report.error("log.level", e);
+            }
+        }
+        if let Some(ref format) = log.format {
+            match format.to_lowercase().as_str() {
+                "text" | "json" => (),
+                _ => report.error("log.format", format!("unknown format '{}', use text or json", format)),
+            }
+        }
+        if let Some(ref color) = log.color {
+            match color.to_lowercase().as_str() {
+                "auto" | "always" | "never" => (),
+                _ => report.error("log.color", format!("unknown value '{}', use auto, always or never", color)),
+            }
+        }
+        if let Some(ref syslog) = log.syslog {
+            if !syslog.starts_with("unix:") && !syslog.starts_with("udp:") {
+                report.error("log.syslog", format!("'{}' must start with unix: or udp:", syslog));
+            }
+        }
+        if let Some(ref facility) = log.syslog_facility {
+            if parse_facility(facility).is_none() {
+                report.error("log.syslog_facility", format!("unknown facility '{}'", facility));
+            }
+        }
+        if let Some(ref sd_id) = log.syslog_sd_id {
+            if let Err(e) = check_sd_id(sd_id) {
+                report.error("log.syslog_sd_id", e);
+            }
+        }
+        if let Some(ref level) = log.slack_level {
+            match level.to_lowercase().as_str() {
+                "error" | "warn" => (),
+                _ => report.error("log.slack_level", format!("unknown level '{}', use error or warn", level)),
+            }
+        }
+        if log.queue_size == Some(0) {
+            report.error("log.queue_size", String::from("must be above 0"));
+        }
+        if let Some(ref overflow) = log.overflow {
+            if let Err(e) = Overflow::parse(overflow) {
+                report.error("log.overflow", e);
+            }
+        }
+        if let Some(ref size) = log.max_size {
+            if super::parse_size(size).is_none() {
+                report.error("log.max_size", format!("'{}' is not a size, use e.g. 10M", size));
+            }
+        }
+    }
+
+    if let Some(listen) = config.http_listen() {
+        if listen.parse::<SocketAddr>().is_err() {
+            report.error("http.listen", format!("'{}' is not an address, use e.g. 127.0.0.1:9184", listen));
+        }
+    }
+
+    if let Some(ref workers) = config.workers {
+        if workers.queue_size == Some(0) {
+            report.error("workers.queue_size", String::from("must be above 0"));
+        }
+        if let Some(ref overflow) = workers.overflow {
+            if let Err(e) = Overflow::parse(overflow) {
+                report.error("workers.overflow", e);
+            }
+        }
+        if workers.timeout_seconds == Some(0) {
+            report.error("workers.timeout_seconds", String::from("must be above 0"));
+        }
+        if let Some(ref timeouts) = workers.timeouts {
+            for (plugin, _) in timeouts.iter().filter(|&(_, seconds)| *seconds == 0) {
+                report.error("workers.timeouts", format!("the timeout of '{}' must be above 0", plugin));
+            }
+        }
+    }
+
+    report.problems
+}
+
+/// Reports tokens that are missing, still have the template value or do not start with one of `prefixes`
+fn token(report: &mut Report, key: &str, value: &str, prefixes: &[&str]) {
+    if value.trim().is_empty() {
+        let env = format!("{}{}", super::ENV_PREFIX, key.replace('.', "_").to_uppercase());
+        report.error(key, format!("is missing or empty, set it in the config file, with '{}_file' or with {}", key, env));
+    } else if value.starts_with(PLACEHOLDER) {
+        report.error(key, String::from("still has the placeholder value from the template"));
+    } else if !prefixes.is_empty() && !prefixes.iter().any(|p| value.starts_with(p)) {
+        report.error(key, format!("expected a token starting with {}", prefixes.join(" or ")));
+    }
+}
+
+/// Reports every key in the file BEST-Bot does not know
+fn unknown_keys(report: &mut Report) {
+    let value = match report.data.parse::<toml::Value>() {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    let root = match value.as_table() {
+        Some(root) => root,
+        None => return,
+    };
+
+    let mut unknown = Vec::new();
+    for (key, value) in root {
+        if !known("", key) {
+            unknown.push(key.clone());
+        } else if let Some(table) = value.as_table() {
+            for sub_key in table.keys() {
+                if !known(key, sub_key) {
+                    unknown.push(format!("{}.{}", key, sub_key));
+                }
+            }
+        }
+    }
+
+    for key in unknown {
+        report.warning(&key, String::from("unknown key, it is ignored"));
+    }
+}
+
+fn known(section: &str, key: &str) -> bool {
+    KNOWN_KEYS.iter()
+        .find(|&&(s, _)| s == section)
+        .map_or(false, |&(_, keys)| keys.contains(&key))
+}
+
+/// Finds the line (starting from 1) there the key is set. `key` is of the form `section.key` or `key`,
+/// a table (e.g. `workers.timeouts`) is found at its `[header]`
+fn find_line(data: &str, full_key: &str) -> Option<usize> {
+    let (section, key) = match full_key.rfind('.') {
+        Some(i) => (&full_key[..i], &full_key[i + 1..]),
+        None => ("", full_key),
+    };
+
+    let mut current = "";
+    for (number, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let header = match line.find('#') {
+                Some(i) => line[..i].trim_end(),
+                None => line,
+            };
+            current = header.trim_matches(|c| c == '[' || c == ']').trim();
+            if current == full_key {
+                return Some(number + 1);
+            }
+        } else if current == section && line.starts_with(key) {
+            let rest = line[key.len()..].trim_start();
+            if rest.starts_with('=') {
+                return Some(number + 1);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The unknown keys in `data` with the line and severity they are reported with
+    fn unknown(data: &str) -> Vec<(String, Option<usize>, Severity)> {
+        let mut report = Report {
+            file: Path::new("test.toml"),
+            data: data,
+            problems: Vec::new(),
+        };
+        unknown_keys(&mut report);
+        report.problems.into_iter().map(|p| (p.key, p.line, p.severity)).collect()
+    }
+
+    #[test]
+    fn unknown_keys_are_warnings() {
+        let data = "plugin_path = \"libs\"\nplugins = \"x\"\n\n[slack]\napi_token = \"xoxb-1\"\napi_tokens = \"xoxb-2\"\n";
+        assert_eq!(unknown(data), vec![
+            (String::from("plugins"), Some(2), Severity::Warning),
+            (String::from("slack.api_tokens"), Some(6), Severity::Warning),
+        ]);
+    }
+
+    #[test]
+    fn unknown_tables() {
+        let data = "[metrics]\nport = 1\n\n[log]\nlevel = \"info\"\n\n[log.extra]\nvalue = 1\n";
+        assert_eq!(unknown(data), vec![
+            (String::from("log.extra"), Some(7), Severity::Warning),
+            (String::from("metrics"), Some(1), Severity::Warning),
+        ]);
+    }
+
+    #[test]
+    fn nested_tables_of_known_keys_are_not_checked() {
+        let data = "[workers]\ntimeout_seconds = 10\n\n[workers.timeouts]\ncalendar = 30\n";
+        assert!(unknown(data).is_empty());
+    }
+
+    #[test]
+    fn finds_keys_in_sections() {
+        let data = "plugin_path = \"libs\"\n[slack]\napi_token = \"a\"\n[log]\nlevel = \"info\"\n";
+        assert_eq!(find_line(data, "plugin_path"), Some(1));
+        assert_eq!(find_line(data, "slack.api_token"), Some(3));
+        assert_eq!(find_line(data, "log.level"), Some(5));
+        assert_eq!(find_line(data, "level"), None);
+        assert_eq!(find_line(data, "slack.level"), None);
+        assert_eq!(find_line(data, "log"), Some(4));
+    }
+
+    #[test]
+    fn finds_nested_tables() {
+        let data = "[workers]\nqueue_size = 10\n\n[workers.timeouts]\ncalendar = 30\n";
+        assert_eq!(find_line(data, "workers.timeouts"), Some(4));
+        assert_eq!(find_line(data, "workers.timeouts.calendar"), Some(5));
+        assert_eq!(find_line(data, "workers.queue_size"), Some(2));
+    }
+
+    #[test]
+    fn skips_comments() {
+        let data = "# level = \"debug\"\n[log] # the logger\n  # level = \"trace\"\n  level = \"info\" # not debug\n";
+        assert_eq!(find_line(data, "log"), Some(2));
+        assert_eq!(find_line(data, "log.level"), Some(4));
+        assert_eq!(find_line(data, "level"), None);
+    }
+
+    #[test]
+    fn keys_that_are_prefixes_of_other_keys() {
+        let data = "[slack]\napi_token_file = \"/run/token\"\napi_token = \"xoxb-1\"\n";
+        assert_eq!(find_line(data, "slack.api_token"), Some(3));
+        assert_eq!(find_line(data, "slack.api_token_file"), Some(2));
+        assert_eq!(find_line(data, "slack.api"), None);
+    }
+}
@@ -0,0 +1,165 @@
+extern crate serde_json;
+
+use self::serde_json::{Map, Value};
+
+use template::slack::{Event, Message};
+use template::api::Channel;
+
+use plugin_worker;
+use slack_bot::{MyHandler, MyEventHandler};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static!(
+    /// Channel ids and their names from the fixture, used to print where a message was posted
+    static ref NAMES: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+);
+
+static HELP: &'static str = "\
+Every line is sent to the plugins as a message. Lines starting with / are commands:
+    /user <id>       Send the next lines as this user
+    /channel <id>    Send the next lines in this channel (an id starting with D is a direct message)
+    /help            Print this help
+    /quit            Stop BEST-Bot (so does end of input)";
+
+/// The fake workspace for `--console`, read from a JSON file like
+/// `{"user": "U0CONSOLE", "channel": "C0CONSOLE", "channels": {"C0CONSOLE": "console"}}`
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Fixture {
+    /// The user the typed lines come from
+    pub user: String,
+    /// The channel the typed lines are posted in
+    pub channel: String,
+    /// Channel ids and their names, `GetChannelName` is answered from these
+    pub channels: BTreeMap<String, String>,
+}
+
+impl Default for Fixture {
+    fn default() -> Fixture {
+        let mut channels = BTreeMap::new();
+        channels.insert(String::from("C0CONSOLE"), String::from("console"));
+
+        Fixture {
+            user: String::from("U0CONSOLE"),
+            channel: String::from("C0CONSOLE"),
+            channels: channels,
+        }
+    }
+}
+
+impl Fixture {
+    pub fn read(path: &Path) -> Result<Fixture, String> {
+        let file = File::open(path).map_err(|e| format!("Cannot open the fixture '{}' ({})", path.display(), e))?;
+        serde_json::from_reader(file).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Turns console mode on, from then on nothing is sent to Slack
+pub fn activate(fixture: &Fixture) {
+    *NAMES.write().unwrap() = fixture.channels.clone();
+    ACTIVE.store(true, Ordering::SeqCst);
+}
+
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Prints a message the host or a plugin posted
+pub fn print_post(channel: &str, text: &str) {
+    let name = NAMES.read().unwrap().get(channel).cloned().unwrap_or(channel.to_string());
+    println!("[#{}] BEST-Bot: {}", name, text.replace('\n', "\n    "));
+}
+
+/// Reads lines from the terminal and sends each one to the plugins as a message,
+/// until `/quit` or the end of the input
+pub fn run(handler: &mut MyHandler, fixture: Fixture) {
+    add_conversations(handler, &fixture);
+    handler.request_handler();
+
+    println!("{}", HELP);
+    let (mut user, mut channel) = (fixture.user, fixture.channel);
+    let stdin = io::stdin();
+
+    loop {
+        print!("{}@{}> ", user, channel);
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                error!("Cannot read from the terminal ({})", e);
+                break;
+            },
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('/') {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match (args[0], args.get(1)) {
+                ("/user", Some(id)) => user = id.to_string(),
+                ("/channel", Some(id)) => channel = id.to_string(),
+                ("/help", None) => println!("{}", HELP),
+                ("/quit", None) => break,
+                _ => println!("Unknown command '{}', try /help", line),
+            }
+            continue;
+        }
+
+        match message(&user, &channel, line) {
+            Ok(event) => {
+                handler.handle_event(event);
+                // So the answers are printed before the next prompt
+                plugin_worker::wait_idle(Duration::from_secs(5));
+            },
+            Err(e) => error!("Cannot make a message of '{}' ({})", line, e),
+        }
+    }
+}
+
+/// Fills the conversation cache from the fixture, since nothing can be looked up
+pub fn add_conversations(handler: &MyHandler, fixture: &Fixture) {
+    for (id, name) in &fixture.channels {
+        match channel(id, name) {
+            Ok(channel) => handler.add_conversation(channel),
+            Err(e) => error!("The channel '{}' in the fixture cannot be used ({})", id, e),
+        }
+    }
+}
+
+/// A `Message::Standard` event, as Slack would send it
+fn message(user: &str, channel: &str, text: &str) -> Result<Event, String> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let mut object = Map::new();
+    object.insert(String::from("type"), Value::String(String::from("message")));
+    object.insert(String::from("user"), Value::String(user.to_string()));
+    object.insert(String::from("channel"), Value::String(channel.to_string()));
+    object.insert(String::from("text"), Value::String(text.to_string()));
+    object.insert(String::from("ts"), Value::String(format!("{}.{:06}", since_epoch.as_secs(), since_epoch.subsec_micros())));
+
+    let message = serde_json::from_value(Value::Object(object)).map_err(|e| e.to_string())?;
+    Ok(Event::Message(Box::new(Message::Standard(message))))
+}
+
+fn channel(id: &str, name: &str) -> Result<Channel, String> {
+    let mut object = Map::new();
+    object.insert(String::from("id"), Value::String(id.to_string()));
+    object.insert(String::from("name"), Value::String(name.to_string()));
+    serde_json::from_value(Value::Object(object)).map_err(|e| e.to_string())
+}
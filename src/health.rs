@@ -0,0 +1,207 @@
+extern crate serde_json;
+
+use plugin_manager::PluginManager;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The threads `/healthz` reports on, a thread that has stopped makes BEST-Bot unhealthy
+static THREADS: &'static [&'static str] = &["logger", "request_handler", "extension_handler"];
+
+lazy_static!(
+    static ref RUNNING: Mutex<BTreeMap<&'static str, bool>> = Mutex::new(BTreeMap::new());
+    static ref PLUGINS: Mutex<Option<Arc<RwLock<PluginManager>>>> = Mutex::new(None);
+    /// The conversations that could not be looked up on connect, `None` until they have all been tried
+    static ref BOOTSTRAP: Mutex<Option<Vec<String>>> = Mutex::new(None);
+);
+
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Marks a thread as running until it is dropped, also when the thread panics
+pub struct Running(&'static str);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().insert(self.0, false);
+    }
+}
+
+/// Call at the start of a thread in `THREADS` and keep the guard for as long as the thread runs
+pub fn running(thread: &'static str) -> Running {
+    RUNNING.lock().unwrap().insert(thread, true);
+    Running(thread)
+}
+
+/// Set from `on_connect` and `on_close`
+pub fn set_connected(connected: bool) {
+    CONNECTED.store(connected, Ordering::SeqCst);
+    if !connected {
+        *BOOTSTRAP.lock().unwrap() = None;
+    }
+}
+
+/// Set once `on_connect` has tried to look up every conversation, with the ids of the ones that failed.
+/// BEST-Bot is only ready if none failed
+pub fn set_bootstrapped(failed: Vec<String>) {
+    *BOOTSTRAP.lock().unwrap() = Some(failed);
+}
+
+/// The plugins `/readyz` checks
+pub fn set_plugins(plugins: Arc<RwLock<PluginManager>>) {
+    *PLUGINS.lock().unwrap() = Some(plugins);
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    threads: BTreeMap<&'static str, &'static str>,
+}
+
+#[derive(Serialize)]
+struct Ready {
+    status: &'static str,
+    connected: bool,
+    conversations_bootstrapped: bool,
+    /// The ids of the conversations that could not be looked up
+    conversations_failed: Vec<String>,
+    plugins: Plugins,
+}
+
+#[derive(Serialize)]
+struct Plugins {
+    loaded: usize,
+    enabled: usize,
+    failed: Vec<String>,
+}
+
+/// For `/healthz`: whether the process and its threads are alive, with the details as JSON
+pub fn health() -> (bool, String) {
+    let (healthy, health) = check_health(&RUNNING.lock().unwrap());
+    (healthy, serde_json::to_string(&health).unwrap())
+}
+
+fn check_health(running: &BTreeMap<&'static str, bool>) -> (bool, Health) {
+    let threads: BTreeMap<&'static str, &'static str> = THREADS.iter()
+        .map(|thread| (*thread, match running.get(thread) {
+            Some(&true) => "running",
+            Some(&false) => "stopped",
+            None => "not started",
+        }))
+        .collect();
+
+    let healthy = threads.values().all(|state| *state != "stopped");
+    (healthy, Health {
+        status: if healthy { "ok" } else { "fail" },
+        threads: threads,
+    })
+}
+
+/// For `/readyz`: whether BEST-Bot is connected to Slack and every plugin is loaded, with the details as JSON
+pub fn readiness() -> (bool, String) {
+    let plugins = match *PLUGINS.lock().unwrap() {
+        Some(ref manager) => {
+            let manager = manager.read().unwrap();
+            let described = manager.describe();
+            Plugins {
+                loaded: described.len(),
+                enabled: described.iter().filter(|p| p.enabled).count(),
+                failed: manager.failed(),
+            }
+        },
+        None => Plugins {
+            loaded: 0,
+            enabled: 0,
+            failed: vec![String::from("the plugins have not been loaded yet")],
+        },
+    };
+
+    let (ready, readiness) = check_readiness(CONNECTED.load(Ordering::SeqCst), BOOTSTRAP.lock().unwrap().clone(), plugins);
+    (ready, serde_json::to_string(&readiness).unwrap())
+}
+
+fn check_readiness(connected: bool, bootstrap: Option<Vec<String>>, plugins: Plugins) -> (bool, Ready) {
+    let bootstrapped = bootstrap.as_ref().map_or(false, |failed| failed.is_empty());
+    let ready = connected && bootstrapped && plugins.failed.is_empty();
+    (ready, Ready {
+        status: if ready { "ok" } else { "fail" },
+        connected: connected,
+        conversations_bootstrapped: bootstrapped,
+        conversations_failed: bootstrap.unwrap_or_default(),
+        plugins: plugins,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugins(failed: &[&str]) -> Plugins {
+        Plugins {
+            loaded: 2,
+            enabled: 2,
+            failed: failed.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn healthy_while_every_thread_runs() {
+        let mut running = BTreeMap::new();
+        assert!(check_health(&running).0);
+
+        running.insert("logger", true);
+        running.insert("request_handler", true);
+        running.insert("extension_handler", true);
+        let (healthy, health) = check_health(&running);
+        assert!(healthy);
+        assert_eq!(health.status, "ok");
+    }
+
+    #[test]
+    fn unhealthy_when_a_thread_has_stopped() {
+        let mut running = BTreeMap::new();
+        running.insert("logger", true);
+        running.insert("request_handler", false);
+
+        let (healthy, health) = check_health(&running);
+        assert!(!healthy);
+        assert_eq!(health.status, "fail");
+        assert_eq!(health.threads["request_handler"], "stopped");
+        assert_eq!(health.threads["extension_handler"], "not started");
+    }
+
+    #[test]
+    fn a_panicking_thread_is_stopped() {
+        let _ = ::std::thread::spawn(|| {
+            let _running = running("health_test");
+            panic!("the thread stops");
+        }).join();
+        assert_eq!(RUNNING.lock().unwrap().get("health_test"), Some(&false));
+    }
+
+    #[test]
+    fn ready_when_connected_bootstrapped_and_every_plugin_loaded() {
+        let (ready, readiness) = check_readiness(true, Some(Vec::new()), plugins(&[]));
+        assert!(ready);
+        assert_eq!(readiness.status, "ok");
+    }
+
+    #[test]
+    fn not_ready_when_a_plugin_failed() {
+        let (ready, readiness) = check_readiness(true, Some(Vec::new()), plugins(&["libbroken.so: undefined symbol"]));
+        assert!(!ready);
+        assert_eq!(readiness.status, "fail");
+        assert_eq!(readiness.plugins.failed.len(), 1);
+    }
+
+    #[test]
+    fn not_ready_until_every_conversation_is_looked_up() {
+        assert!(!check_readiness(false, Some(Vec::new()), plugins(&[])).0);
+        assert!(!check_readiness(true, None, plugins(&[])).0);
+
+        let (ready, readiness) = check_readiness(true, Some(vec![String::from("C1")]), plugins(&[]));
+        assert!(!ready);
+        assert!(!readiness.conversations_bootstrapped);
+        assert_eq!(readiness.conversations_failed, vec!["C1"]);
+    }
+}
@@ -0,0 +1,145 @@
+use health;
+use metrics;
+
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// What an endpoint answers
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(status: &'static str, body: String) -> Response {
+        Response {
+            status: status,
+            content_type: "text/plain; charset=utf-8",
+            body: body,
+        }
+    }
+
+    /// 200 if `ok`, otherwise 503 so an orchestrator sees the failure from the status alone
+    fn json((ok, body): (bool, String)) -> Response {
+        Response {
+            status: if ok { "200 OK" } else { "503 Service Unavailable" },
+            content_type: "application/json",
+            body: body,
+        }
+    }
+}
+
+/// Starts the HTTP server on `address` from its own thread, every connection gets a thread too.
+/// Returns the address it listens on
+pub fn start(address: &str) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(address).map_err(|e| format!("cannot listen on '{}' ({})", address, e))?;
+    let local = listener.local_addr().map_err(|e| e.to_string())?;
+    info!("Serving HTTP on {}", local);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    thread::spawn(move || {
+                        if let Err(e) = handle(stream) {
+                            debug!("http: failed to answer a request ({})", e);
+                        }
+                    });
+                },
+                Err(e) => warn!("http: failed to accept a connection ({})", e),
+            }
+        }
+    });
+
+    Ok(local)
+}
+
+/// Reads a single request and answers it, the connection is closed afterwards
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // The headers are not needed
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+    debug!("http: {} {}", method, path);
+
+    let response = route(method, path);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    if method != "HEAD" {
+        stream.write_all(response.body.as_bytes())?;
+    }
+    stream.flush()
+}
+
+fn route(method: &str, path: &str) -> Response {
+    if method != "GET" && method != "HEAD" {
+        return Response::text("405 Method Not Allowed", String::from("Only GET is supported\n"));
+    }
+
+    match path {
+        "/metrics" => Response {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: metrics::render(),
+        },
+        "/healthz" => Response::json(health::health()),
+        "/readyz" => Response::json(health::readiness()),
+        _ => Response::text("404 Not Found", format!("There is nothing at '{}'\n", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn endpoints() {
+        let address = start("127.0.0.1:0").unwrap();
+
+        let response = get(address, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE best_bot_events_total counter\n"));
+
+        // Nothing is connected in a test
+        let response = get(address, "GET /readyz HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\"connected\":false"));
+
+        let response = get(address, "GET /nope HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = get(address, "POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
@@ -0,0 +1,269 @@
+extern crate chrono;
+extern crate flate2;
+
+use self::chrono::{DateTime, Local, NaiveDate};
+use self::flate2::Compression;
+use self::flate2::write::GzEncoder;
+
+use config;
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+static FILE_NAME: &'static str = "BEST-Bot.log";
+
+/// The log file. It is appended to and rotated by size and/or by day,
+/// the rotated files are named `BEST-Bot.log.1`, `BEST-Bot.log.2`, ... with `.1` being the newest
+pub struct RotatingFile {
+    dir: PathBuf,
+    file: File,
+    size: u64,
+    day: NaiveDate,
+    max_size: Option<u64>,
+    daily: bool,
+    keep: usize,
+    compress: bool,
+}
+
+impl RotatingFile {
+    /// Opens the log file in the folder from the config
+    pub fn open(log: &config::Log) -> io::Result<RotatingFile> {
+        let dir = log.path();
+        fs::create_dir_all(&dir)?;
+
+        let file = open(&dir)?;
+        let size = file.metadata()?.len();
+        // After a restart the file can be from an earlier day, it is then rotated before the first record
+        let day = written_on(&file);
+
+        Ok(RotatingFile {
+            dir: dir,
+            file: file,
+            size: size,
+            day: day,
+            max_size: log.max_size(),
+            daily: log.rotate_daily(),
+            keep: log.keep(),
+            compress: log.compress(),
+        })
+    }
+
+    /// Opens the file again, used after an external program (e.g. logrotate) has moved it
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file = open(&self.dir)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(FILE_NAME)
+    }
+
+    /// The path of the n'th rotated file
+    fn archive(&self, n: usize, compressed: bool) -> PathBuf {
+        if compressed {
+            self.dir.join(format!("{}.{}.gz", FILE_NAME, n))
+        } else {
+            self.dir.join(format!("{}.{}", FILE_NAME, n))
+        }
+    }
+
+    fn needs_rotation(&self, len: usize) -> bool {
+        let too_big = self.max_size.map_or(false, |max| self.size > 0 && self.size + len as u64 > max);
+        let new_day = self.daily && Local::now().date_naive() != self.day;
+        too_big || new_day
+    }
+
+    /// Moves every rotated file one number up, deletes the ones above `keep` and starts a new file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        for &compressed in &[false, true] {
+            let _ = fs::remove_file(self.archive(self.keep, compressed));
+            for n in (1..self.keep).rev() {
+                let from = self.archive(n, compressed);
+                if from.exists() {
+                    fs::rename(&from, self.archive(n + 1, compressed))?;
+                }
+            }
+        }
+
+        if self.keep > 0 {
+            let first = self.archive(1, false);
+            fs::rename(self.path(), &first)?;
+            if self.compress {
+                compress(&first, &self.archive(1, true))?;
+            }
+        } else {
+            fs::remove_file(self.path())?;
+        }
+
+        self.day = Local::now().date_naive();
+        self.reopen()
+    }
+}
+
+/// Every write is taken as one whole record: the file is rotated before it if needed, never in the middle of it
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.needs_rotation(buf.len()) {
+            if let Err(e) = self.rotate() {
+                let _ = writeln!(io::stderr(), "Failed to rotate the log file ({})", e);
+            }
+        }
+
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(dir: &PathBuf) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(dir.join(FILE_NAME))
+}
+
+/// The day the file was last written to, today if it is empty or the time is unknown
+fn written_on(file: &File) -> NaiveDate {
+    match file.metadata() {
+        Ok(ref metadata) if metadata.len() > 0 => metadata.modified()
+            .map(|modified| DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive()),
+        _ => Local::now().date_naive(),
+    }
+}
+
+/// Gzips `from` to `to` and deletes `from`
+fn compress(from: &PathBuf, to: &PathBuf) -> io::Result<()> {
+    let mut input = File::open(from)?;
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::flate2::read::GzDecoder;
+    use std::io::Read;
+
+    /// A log file in an empty folder of its own, `name` has to be unique among the tests
+    fn rotating(name: &str, max_size: u64, keep: usize, compress: bool) -> RotatingFile {
+        let dir = ::std::env::temp_dir().join(format!("best-bot-log-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        RotatingFile {
+            file: open(&dir).unwrap(),
+            dir: dir,
+            size: 0,
+            day: Local::now().date_naive(),
+            max_size: Some(max_size),
+            daily: false,
+            keep: keep,
+            compress: compress,
+        }
+    }
+
+    fn read(path: &PathBuf) -> String {
+        let mut data = String::new();
+        File::open(path).unwrap().read_to_string(&mut data).unwrap();
+        data
+    }
+
+    fn read_gz(path: &PathBuf) -> String {
+        let mut data = String::new();
+        GzDecoder::new(File::open(path).unwrap()).read_to_string(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_the_newest() {
+        let mut file = rotating("size", 30, 2, false);
+        for n in 1..5 {
+            file.write_all(format!("record {} -----------\n", n).as_bytes()).unwrap();
+        }
+
+        assert_eq!(read(&file.path()), "record 4 -----------\n");
+        assert_eq!(read(&file.archive(1, false)), "record 3 -----------\n");
+        assert_eq!(read(&file.archive(2, false)), "record 2 -----------\n");
+        assert!(!file.archive(3, false).exists());
+        let _ = fs::remove_dir_all(&file.dir);
+    }
+
+    #[test]
+    fn never_splits_a_record() {
+        let mut file = rotating("split", 30, 1, false);
+        file.write_all(b"first record -------\n").unwrap();
+        file.write_all(b"second record, too long for the rest of the file\n").unwrap();
+        file.write_all(b"third\n").unwrap();
+
+        assert_eq!(read(&file.archive(1, false)), "second record, too long for the rest of the file\n");
+        assert_eq!(read(&file.path()), "third\n");
+        let _ = fs::remove_dir_all(&file.dir);
+    }
+
+    #[test]
+    fn compresses_the_rotated_files() {
+        let mut file = rotating("compress", 10, 2, true);
+        for n in 1..4 {
+            file.write_all(format!("record {}\n", n).as_bytes()).unwrap();
+        }
+
+        assert_eq!(read(&file.path()), "record 3\n");
+        assert_eq!(read_gz(&file.archive(1, true)), "record 2\n");
+        assert_eq!(read_gz(&file.archive(2, true)), "record 1\n");
+        assert!(!file.archive(1, false).exists());
+        let _ = fs::remove_dir_all(&file.dir);
+    }
+
+    #[test]
+    fn rotates_a_file_from_an_earlier_day_after_a_restart() {
+        use std::time::{Duration, SystemTime};
+
+        let mut file = rotating("restart", 1024, 1, false);
+        file.write_all(b"yesterday\n").unwrap();
+        file.file.set_modified(SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60)).unwrap();
+
+        // What `open` does when BEST-Bot starts again
+        let reopened = open(&file.dir).unwrap();
+        assert!(written_on(&reopened) < Local::now().date_naive());
+        file.day = written_on(&reopened);
+        file.daily = true;
+        file.write_all(b"today\n").unwrap();
+
+        assert_eq!(read(&file.archive(1, false)), "yesterday\n");
+        assert_eq!(read(&file.path()), "today\n");
+        let _ = fs::remove_dir_all(&file.dir);
+    }
+
+    #[test]
+    fn an_empty_file_is_from_today() {
+        let file = rotating("empty", 1024, 1, false);
+        assert_eq!(written_on(&file.file), Local::now().date_naive());
+        let _ = fs::remove_dir_all(&file.dir);
+    }
+
+    #[test]
+    fn keeps_nothing_with_keep_0() {
+        let mut file = rotating("keep-0", 10, 0, false);
+        file.write_all(b"record 1\n").unwrap();
+        file.write_all(b"record 2\n").unwrap();
+
+        assert_eq!(read(&file.path()), "record 2\n");
+        assert_eq!(fs::read_dir(&file.dir).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(&file.dir);
+    }
+}
@@ -0,0 +1,110 @@
+use log::{Level, LevelFilter};
+
+use std::str::FromStr;
+
+/// Prefix of the directives that apply to a plugin instead of a module
+static PLUGIN_PREFIX: &'static str = "plugin:";
+
+/// Decides which records are logged, parsed from directives in the style of `env_logger`:
+/// `info,slack_bot=debug,plugin:karma=trace`.
+///
+/// A bare level sets the default, `module=level` applies to a module of BEST-Bot (with or without
+/// the `best_bot::` prefix) and everything in it, and `plugin:name=level` applies to a plugin.
+/// A directive without a level turns on everything for that module or plugin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+    plugins: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Filter, String> {
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+            plugins: Vec::new(),
+        };
+
+        for directive in spec.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let level = match parts.next() {
+                Some(level) => LevelFilter::from_str(level.trim()).map_err(|_| format!("unknown level '{}' in '{}'", level.trim(), directive))?,
+                None => match LevelFilter::from_str(name) {
+                    Ok(level) => {
+                        filter.default = level;
+                        continue;
+                    },
+                    Err(_) => LevelFilter::Trace,
+                },
+            };
+
+            if name.starts_with(PLUGIN_PREFIX) {
+                filter.plugins.push((name[PLUGIN_PREFIX.len()..].to_string(), level));
+            } else {
+                filter.modules.push((name.to_string(), level));
+            }
+        }
+
+        // The longest module names are tried first, so the most specific directive wins
+        filter.modules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(filter)
+    }
+
+    /// The level for a record from the host (`plugin` is `None`) or from a plugin
+    pub fn level_for(&self, target: &str, plugin: Option<&str>) -> LevelFilter {
+        match plugin {
+            Some(plugin) => self.plugins.iter()
+                .find(|&&(ref name, _)| name == plugin)
+                .map_or(self.default, |&(_, level)| level),
+            None => {
+                let short = target.splitn(2, "::").nth(1).unwrap_or("");
+                self.modules.iter()
+                    .find(|&&(ref name, _)| in_module(target, name) || in_module(short, name))
+                    .map_or(self.default, |&(_, level)| level)
+            },
+        }
+    }
+
+    pub fn enabled(&self, level: Level, target: &str, plugin: Option<&str>) -> bool {
+        level <= self.level_for(target, plugin)
+    }
+
+    /// The most verbose level of all the directives, records above it are never logged
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules.iter().chain(self.plugins.iter())
+            .map(|&(_, level)| level)
+            .fold(self.default, |max, level| if level > max { level } else { max })
+    }
+}
+
+fn in_module(path: &str, module: &str) -> bool {
+    path == module || (path.starts_with(module) && path[module.len()..].starts_with("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives() {
+        let filter = Filter::parse("warn, slack_bot=debug, plugin:karma=trace, best_bot::logger").unwrap();
+
+        assert_eq!(filter.level_for("best_bot::main", None), LevelFilter::Warn);
+        assert_eq!(filter.level_for("best_bot::slack_bot", None), LevelFilter::Debug);
+        assert_eq!(filter.level_for("best_bot::slack_bot::inner", None), LevelFilter::Debug);
+        assert_eq!(filter.level_for("best_bot::slack_botx", None), LevelFilter::Warn);
+        assert_eq!(filter.level_for("best_bot::logger", None), LevelFilter::Trace);
+        assert_eq!(filter.level_for("best_bot::logger", Some("karma")), LevelFilter::Trace);
+        assert_eq!(filter.level_for("best_bot::logger", Some("calendar")), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Filter::parse("slack_bot=loud").is_err());
+        assert_eq!(Filter::parse("").unwrap().max_level(), LevelFilter::Info);
+        assert_eq!(Filter::parse("DEBUG").unwrap().max_level(), LevelFilter::Debug);
+    }
+}
@@ -0,0 +1,282 @@
+extern crate chrono;
+extern crate serde_json;
+extern crate thread_id;
+
+use self::chrono::{DateTime, Local, Utc};
+use self::serde_json::{Map, Number, Value};
+
+use log::{Level, Record};
+use log::kv;
+
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The line format used when none is configured, it gives the same lines as earlier versions
+pub static DEFAULT_LINE_FORMAT: &'static str = "[{uptime}] ({thread}) [{plugin}] {level:6} {message}";
+
+/// How a log record is written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// One human readable line per record
+    Text,
+    /// One JSON object per line, for log shippers
+    Json,
+}
+
+/// When and where a record was logged, taken before it is queued for writing
+#[derive(Clone, Copy, Debug)]
+pub struct Origin {
+    pub time: DateTime<Local>,
+    /// Time since BEST-Bot was started
+    pub uptime: Duration,
+    pub thread: usize,
+}
+
+impl Origin {
+    /// The origin of a record logged on this thread now
+    pub fn now(uptime: Duration) -> Origin {
+        Origin {
+            time: Local::now(),
+            uptime: uptime,
+            thread: thread_id::get(),
+        }
+    }
+}
+
+/// The record as a single line following `line_format`, ending in a newline. The placeholders are
+/// `{local}`, `{utc}`, `{uptime}`, `{level}`, `{plugin}`, `{thread}`, `{module}` and `{message}`,
+/// a minimum width can be given as `{level:6}`. With `color` the level is coloured by ANSI codes
+pub fn text(line_format: &str, origin: &Origin, record: &Record, plugin_name: &str, color: bool) -> String {
+    let mut line = String::new();
+    let mut rest = line_format;
+
+    while let Some(start) = rest.find('{') {
+        line.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                rest = &rest[start..];
+                break;
+            },
+        };
+
+        let placeholder = &rest[start + 1..end];
+        let (name, width) = match placeholder.find(':') {
+            Some(i) => (&placeholder[..i], placeholder[i + 1..].parse::<usize>().unwrap_or(0)),
+            None => (placeholder, 0),
+        };
+
+        let value = match name {
+            "local" => origin.time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            "utc" => origin.time.with_timezone(&Utc).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            "uptime" => format_uptime(origin.uptime),
+            "level" => record.level().to_string(),
+            "plugin" => plugin_name.to_string(),
+            "thread" => format!("{:x}", origin.thread),
+            "module" => record.module_path().unwrap_or("").to_string(),
+            "message" => record.args().to_string(),
+            _ => format!("{{{}}}", placeholder),
+        };
+
+        if name == "level" && color {
+            let _ = write!(line, "\x1b[{}m{:<width$}\x1b[0m", ansi_color(record.level()), value, width = width);
+        } else {
+            let _ = write!(line, "{:<width$}", value, width = width);
+        }
+        rest = &rest[end + 1..];
+    }
+    line.push_str(rest);
+    line.push('\n');
+    line
+}
+
+/// Time since BEST-Bot was started as `hh:mm:ss.mmm`
+fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60,
+        uptime.subsec_nanos() / 1_000_000
+    )
+}
+
+fn ansi_color(level: Level) -> &'static str {
+    match level {
+        Level::Error => "31",
+        Level::Warn => "33",
+        Level::Info => "32",
+        Level::Debug => "34",
+        Level::Trace => "35",
+    }
+}
+
+/// The record as a single line of JSON, ending in a newline
+pub fn json(origin: &Origin, record: &Record, plugin_name: &str) -> String {
+    let mut fields = Fields(Map::new());
+    let _ = record.key_values().visit(&mut fields);
+
+    let mut object = Map::new();
+    object.insert(String::from("timestamp"), Value::String(origin.time.to_rfc3339()));
+    object.insert(String::from("level"), Value::String(record.level().to_string()));
+    object.insert(String::from("plugin"), Value::String(plugin_name.to_string()));
+    object.insert(String::from("module"), record.module_path().map_or(Value::Null, |m| Value::String(m.to_string())));
+    object.insert(String::from("file"), record.file().map_or(Value::Null, |f| Value::String(f.to_string())));
+    object.insert(String::from("line"), record.line().map_or(Value::Null, |l| Value::Number(Number::from(l))));
+    object.insert(String::from("thread"), Value::String(format!("{:x}", origin.thread)));
+    object.insert(String::from("message"), Value::String(record.args().to_string()));
+    object.insert(String::from("fields"), Value::Object(fields.0));
+
+    let mut line = Value::Object(object).to_string();
+    line.push('\n');
+    line
+}
+
+/// Collects the structured key-values of a record
+struct Fields(Map<String, Value>);
+
+impl<'kvs> kv::VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(b) = value.to_bool() {
+            Value::Bool(b)
+        } else if let Some(n) = value.to_i64() {
+            Value::Number(Number::from(n))
+        } else if let Some(n) = value.to_u64() {
+            Value::Number(Number::from(n))
+        } else if let Some(n) = value.to_f64().and_then(Number::from_f64) {
+            Value::Number(n)
+        } else {
+            Value::String(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::chrono::FixedOffset;
+    use log::kv::Source;
+
+    fn origin() -> Origin {
+        Origin {
+            time: DateTime::<FixedOffset>::parse_from_rfc3339("2024-03-01T12:34:56.789Z").unwrap().with_timezone(&Local),
+            uptime: Duration::from_millis(3_723_045),
+            thread: 0x2a,
+        }
+    }
+
+    /// Formats a record logged from `src/slack_bot.rs:42` with `format`
+    fn format<F: FnOnce(&Record) -> String>(level: Level, message: &str, fields: &Source, format: F) -> String {
+        format(&Record::builder()
+            .args(format_args!("{}", message))
+            .level(level)
+            .module_path(Some("best_bot::slack_bot"))
+            .file(Some("src/slack_bot.rs"))
+            .line(Some(42))
+            .key_values(fields)
+            .build())
+    }
+
+    fn line(line_format: &str, message: &str, color: bool) -> String {
+        format(Level::Warn, message, &[("user", "U123")], |record| text(line_format, &origin(), record, "karma", color))
+    }
+
+    #[test]
+    fn text_default_format() {
+        assert_eq!(line(DEFAULT_LINE_FORMAT, "hello", false), "[01:02:03.045] (2a) [karma] WARN   hello\n");
+    }
+
+    #[test]
+    fn text_placeholders() {
+        assert_eq!(line("{utc} {module} {plugin}", "", false), "2024-03-01T12:34:56.789Z best_bot::slack_bot karma\n");
+        assert_eq!(line("{local}", "", false), format!("{}\n", origin().time.format("%Y-%m-%d %H:%M:%S%.3f")));
+        // The message is not read as a format
+        assert_eq!(line("{message}", "a {level} b", false), "a {level} b\n");
+    }
+
+    #[test]
+    fn text_widths() {
+        assert_eq!(line("{level:6}|", "", false), "WARN  |\n");
+        assert_eq!(line("{plugin:2}|", "", false), "karma|\n");
+        // A width that is not a number is ignored
+        assert_eq!(line("{level:x}|{level:-1}|{level:}|", "", false), "WARN|WARN|WARN|\n");
+    }
+
+    #[test]
+    fn text_keeps_unknown_placeholders_and_lone_braces() {
+        assert_eq!(line("{nope} {nope:5} {}", "", false), "{nope} {nope:5} {}\n");
+        assert_eq!(line("}{level}{", "", false), "}WARN{\n");
+        assert_eq!(line("{level", "", false), "{level\n");
+        assert_eq!(line("{{level}}", "", false), "{{level}}\n");
+    }
+
+    #[test]
+    fn text_colors_only_the_level() {
+        assert_eq!(line("{level:6} {message}", "hello", true), "\x1b[33mWARN  \x1b[0m hello\n");
+    }
+
+    fn parse(line: &str) -> Value {
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.matches('\n').count(), 1, "{}", line);
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn json_has_every_field() {
+        let line = format(Level::Warn, "hello", &[("user", "U123")], |record| json(&origin(), record, "karma"));
+        let object = parse(&line);
+
+        assert_eq!(object["timestamp"], Value::String(origin().time.to_rfc3339()));
+        assert_eq!(object["level"], "WARN");
+        assert_eq!(object["plugin"], "karma");
+        assert_eq!(object["module"], "best_bot::slack_bot");
+        assert_eq!(object["file"], "src/slack_bot.rs");
+        assert_eq!(object["line"], 42);
+        assert_eq!(object["thread"], "2a");
+        assert_eq!(object["message"], "hello");
+        assert_eq!(object["fields"]["user"], "U123");
+    }
+
+    #[test]
+    fn json_escapes_the_message() {
+        let message = "a \"quoted\" \\ path\nsecond line\tand \u{1} and æøå";
+        let line = format(Level::Error, message, &[("key", "value")], |record| json(&origin(), record, "plug\"in"));
+        let object = parse(&line);
+
+        assert_eq!(object["message"], message);
+        assert_eq!(object["plugin"], "plug\"in");
+    }
+
+    #[test]
+    fn json_keeps_the_type_of_the_fields() {
+        let fields = [
+            ("bool", kv::Value::from(true)),
+            ("negative", kv::Value::from(-3i64)),
+            ("count", kv::Value::from(u64::max_value())),
+            ("ratio", kv::Value::from(0.5f64)),
+            ("text", kv::Value::from("xoxb")),
+        ];
+        let line = format(Level::Info, "fields", &fields, |record| json(&origin(), record, ""));
+        let object = parse(&line);
+
+        assert_eq!(object["fields"]["bool"], true);
+        assert_eq!(object["fields"]["negative"], -3);
+        assert_eq!(object["fields"]["count"], u64::max_value());
+        assert_eq!(object["fields"]["ratio"], 0.5);
+        assert_eq!(object["fields"]["text"], "xoxb");
+    }
+
+    #[test]
+    fn json_without_a_location() {
+        let line = json(&origin(), &Record::builder().args(format_args!("bare")).build(), "");
+        let object = parse(&line);
+
+        assert_eq!(object["module"], Value::Null);
+        assert_eq!(object["file"], Value::Null);
+        assert_eq!(object["line"], Value::Null);
+        assert_eq!(object["fields"], Value::Object(Map::new()));
+    }
+}
@@ -0,0 +1,325 @@
+extern crate chrono;
+
+use self::chrono::SecondsFormat;
+
+use log_format::Origin;
+
+use log::{Level, Record};
+
+use std::env;
+use std::fs;
+use std::io;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// The socket journald listens for native protocol messages on
+static JOURNALD_SOCKET: &'static str = "/run/systemd/journal/socket";
+
+/// The name BEST-Bot logs as in syslog and journald
+static APP_NAME: &'static str = "best-bot";
+
+/// The syslog severity of a log level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug => 7,
+        Level::Trace => 7,
+    }
+}
+
+/// Sends the records to journald with the native protocol, so they keep their fields
+/// (`PRIORITY=`, `PLUGIN=`, `CODE_FILE=`, ...)
+pub struct Journald {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl Journald {
+    pub fn connect() -> io::Result<Journald> {
+        Journald::connect_to(JOURNALD_SOCKET)
+    }
+
+    pub fn connect_to<P: AsRef<Path>>(path: P) -> io::Result<Journald> {
+        Ok(Journald {
+            socket: UnixDatagram::unbound()?,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn send(&self, record: &Record, plugin_name: &str, origin: &Origin) -> io::Result<()> {
+        let mut data = Vec::new();
+        field(&mut data, "MESSAGE", &record.args().to_string());
+        field(&mut data, "PRIORITY", &severity(record.level()).to_string());
+        field(&mut data, "SYSLOG_IDENTIFIER", APP_NAME);
+        field(&mut data, "PLUGIN", plugin_name);
+        field(&mut data, "TARGET", record.target());
+        field(&mut data, "THREAD_ID", &format!("{:x}", origin.thread));
+        if let Some(module) = record.module_path() {
+            field(&mut data, "CODE_MODULE", module);
+        }
+        if let Some(file) = record.file() {
+            field(&mut data, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            field(&mut data, "CODE_LINE", &line.to_string());
+        }
+
+        self.socket.send_to(&data, &self.path).map(|_| ())
+    }
+}
+
+/// Adds a field in the journald native format, values with a newline are length prefixed
+fn field(data: &mut Vec<u8>, name: &str, value: &str) {
+    data.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        data.push(b'\n');
+        let len = value.len() as u64;
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(value.as_bytes());
+    } else {
+        data.push(b'=');
+        data.extend_from_slice(value.as_bytes());
+    }
+    data.push(b'\n');
+}
+
+enum Transport {
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket),
+}
+
+/// Sends the records as RFC 5424 syslog messages over a Unix socket or UDP
+pub struct Syslog {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+    sd_id: Option<String>,
+}
+
+impl Syslog {
+    /// Connects to `address`, which is either `unix:/dev/log` or `udp:host:port`. The plugin and
+    /// target are sent as structured data only if there is an `sd_id`
+    pub fn connect(address: &str, facility: &str, sd_id: Option<String>) -> Result<Syslog, String> {
+        let facility = parse_facility(facility).ok_or(format!("unknown syslog facility '{}'", facility))?;
+
+        let transport = if address.starts_with("unix:") {
+            let path = PathBuf::from(&address["unix:".len()..]);
+            let socket = UnixDatagram::unbound().map_err(|e| e.to_string())?;
+            Transport::Unix(socket, path)
+        } else if address.starts_with("udp:") {
+            let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+            socket.connect(&address["udp:".len()..]).map_err(|e| format!("cannot connect to '{}' ({})", address, e))?;
+            Transport::Udp(socket)
+        } else {
+            return Err(format!("the syslog address '{}' must start with unix: or udp:", address));
+        };
+
+        if let Some(ref sd_id) = sd_id {
+            check_sd_id(sd_id)?;
+        }
+
+        Ok(Syslog {
+            transport: transport,
+            facility: facility,
+            hostname: hostname(),
+            sd_id: sd_id,
+        })
+    }
+
+    pub fn send(&self, record: &Record, plugin_name: &str, origin: &Origin) -> io::Result<()> {
+        let message = self.format(record, plugin_name, origin);
+        match self.transport {
+            Transport::Unix(ref socket, ref path) => socket.send_to(message.as_bytes(), path).map(|_| ()),
+            Transport::Udp(ref socket) => socket.send(message.as_bytes()).map(|_| ()),
+        }
+    }
+
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD MSG`
+    fn format(&self, record: &Record, plugin_name: &str, origin: &Origin) -> String {
+        let structured_data = match self.sd_id {
+            Some(ref sd_id) => format!("[{} plugin=\"{}\" target=\"{}\"]", sd_id, escape(plugin_name), escape(record.target())),
+            None => String::from("-"),
+        };
+        format!(
+            "<{}>1 {} {} {} {} - {} {}",
+            self.facility * 8 + severity(record.level()),
+            origin.time.to_rfc3339_opts(SecondsFormat::Micros, false),
+            self.hostname,
+            APP_NAME,
+            process::id(),
+            structured_data,
+            record.args()
+        )
+    }
+}
+
+/// The name of this machine from /proc, $HOSTNAME or the `hostname` command, "-" (the nil value)
+/// if none of them has it
+fn hostname() -> String {
+    let from_proc = || fs::read_to_string("/proc/sys/kernel/hostname").ok();
+    let from_env = || env::var("HOSTNAME").ok();
+    let from_command = || Command::new("hostname").output().ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    from_proc().into_iter().chain(from_env()).chain(from_command())
+        .map(|hostname| hostname.trim().to_string())
+        .find(|hostname| !hostname.is_empty() && hostname.len() <= 255 && hostname.bytes().all(|b| b > 32 && b < 127))
+        .unwrap_or_else(|| String::from("-"))
+}
+
+/// An SD-ID without an IANA registered name must be "name@<private enterprise number>"
+pub fn check_sd_id(sd_id: &str) -> Result<(), String> {
+    let mut parts = sd_id.splitn(2, '@');
+    let name = parts.next().unwrap_or("");
+    let number = parts.next().unwrap_or("");
+    let printable = sd_id.bytes().all(|b| b > 32 && b < 127 && b != b'=' && b != b']' && b != b'"');
+    let enterprise_number = number.split('.').all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    if !printable || sd_id.len() > 32 || name.is_empty() || !enterprise_number {
+        return Err(format!("the SD-ID '{}' must be name@<private enterprise number> with at most 32 characters", sd_id));
+    }
+    Ok(())
+}
+
+/// Escapes the characters RFC 5424 does not allow in a structured data value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+pub fn parse_facility(facility: &str) -> Option<u8> {
+    let facility = facility.to_lowercase();
+    let number = match facility.as_str() {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        _ if facility.starts_with("local") => match facility["local".len()..].parse::<u8>() {
+            Ok(n) if n <= 7 => 16 + n,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::str;
+    use std::time::Duration;
+
+    fn record<F: FnOnce(&Record)>(f: F) {
+        f(&Record::builder()
+            .args(format_args!("two\nlines"))
+            .level(Level::Warn)
+            .target("best_bot::slack_bot")
+            .build())
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("best-bot-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn journald() {
+        let path = socket_path("journald");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let journald = Journald::connect_to(&path).unwrap();
+        record(|r| journald.send(r, "karma", &Origin::now(Duration::from_secs(0))).unwrap());
+
+        let mut buf = [0; 4096];
+        let n = server.recv(&mut buf).unwrap();
+        let data = &buf[..n];
+
+        let mut message = b"MESSAGE\n".to_vec();
+        message.extend_from_slice(&9u64.to_le_bytes());
+        message.extend_from_slice(b"two\nlines\n");
+        assert!(data.starts_with(&message));
+
+        let rest = str::from_utf8(&data[message.len()..]).unwrap();
+        assert!(rest.contains("PRIORITY=4\n"));
+        assert!(rest.contains("PLUGIN=karma\n"));
+        assert!(rest.contains("SYSLOG_IDENTIFIER=best-bot\n"));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn syslog_unix() {
+        let path = socket_path("syslog");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let syslog = Syslog::connect(&format!("unix:{}", path.display()), "local3", Some(String::from("best-bot@32473"))).unwrap();
+        record(|r| syslog.send(r, "ka\"rma", &Origin::now(Duration::from_secs(0))).unwrap());
+
+        let mut buf = [0; 4096];
+        let n = server.recv(&mut buf).unwrap();
+        let message = str::from_utf8(&buf[..n]).unwrap();
+
+        assert!(message.starts_with("<156>1 "));
+        assert!(message.contains(&format!(" best-bot {} - [best-bot@32473 plugin=\"ka\\\"rma\" target=\"best_bot::slack_bot\"] two\nlines", process::id())));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn syslog_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = format!("udp:{}", server.local_addr().unwrap());
+
+        let syslog = Syslog::connect(&address, "daemon", None).unwrap();
+        record(|r| syslog.send(r, "BEST-Bot", &Origin::now(Duration::from_secs(0))).unwrap());
+
+        let mut buf = [0; 4096];
+        let n = server.recv(&mut buf).unwrap();
+        let message = str::from_utf8(&buf[..n]).unwrap();
+        assert!(message.starts_with("<28>1 "));
+        // Without an SD-ID there is no structured data
+        assert!(message.contains(&format!(" best-bot {} - - two\nlines", process::id())));
+    }
+
+    #[test]
+    fn sd_ids() {
+        assert!(check_sd_id("best-bot@32473").is_ok());
+        assert!(check_sd_id("bot@32473.1.2").is_ok());
+        assert!(check_sd_id("best-bot").is_err());
+        assert!(check_sd_id("@32473").is_err());
+        assert!(check_sd_id("best-bot@").is_err());
+        assert!(check_sd_id("best-bot@ex").is_err());
+        assert!(check_sd_id("best bot@32473").is_err());
+        assert!(check_sd_id("a-name-that-is-far-too-long@32473").is_err());
+        assert!(Syslog::connect("unix:/dev/log", "daemon", Some(String::from("best-bot"))).is_err());
+    }
+
+    #[test]
+    fn hostname_is_never_empty() {
+        let hostname = hostname();
+        assert!(!hostname.is_empty());
+        assert!(!hostname.contains(char::is_whitespace));
+    }
+
+    #[test]
+    fn facilities() {
+        assert_eq!(parse_facility("daemon"), Some(3));
+        assert_eq!(parse_facility("LOCAL7"), Some(23));
+        assert_eq!(parse_facility("local8"), None);
+        assert_eq!(parse_facility("nope"), None);
+        assert!(Syslog::connect("tcp:localhost:514", "daemon", None).is_err());
+    }
+}
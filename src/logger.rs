@@ -1,21 +1,39 @@
 extern crate log;
-extern crate thread_id;
-use log::{Record, Level, Metadata, RecordBuilder};
+use log::{Record, Level, LevelFilter, Metadata, RecordBuilder};
 use log::{SetLoggerError};
+use log::kv;
 
+use config;
 use config::CONFIG;
+use health;
+use log_file::RotatingFile;
+use log_filter::Filter;
+use log_sink::{Journald, Syslog};
+use slack_sink;
+use slack_sink::Alert;
+use log_format;
+use log_format::{Format, Origin};
+use metrics;
+use queue::{BoundedQueue, Overflow};
 
 use template::logger::Log;
 
 use std::sync::mpsc::{channel, Sender};
 use std::thread;
+use std::thread::JoinHandle;
 use std::io;
-use std::io::Write;
-use std::sync::Mutex;
+use std::io::{IsTerminal, Write};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Mutex, RwLock};
 use std::time::Instant;
 use std::time::Duration;
-use std::fs::create_dir_all;
-use std::fs::File;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+extern crate chrono;
+extern crate signal_hook;
+
+use self::chrono::{DateTime, Local};
 
 lazy_static!(
     static ref LOGGER: Logger = Logger {
@@ -23,7 +41,17 @@ lazy_static!(
         stdout: Mutex::new(Box::new(io::stdout())),
         stderr: Mutex::new(Box::new(io::stderr())),
         file: Mutex::new(None),
+        journald: Mutex::new(None),
+        syslog: Mutex::new(None),
+        slack: Mutex::new(None),
+        history: Mutex::new(BTreeMap::new()),
+        filter: RwLock::new(Filter::parse("info").unwrap()),
+        queue: BoundedQueue::new(10000, Overflow::DropOldest),
+        writer: Mutex::new(None),
     };
+
+    /// Set on SIGUSR1, the log file is opened again before the next write
+    static ref REOPEN: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
 );
 
 pub fn init() -> Result<Sender<(String, Log)>, SetLoggerError> {
@@ -36,9 +64,6 @@ pub fn init() -> Result<Sender<(String, Log)>, SetLoggerError> {
                     .args(format_args!($($arg)+))
                     .level(lvl)
                     .target($target)
-                    .module_path(Some(module_path!()))
-                    .file(Some(file!()))
-                    .line(Some(line!()))
                     .build(),
                 &$plugin_name
             )
@@ -48,20 +73,22 @@ pub fn init() -> Result<Sender<(String, Log)>, SetLoggerError> {
 
     let (sender, receiver) = channel::<(String, Log)>();
 
-    let log = CONFIG.log();
+    let log = CONFIG.get().log();
+    *LOGGER.filter.write().unwrap() = log.filter();
     log::set_max_level(log.level());
+    LOGGER.queue.configure(log.queue_size(), log.overflow());
 
     let result = log::set_logger(&LOGGER);
     match result {
         Ok(_) => {
-            if CONFIG.log().to_file() {
-                let mut path = log.path();
-                create_dir_all(path.as_path()).expect(&format!("Failed to create the directory '{}'", path.to_str().unwrap()));
-                path.push("BEST-Bot.log");
-                match File::create(path) {
-                    Ok(f) => *LOGGER.file.lock().unwrap() = Some(Box::new(f)),
-                    Err(e) => error!("could not create the log file ({:?})", e),
-                }
+            *LOGGER.writer.lock().unwrap() = Some(thread::spawn(write_queued));
+            if log.to_file() {
+                *LOGGER.file.lock().unwrap() = open_file(&log);
+            }
+            open_system_sinks(&log);
+            start_slack_sink(&log);
+            if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGUSR1, REOPEN.clone()) {
+                error!("Cannot listen for SIGUSR1, the log file cannot be reopened ({})", e);
             }
 
             thread::spawn(move || {
@@ -86,65 +113,385 @@ pub fn init() -> Result<Sender<(String, Log)>, SetLoggerError> {
     }
 }
 
-fn write<S: Write>(sink: &mut S, now: Duration, record: &Record, plugin_name: &str) {
-    let seconds = now.as_secs();
-    let hours = seconds / 3600;
-    let minutes = (seconds / 60) % 60;
-    let seconds = seconds % 60;
-    let miliseconds = now.subsec_nanos() / 1_000_000;
+/// Applies the log settings of a reloaded config
+pub fn reconfigure(old: &config::Config, new: &config::Config) {
+    let (old, new) = (old.log(), new.log());
+
+    if old.filter() != new.filter() {
+        let filter = new.filter();
+        log::set_max_level(filter.max_level());
+        *LOGGER.filter.write().unwrap() = filter;
+        info!("Log filter changed");
+    }
+
+    let file_settings = |log: &config::Log| (log.to_file(), log.path(), log.max_size(), log.rotate_daily(), log.keep(), log.compress());
+    if file_settings(&old) != file_settings(&new) {
+        let file = if new.to_file() {
+            open_file(&new)
+        } else {
+            None
+        };
+        *LOGGER.file.lock().unwrap() = file;
+        info!("Log file settings changed, writing to file: {}", new.to_file());
+    }
+
+    let system_settings = |log: &config::Log| (log.to_journald(), log.syslog(), log.syslog_facility(), log.syslog_sd_id());
+    if system_settings(&old) != system_settings(&new) {
+        open_system_sinks(&new);
+        info!("journald/syslog settings changed");
+    }
+
+    if old.slack_settings() != new.slack_settings() {
+        start_slack_sink(&new);
+        info!("Slack log settings changed");
+    }
+
+    if (old.queue_size(), old.overflow()) != (new.queue_size(), new.overflow()) {
+        LOGGER.queue.configure(new.queue_size(), new.overflow());
+        info!("Log queue settings changed, size: {}, overflow: {:?}", new.queue_size(), new.overflow());
+    }
+}
+
+/// Writes every record in the queue and stops the writer thread, records logged after this are lost
+pub fn shutdown() {
+    log::logger().flush();
+    LOGGER.queue.close();
+    if let Some(writer) = LOGGER.writer.lock().unwrap().take() {
+        let _ = writer.join();
+    }
+}
+
+/// How many records have been dropped because the queue was full
+pub fn dropped() -> usize {
+    LOGGER.queue.dropped()
+}
+
+/// Starts posting records to Slack if a channel is configured, an already running sink is stopped
+fn start_slack_sink(log: &config::Log) {
+    *LOGGER.slack.lock().unwrap() = log.slack_settings().map(|settings| {
+        let sender = slack_sink::start(settings.clone());
+        (settings, sender)
+    });
+}
+
+/// Opens the journald and syslog sinks there are turned on in the config, and closes the others
+fn open_system_sinks(log: &config::Log) {
+    *LOGGER.journald.lock().unwrap() = if log.to_journald() {
+        match Journald::connect() {
+            Ok(journald) => Some(journald),
+            Err(e) => {
+                error!("could not connect to journald ({:?})", e);
+                None
+            },
+        }
+    } else {
+        None
+    };
+
+    *LOGGER.syslog.lock().unwrap() = match log.syslog() {
+        Some(address) => match Syslog::connect(&address, &log.syslog_facility(), log.syslog_sd_id()) {
+            Ok(syslog) => Some(syslog),
+            Err(e) => {
+                error!("could not connect to syslog ({})", e);
+                None
+            },
+        },
+        None => None,
+    };
+}
+
+/// Replaces the log filter, e.g. with `info,slack_bot=debug,plugin:karma=trace`
+pub fn set_filter(spec: &str) -> Result<(), String> {
+    let filter = Filter::parse(spec)?;
+    log::set_max_level(filter.max_level());
+    *LOGGER.filter.write().unwrap() = filter;
+    info!("Log filter changed to '{}'", spec);
+    Ok(())
+}
+
+/// Opens the log file in the folder from the config
+fn open_file(log: &config::Log) -> Option<RotatingFile> {
+    match RotatingFile::open(log) {
+        Ok(f) => Some(f),
+        Err(e) => {
+            error!("could not open the log file in '{}' ({:?})", log.path().display(), e);
+            None
+        },
+    }
+}
+
+/// Writes the whole record with a single `write_all`, so the log file is never rotated in the middle of it
+fn write<S: Write>(sink: &mut S, origin: &Origin, record: &Record, plugin_name: &str, log: &config::Log, color: bool) {
+    let line = match log.format() {
+        Format::Text => log_format::text(&log.line_format(), origin, record, plugin_name, color),
+        Format::Json => log_format::json(origin, record, plugin_name),
+    };
+    let _ = sink.write_all(line.as_bytes());
+}
+
+/// A record waiting in the queue for the writer thread
+struct Queued {
+    level: Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    message: String,
+    fields: Fields,
+    plugin_name: String,
+    origin: Origin,
+    /// Logged on the thread posting to Slack, so it is not posted again
+    from_slack_sink: bool,
+}
+
+enum Message {
+    Record(Queued),
+    /// Answered once every record queued before it has been written
+    Flush(Sender<()>),
+}
+
+/// A structured value of a record, copied so it can outlive the record
+enum Field {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+}
+
+impl kv::ToValue for Field {
+    fn to_value(&self) -> kv::Value {
+        match *self {
+            Field::Bool(b) => kv::Value::from(b),
+            Field::I64(n) => kv::Value::from(n),
+            Field::U64(n) => kv::Value::from(n),
+            Field::F64(n) => kv::Value::from(n),
+            Field::Str(ref s) => kv::Value::from(s.as_str()),
+        }
+    }
+}
+
+/// The structured key-values of a queued record
+struct Fields(Vec<(String, Field)>);
 
-    let _ = write!(
-        sink,
-        "[{:02}:{:02}:{:02}.{:03}] ({:x}) [{}] {:6} {}\n",
-        hours,
-        minutes,
-        seconds,
-        miliseconds,
-        thread_id::get(),
-        plugin_name,
-        record.level(),
-        record.args()
-    );
+impl<'kvs> kv::VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(b) = value.to_bool() {
+            Field::Bool(b)
+        } else if let Some(n) = value.to_i64() {
+            Field::I64(n)
+        } else if let Some(n) = value.to_u64() {
+            Field::U64(n)
+        } else if let Some(n) = value.to_f64() {
+            Field::F64(n)
+        } else {
+            Field::Str(value.to_string())
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+impl kv::Source for Fields {
+    fn visit<'kvs>(&'kvs self, visitor: &mut kv::VisitSource<'kvs>) -> Result<(), kv::Error> {
+        for &(ref key, ref value) in &self.0 {
+            visitor.visit_pair(kv::Key::from_str(key), kv::ToValue::to_value(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs on the writer thread until the queue is closed and empty
+fn write_queued() {
+    let _running = health::running("logger");
+    let mut reported = 0;
+    while let Some(message) = LOGGER.queue.pop() {
+        match message {
+            Message::Record(queued) => LOGGER.write_record(
+                &RecordBuilder::new()
+                    .args(format_args!("{}", queued.message))
+                    .level(queued.level)
+                    .target(&queued.target)
+                    .module_path(queued.module_path.as_ref().map(|m| m.as_str()))
+                    .file(queued.file.as_ref().map(|f| f.as_str()))
+                    .line(queued.line)
+                    .key_values(&queued.fields)
+                    .build(),
+                &queued.plugin_name,
+                &queued.origin,
+                queued.from_slack_sink
+            ),
+            Message::Flush(done) => {
+                if let Some(ref mut file) = *LOGGER.file.lock().unwrap() {
+                    let _ = file.flush();
+                }
+                let _ = done.send(());
+            },
+        }
+
+        // Written directly, a queued warning could be dropped as well
+        let dropped = LOGGER.queue.dropped();
+        if dropped > reported {
+            LOGGER.write_record(
+                &RecordBuilder::new()
+                    .args(format_args!("The log queue was full, {} records have been dropped ({} in total)", dropped - reported, dropped))
+                    .level(Level::Warn)
+                    .target(module_path!())
+                    .build(),
+                HOST,
+                &Origin::now(LOGGER.start.elapsed()),
+                false
+            );
+            reported = dropped;
+        }
+    }
 }
 
 struct Logger {
     start: Instant,
     stdout: Mutex<Box<Write + Send>>,
     stderr: Mutex<Box<Write + Send>>,
-    file: Mutex<Option<Box<Write + Send>>>,
+    file: Mutex<Option<RotatingFile>>,
+    journald: Mutex<Option<Journald>>,
+    syslog: Mutex<Option<Syslog>>,
+    slack: Mutex<Option<(slack_sink::Settings, Sender<Alert>)>>,
+    history: Mutex<BTreeMap<String, VecDeque<Entry>>>,
+    filter: RwLock<Filter>,
+    /// The records waiting for the writer thread, so logging never waits on a slow sink
+    queue: BoundedQueue<Message>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// A record kept in memory, see `recent`
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub time: DateTime<Local>,
+    pub level: Level,
+    pub message: String,
+}
+
+/// The newest records of a plugin (`BEST-Bot` for the host itself) at `level` or more severe, oldest first
+pub fn recent(plugin_name: &str, level: LevelFilter, count: usize) -> Vec<Entry> {
+    let history = LOGGER.history.lock().unwrap();
+    let entries = match history.get(plugin_name) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+
+    let mut recent: Vec<Entry> = entries.iter().rev()
+        .filter(|entry| entry.level <= level)
+        .take(count)
+        .cloned()
+        .collect();
+    recent.reverse();
+    recent
 }
 
+/// The names of the plugins there are records in memory for
+pub fn history_names() -> Vec<String> {
+    LOGGER.history.lock().unwrap().keys().cloned().collect()
+}
+
+/// The name the host's own records are logged with
+static HOST: &'static str = "BEST-Bot";
+
 trait PluginLog: log::Log {
     fn log_plugin(&self, record: &Record, plugin_name: &str);
 }
 
 impl PluginLog for LOGGER {
+    /// Queues the record for the writer thread
     fn log_plugin(&self, record: &Record, plugin_name: &str) {
-        use log::Log;
-
-        if self.enabled(record.metadata()) {
-            if CONFIG.log().to_terminal() {
-                match record.level() {
-                    Level::Error => write(&mut *self.stderr.lock().unwrap(), self.start.elapsed(), record, plugin_name),
-                    Level::Warn => write(&mut *self.stderr.lock().unwrap(), self.start.elapsed(), record, plugin_name),
-                    Level::Info => write(&mut *self.stdout.lock().unwrap(), self.start.elapsed(), record, plugin_name),
-                    Level::Debug => write(&mut *self.stdout.lock().unwrap(), self.start.elapsed(), record, plugin_name),
-                    Level::Trace => write(&mut *self.stdout.lock().unwrap(), self.start.elapsed(), record, plugin_name),
-                }
+        let plugin = if plugin_name == HOST { None } else { Some(plugin_name) };
+
+        if self.filter.read().unwrap().enabled(record.level(), record.target(), plugin) {
+            metrics::inc("best_bot_log_records_total", &[("level", record.level().as_str()), ("plugin", plugin_name)]);
+
+            let mut fields = Fields(Vec::new());
+            let _ = record.key_values().visit(&mut fields);
+
+            self.queue.push(Message::Record(Queued {
+                level: record.level(),
+                target: record.target().to_string(),
+                module_path: record.module_path().map(|m| m.to_string()),
+                file: record.file().map(|f| f.to_string()),
+                line: record.line(),
+                message: record.args().to_string(),
+                fields: fields,
+                plugin_name: plugin_name.to_string(),
+                origin: Origin::now(self.start.elapsed()),
+                from_slack_sink: slack_sink::is_sink_thread(),
+            }));
+        }
+    }
+}
+
+impl Logger {
+    /// Writes a record to every sink, only called on the writer thread
+    fn write_record(&self, record: &Record, plugin_name: &str, origin: &Origin, from_slack_sink: bool) {
+        let log = CONFIG.get().log();
+        let stdout_color = log.color(io::stdout().is_terminal());
+        let stderr_color = log.color(io::stderr().is_terminal());
+
+        if log.to_terminal() {
+            match record.level() {
+                Level::Error => write(&mut *self.stderr.lock().unwrap(), origin, record, plugin_name, &log, stderr_color),
+                Level::Warn => write(&mut *self.stderr.lock().unwrap(), origin, record, plugin_name, &log, stderr_color),
+                Level::Info => write(&mut *self.stdout.lock().unwrap(), origin, record, plugin_name, &log, stdout_color),
+                Level::Debug => write(&mut *self.stdout.lock().unwrap(), origin, record, plugin_name, &log, stdout_color),
+                Level::Trace => write(&mut *self.stdout.lock().unwrap(), origin, record, plugin_name, &log, stdout_color),
             }
+        }
 
-            if CONFIG.log().to_file() {
-                let ref mut sink: Option<Box<Write + Send>> = *self.file.lock().unwrap();
-                if sink.is_some() {
-                    let sink = sink.as_mut().unwrap();
-                    match record.level() {
-                        Level::Error => write(sink, self.start.elapsed(), record, plugin_name),
-                        Level::Warn => write(sink, self.start.elapsed(), record, plugin_name),
-                        Level::Info => write(sink, self.start.elapsed(), record, plugin_name),
-                        Level::Debug => write(sink, self.start.elapsed(), record, plugin_name),
-                        Level::Trace => write(sink, self.start.elapsed(), record, plugin_name),
+        if log.to_file() {
+            let ref mut sink: Option<RotatingFile> = *self.file.lock().unwrap();
+            if sink.is_some() {
+                let sink = sink.as_mut().unwrap();
+                if REOPEN.swap(false, Ordering::SeqCst) {
+                    if let Err(e) = sink.reopen() {
+                        let _ = writeln!(io::stderr(), "Failed to reopen the log file ({})", e);
                     }
                 }
+                write(sink, origin, record, plugin_name, &log, false);
+            }
+        }
+
+        // A failing sink cannot log about itself, so the error goes directly to stderr
+        if let Some(ref journald) = *self.journald.lock().unwrap() {
+            if let Err(e) = journald.send(record, plugin_name, origin) {
+                let _ = writeln!(io::stderr(), "Failed to write to journald ({})", e);
+            }
+        }
+        if let Some(ref syslog) = *self.syslog.lock().unwrap() {
+            if let Err(e) = syslog.send(record, plugin_name, origin) {
+                let _ = writeln!(io::stderr(), "Failed to write to syslog ({})", e);
+            }
+        }
+
+        let history_size = log.history_size();
+        if history_size > 0 {
+            let mut history = self.history.lock().unwrap();
+            let entries = history.entry(plugin_name.to_string()).or_insert_with(VecDeque::new);
+            while entries.len() >= history_size {
+                entries.pop_front();
+            }
+            entries.push_back(Entry {
+                time: origin.time,
+                level: record.level(),
+                message: record.args().to_string(),
+            });
+        }
+
+        // Records from the thread posting to Slack are never posted, or a failing post would loop
+        if !from_slack_sink {
+            if let Some((ref settings, ref sender)) = *self.slack.lock().unwrap() {
+                if record.level() <= settings.level {
+                    let _ = sender.send(Alert {
+                        level: record.level(),
+                        plugin: plugin_name.to_string(),
+                        message: record.args().to_string(),
+                    });
+                }
             }
         }
     }
@@ -152,21 +499,21 @@ impl PluginLog for LOGGER {
 
 impl log::Log for LOGGER {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Trace
+        self.filter.read().unwrap().enabled(metadata.level(), metadata.target(), None)
     }
 
     fn log(&self, record: &Record) {
-        self.log_plugin(record, "BEST-Bot")
+        self.log_plugin(record, HOST)
     }
 
+    /// Waits until the records queued so far have been written
     fn flush(&self) {
-        if CONFIG.log().to_file() {
-            let ref mut sink: Option<Box<Write + Send>> = *self.file.lock().unwrap();
-            if sink.is_some() {
-                let sink = sink.as_mut().unwrap();
-                sink.flush();
-            }
+        if self.queue.is_closed() || self.writer.lock().unwrap().is_none() {
+            return;
         }
+        let (done, wait) = channel();
+        self.queue.push_always(Message::Flush(done));
+        let _ = wait.recv_timeout(Duration::from_secs(5));
     }
 }
 
@@ -9,69 +9,252 @@ extern crate log;
 extern crate simple_logging;
 
 extern crate template;
+extern crate best_bot_extension as extension;
 use template::slack::Error as sError;
 use template::channel_return::unbounded;
 
+mod abi;
+mod admin;
+
+mod cli;
+use cli::Command;
+
 mod config;
 use config::CONFIG;
 
+mod console;
+
+mod health;
+mod http;
+
 mod logger;
+mod log_file;
+mod log_filter;
+mod log_format;
+mod log_sink;
+
+mod metrics;
 
 mod misc;
+use misc::die;
+
+mod paths;
 
+mod plugin_api_c;
 mod plugin_manager;
 use plugin_manager::*;
 
+mod plugin_worker;
+
+mod plugin_bus;
+use plugin_bus::PluginBus;
+
+mod queue;
+
+mod recording;
+
+mod rtm;
+
+mod scaffold;
+
+mod slack_api;
 mod slack_bot;
+mod slack_sink;
 use slack_bot::MyHandler;
 use slack_bot::MyEventHandler;
 
-use std::process::exit;
+mod stats;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 fn main() {
-    let logger_sender = logger::init().expect("BEST-Bot failed at starting the logging module");
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            die(2);
+        },
+    };
+    cli::apply(&args);
 
-    let (plugin_sender, plugin_receiver) = unbounded::<template::plugin_api_v2::Channel>();
+    match args.command {
+        Command::Run if args.console => run_console(args.fixture),
+        Command::Run => run(args.record),
+        Command::CheckConfig => die(cli::check_config()),
+        Command::DumpConfig => die(cli::dump_config()),
+        Command::ListPlugins => die(cli::list_plugins()),
+        Command::Init => die(cli::init(args.force)),
+        Command::Replay(path) => run_replay(&path, args.fixture, args.speed),
+        Command::NewPlugin(name) => die(scaffold::new_plugin(&name, args.api.unwrap_or(2), args.force)),
+        Command::Help => println!("{}", cli::USAGE),
+    }
+}
 
-    // Init Slack Bot Handler
-    let mut handler = MyHandler::new(plugin_receiver);
+/// The wait before reconnecting to Slack, doubled after every failed attempt up to `RECONNECT_MAX`
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
 
-    // Init Plugin Manager
-    let mut plugin_manager = PluginManager::new(logger_sender, plugin_sender);
+/// Connects to Slack and runs the bot, reconnecting when the connection fails
+fn run(record: Option<PathBuf>) {
+    let mut handler = start();
 
-    info!("Looking for plugins in the folder {:?}", CONFIG.plugin_path());
-    for path in misc::find_plugins() {
-                plugin_manager.load_plugin(path);
+    if let Some(path) = record {
+        match recording::Recorder::create(&path) {
+            Ok(recorder) => {
+                info!("Recording the events from Slack to '{}'", path.display());
+                handler.record_to(recorder);
+            },
+            Err(e) => error!("Cannot record to '{}' ({})", path.display(), e),
+        }
     }
 
-    plugin_api_v1(&plugin_manager, &mut handler);
-    plugin_api_v2(&plugin_manager, &mut handler);
-
-
+    let mut backoff = RECONNECT_MIN;
     let _loop = true;
     while _loop {
+        let connected_at = Instant::now();
         match handler.init() {
-            Ok(_) => exit(0),
+            Ok(_) => die(0),
             Err(error) => {
+                health::set_connected(false);
+                stats::count("reconnects");
+                metrics::inc("best_bot_reconnects_total", &[]);
                 match error {
                     sError::WebSocket(ws_error) => warn!("WebSocket -> '{:?}'", ws_error),
                     _ => error!("Unknown -> '{:?}'", error)
                 }
             }
         }
+
+        // A connection that lasted starts over from the shortest wait
+        if connected_at.elapsed() > RECONNECT_MAX {
+            backoff = RECONNECT_MIN;
+        }
+        info!("Reconnecting to Slack in {}s", backoff.as_secs());
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(RECONNECT_MAX);
     }
 }
 
-/// Get a list of all the plugins using api v1 and the list of events they are subscript to and adds them to these events
-fn plugin_api_v1(plugin_manager: &PluginManager, handler: &mut MyHandler) {
-    for _plugin in plugin_manager.list_of_api_v1_plugins() {
-                handler.subscript_to_v1(_plugin);
+/// Runs the bot without Slack, driven by the lines typed in the terminal
+fn run_console(fixture: Option<PathBuf>) {
+    let fixture = read_fixture(fixture);
+    console::activate(&fixture);
+
+    let mut handler = start();
+    console::run(&mut handler, fixture);
+    logger::shutdown();
+}
+
+/// Sends a recording to the plugins without Slack, what they post is printed like in `--console` mode
+fn run_replay(path: &Path, fixture: Option<PathBuf>, speed: f64) {
+    let fixture = read_fixture(fixture);
+    console::activate(&fixture);
+
+    let mut handler = start();
+    console::add_conversations(&handler, &fixture);
+    handler.request_handler();
+
+    let code = match recording::replay(path, speed, |event| handler.handle_event(event)) {
+        Ok(replayed) => {
+            info!("Replayed {} events from '{}'", replayed, path.display());
+            0
+        },
+        Err(e) => {
+            error!("{}", e);
+            1
+        },
+    };
+    // The plugins handle the last events on their own threads
+    if !plugin_worker::wait_idle(Duration::from_secs(30)) {
+        warn!("Some plugins are still handling the replayed events, they are stopped");
     }
+    die(code);
 }
 
-/// Get a list of all the plugins using api v2 and the list of events they are subscript to and adds them to these events
-fn plugin_api_v2(plugin_manager: &PluginManager, handler: &mut MyHandler) {
+/// The fixture for the modes without Slack, the default one if no file is given
+fn read_fixture(path: Option<PathBuf>) -> console::Fixture {
+    match path {
+        Some(path) => match console::Fixture::read(&path) {
+            Ok(fixture) => fixture,
+            Err(e) => {
+                eprintln!("{}", e);
+                die(1);
+            },
+        },
+        None => console::Fixture::default(),
+    }
+}
+
+/// Starts the logger and the HTTP server, loads the plugins and returns the handler for their events
+fn start() -> MyHandler {
+    stats::start();
+    let logger_sender = logger::init().expect("BEST-Bot failed at starting the logging module");
+    misc::die_on_signal();
+
+    if let Some(address) = CONFIG.get().http_listen() {
+        if let Err(e) = http::start(&address) {
+            error!("The HTTP server could not be started ({})", e);
+        }
+    }
+
+    let (plugin_sender, plugin_receiver) = unbounded::<template::plugin_api_v2::Channel>();
+
+    // Init the bus the plugins use to talk to each other
+    let bus = PluginBus::new();
+    let (extension_host, extension_receiver) = extension::channel();
+    slack_bot::extension_handler(extension_receiver, bus.clone());
+
+    // Init Plugin Manager
+    let mut plugin_manager = PluginManager::new(logger_sender, plugin_sender, extension_host);
+
+    info!("Looking for plugins in the folder {:?}", CONFIG.get().plugin_path());
+    for path in misc::find_plugins() {
+                plugin_manager.load_plugin(path);
+    }
+
     for _plugin in plugin_manager.list_of_api_v2_plugins() {
-        handler.subscript_to_v2(_plugin);
+        if let Some(ref plugin) = _plugin.extension {
+            bus.register(plugin);
+        }
     }
+
+    // Shared with the admin commands, which can disable and reload plugins
+    let plugin_manager = Arc::new(RwLock::new(plugin_manager));
+    health::set_plugins(plugin_manager.clone());
+
+    // Init Slack Bot Handler
+    let handler = MyHandler::new(plugin_receiver, bus.clone(), plugin_manager.clone());
+
+    watch_config(plugin_manager);
+
+    handler
+}
+
+/// Reloads the config on SIGHUP or when the file changes, applies the log settings
+/// and tells the plugins subscribed to `ConfigChanged`
+fn watch_config(plugin_manager: Arc<RwLock<PluginManager>>) {
+    use template::Name;
+    use extension::{Event, EventSubscribe};
+
+    config::watch(move |old, new| {
+        logger::reconfigure(old, new);
+        plugin_worker::reconfigure();
+
+        for field in config::restart_required(old, new) {
+            warn!("The setting '{}' has changed, restart BEST-Bot for it to take effect", field);
+        }
+
+        let subscribers = plugin_manager.read().unwrap().subscribers_extension(|sub| match *sub {
+            EventSubscribe::ConfigChanged => true,
+        });
+        for plugin in subscribers {
+            let name = plugin.name();
+            plugin_worker::submit(&name, move || plugin.extension_event(Event::ConfigChanged));
+        }
+        info!("The config has been reloaded");
+    });
 }
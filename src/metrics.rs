@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in seconds
+static BUCKETS: &'static [f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Name, type and help text of every metric, in the order they are rendered
+static METRICS: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("best_bot_events_total", "counter", "Events received from the Slack RTM API by type"),
+    ("best_bot_plugin_dispatch_seconds", "histogram", "Time a plugin takes to handle an event"),
+    ("best_bot_plugin_panics_total", "counter", "Panics caught while a plugin handled an event"),
+    ("best_bot_plugin_queue_depth", "gauge", "Events waiting for a plugin's worker thread"),
+    ("best_bot_plugin_events_dropped_total", "counter", "Events dropped because a plugin's queue was full or it went over its timeout"),
+    ("best_bot_plugin_timeouts_total", "counter", "Events a plugin took longer than its timeout on"),
+    ("best_bot_reconnects_total", "counter", "Reconnects to the Slack RTM API"),
+    ("best_bot_slack_api_calls_total", "counter", "Calls to the Slack Web API by method and result"),
+    ("best_bot_slack_api_rate_limited_total", "counter", "Calls to the Slack Web API answered with 429 Too Many Requests"),
+    ("best_bot_plugin_requests_total", "counter", "Requests from the plugins by type"),
+    ("best_bot_conversation_cache_size", "gauge", "Conversations in the cache"),
+    ("best_bot_conversation_cache_lookups_total", "counter", "Lookups in the conversation cache by result (hit or miss)"),
+    ("best_bot_log_records_total", "counter", "Log records by level and plugin"),
+];
+
+type Key = (&'static str, Vec<(&'static str, String)>);
+
+struct Histogram {
+    /// Cumulative, `buckets[i]` counts the observations up to `BUCKETS[i]`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<Key, u64>,
+    gauges: BTreeMap<Key, f64>,
+    histograms: BTreeMap<Key, Histogram>,
+}
+
+lazy_static!(
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+);
+
+fn key(name: &'static str, labels: &[(&'static str, &str)]) -> Key {
+    (name, labels.iter().map(|&(label, value)| (label, value.to_string())).collect())
+}
+
+/// Adds one to a counter, e.g. `inc("best_bot_events_total", &[("type", "Message")])`
+pub fn inc(name: &'static str, labels: &[(&'static str, &str)]) {
+    *REGISTRY.lock().unwrap().counters.entry(key(name, labels)).or_insert(0) += 1;
+}
+
+pub fn set(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    REGISTRY.lock().unwrap().gauges.insert(key(name, labels), value);
+}
+
+/// Adds a duration to a histogram
+pub fn observe(name: &'static str, labels: &[(&'static str, &str)], duration: Duration) {
+    let seconds = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+
+    let mut registry = REGISTRY.lock().unwrap();
+    let histogram = registry.histograms.entry(key(name, labels)).or_insert_with(|| Histogram {
+        buckets: vec![0; BUCKETS.len()],
+        sum: 0.0,
+        count: 0,
+    });
+    for (i, bound) in BUCKETS.iter().enumerate() {
+        if seconds <= *bound {
+            histogram.buckets[i] += 1;
+        }
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+/// Every metric in the Prometheus text format
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    for &(name, kind, help) in METRICS {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+        for (&(_, ref labels), value) in registry.counters.iter().filter(|&(k, _)| k.0 == name) {
+            let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+        }
+        for (&(_, ref labels), value) in registry.gauges.iter().filter(|&(k, _)| k.0 == name) {
+            let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+        }
+        for (&(_, ref labels), histogram) in registry.histograms.iter().filter(|&(k, _)| k.0 == name) {
+            for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+                let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, Some(&bound.to_string())), count);
+            }
+            let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, Some("+Inf")), histogram.count);
+            let _ = writeln!(out, "{}_sum{} {}", name, format_labels(labels, None), histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, format_labels(labels, None), histogram.count);
+        }
+    }
+
+    out
+}
+
+/// `{label="value",...}`, with `le` added for a histogram bucket
+fn format_labels(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels.iter()
+        .map(|&(label, ref value)| format!("{}=\"{}\"", label, escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_format() {
+        inc("best_bot_plugin_panics_total", &[("plugin", "te\"st")]);
+        inc("best_bot_plugin_panics_total", &[("plugin", "te\"st")]);
+        observe("best_bot_plugin_dispatch_seconds", &[("plugin", "render")], Duration::from_millis(20));
+
+        let text = render();
+        assert!(text.contains("# TYPE best_bot_plugin_panics_total counter\n"));
+        assert!(text.contains("best_bot_plugin_panics_total{plugin=\"te\\\"st\"} 2\n"));
+        assert!(text.contains("best_bot_plugin_dispatch_seconds_bucket{plugin=\"render\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("best_bot_plugin_dispatch_seconds_bucket{plugin=\"render\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("best_bot_plugin_dispatch_seconds_bucket{plugin=\"render\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("best_bot_plugin_dispatch_seconds_count{plugin=\"render\"} 1\n"));
+    }
+}
@@ -1,12 +1,43 @@
+extern crate signal_hook;
+
+use self::signal_hook::consts::{SIGINT, SIGTERM};
+use self::signal_hook::iterator::Signals;
+
 use std::fs::create_dir_all;
 use std::path::PathBuf;
 use std::process::exit;
+use std::thread;
 
 use config::CONFIG;
+use logger;
+
+/// Writes the log records still waiting in the queue and exits. Use it instead of `exit`,
+/// otherwise the last records, often the reason for exiting, are lost
+pub fn die(code: i32) -> ! {
+    logger::shutdown();
+    exit(code)
+}
+
+/// Exits with `die` on SIGTERM and SIGINT, so the log is written before BEST-Bot stops
+pub fn die_on_signal() {
+    let mut signals = match Signals::new(&[SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Cannot listen for SIGTERM and SIGINT, the last log records can be lost when stopped ({})", e);
+            return;
+        },
+    };
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!("Stopping on signal {}", signal);
+            die(128 + signal);
+        }
+    });
+}
 
 /// Creates a list of all plugins present in the plugin folder
 pub fn find_plugins() -> Vec<PathBuf> {
-    let plugin_dir = CONFIG.plugin_path();
+    let plugin_dir = CONFIG.get().plugin_path();
     let mut plugins = Vec::new();
 
     // Checks if the path exist and that it is a folder
@@ -15,8 +46,8 @@ pub fn find_plugins() -> Vec<PathBuf> {
         // Get a list of all the files in the plugin folder
         match plugin_dir.read_dir() {
             Err(e) => {
-                error!("{:?}", e);
-                exit(1);
+                error!("Cannot read the folder '{}' ({})", plugin_dir.display(), e);
+                die(1);
             },
             Ok(paths) => {
                 for path in paths {
@@ -40,8 +71,10 @@ pub fn find_plugins() -> Vec<PathBuf> {
         }
     } else {
         error!("Cannot find the folder '{}' and will try to create it", plugin_dir.to_str().unwrap());
-        create_dir_all(&plugin_dir).expect(&format!("Cannot create the folder '{}'", plugin_dir.to_str().unwrap()));
-        exit(1);
+        if let Err(e) = create_dir_all(&plugin_dir) {
+            error!("Cannot create the folder '{}' ({})", plugin_dir.display(), e);
+        }
+        die(1);
     }
 
     plugins
@@ -0,0 +1,141 @@
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the folder BEST-Bot keeps its files in
+static APP_DIR: &'static str = "BEST-Bot";
+
+/// Relative paths starting with this folder were written relative to the home directory by
+/// earlier versions of BEST-Bot, they are still resolved that way
+static LEGACY_CONFIG_DIR: &'static str = ".config/BEST-Bot";
+
+/// What a path from the config file is resolved against
+#[derive(Clone, Debug)]
+pub struct Base {
+    /// The home directory, `None` for users without one (e.g. system service users)
+    pub home: Option<PathBuf>,
+    /// The folder the config file is in
+    pub config_dir: PathBuf,
+}
+
+impl Base {
+    /// The base for the config file in use
+    pub fn new(config_file: &Path) -> Base {
+        Base {
+            home: home_dir(),
+            config_dir: config_file.parent().map_or(PathBuf::from("."), |p| p.to_path_buf()),
+        }
+    }
+}
+
+/// `$HOME`, `None` if it is not set or not an absolute path.
+/// `std::env::home_dir` is deprecated, and falling back to the user database is not wanted for service users
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(PathBuf::from).filter(|home| home.is_absolute())
+}
+
+/// The folder the config file is in, when it is not given on the command line.
+/// `$XDG_CONFIG_HOME/BEST-Bot`, `~/.config/BEST-Bot` or `/etc/BEST-Bot` if there is no home directory
+pub fn default_config_dir() -> PathBuf {
+    config_dir_from(env::var_os("XDG_CONFIG_HOME").map(PathBuf::from), home_dir())
+}
+
+fn config_dir_from(xdg_config_home: Option<PathBuf>, home: Option<PathBuf>) -> PathBuf {
+    match (xdg_config_home, home) {
+        (Some(ref xdg), _) if xdg.is_absolute() => xdg.join(APP_DIR),
+        (_, Some(home)) => home.join(".config").join(APP_DIR),
+        _ => Path::new("/etc").join(APP_DIR),
+    }
+}
+
+/// Resolves a path from the config file:
+///
+/// * absolute paths are used as they are
+/// * `~` and `~/...` are relative to the home directory, or to the config folder if there is none
+/// * other relative paths are relative to the folder the config file is in
+pub fn resolve(raw: &str, base: &Base) -> PathBuf {
+    let path = Path::new(raw);
+
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+
+    let mut components = path.components();
+    if components.next() == Some(Component::Normal("~".as_ref())) {
+        let rest = components.as_path();
+        return match base.home {
+            Some(ref home) => home.join(rest),
+            None => {
+                warn!("There is no home directory, '{}' is resolved relative to '{}'", raw, base.config_dir.display());
+                base.config_dir.join(rest)
+            },
+        };
+    }
+
+    if let Some(ref home) = base.home {
+        if path.starts_with(LEGACY_CONFIG_DIR) {
+            return home.join(path);
+        }
+    }
+
+    base.config_dir.join(path)
+}
+
+/// Resolves `raw` if it is set, otherwise `default` inside the config folder
+pub fn resolve_or(raw: &Option<String>, default: &str, base: &Base) -> PathBuf {
+    match *raw {
+        Some(ref raw) => resolve(raw, base),
+        None => base.config_dir.join(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base(home: Option<&str>) -> Base {
+        Base {
+            home: home.map(PathBuf::from),
+            config_dir: PathBuf::from("/etc/best-bot"),
+        }
+    }
+
+    #[test]
+    fn absolute() {
+        assert_eq!(resolve("/opt/best-bot/libs", &base(Some("/home/bot"))), PathBuf::from("/opt/best-bot/libs"));
+        assert_eq!(resolve("/opt/best-bot/libs", &base(None)), PathBuf::from("/opt/best-bot/libs"));
+    }
+
+    #[test]
+    fn tilde() {
+        assert_eq!(resolve("~/libs", &base(Some("/home/bot"))), PathBuf::from("/home/bot/libs"));
+        assert_eq!(resolve("~", &base(Some("/home/bot"))), PathBuf::from("/home/bot"));
+        assert_eq!(resolve("~/libs", &base(None)), PathBuf::from("/etc/best-bot/libs"));
+        assert_eq!(resolve("~bot/libs", &base(Some("/home/bot"))), PathBuf::from("/etc/best-bot/~bot/libs"));
+    }
+
+    #[test]
+    fn config_relative() {
+        assert_eq!(resolve("libs", &base(Some("/home/bot"))), PathBuf::from("/etc/best-bot/libs"));
+        assert_eq!(resolve("./libs", &base(None)), PathBuf::from("/etc/best-bot/libs"));
+    }
+
+    #[test]
+    fn legacy_home_relative() {
+        assert_eq!(resolve(".config/BEST-Bot/libs", &base(Some("/home/bot"))), PathBuf::from("/home/bot/.config/BEST-Bot/libs"));
+        assert_eq!(resolve(".config/BEST-Bot/libs", &base(None)), PathBuf::from("/etc/best-bot/.config/BEST-Bot/libs"));
+    }
+
+    #[test]
+    fn default() {
+        assert_eq!(resolve_or(&None, "libs", &base(None)), PathBuf::from("/etc/best-bot/libs"));
+        assert_eq!(resolve_or(&Some(String::from("~/x")), "libs", &base(Some("/home/bot"))), PathBuf::from("/home/bot/x"));
+    }
+
+    #[test]
+    fn default_config_dir() {
+        assert_eq!(config_dir_from(Some(PathBuf::from("/xdg")), Some(PathBuf::from("/home/bot"))), PathBuf::from("/xdg/BEST-Bot"));
+        assert_eq!(config_dir_from(Some(PathBuf::from("relative")), Some(PathBuf::from("/home/bot"))), PathBuf::from("/home/bot/.config/BEST-Bot"));
+        assert_eq!(config_dir_from(None, Some(PathBuf::from("/home/bot"))), PathBuf::from("/home/bot/.config/BEST-Bot"));
+        assert_eq!(config_dir_from(None, None), PathBuf::from("/etc/BEST-Bot"));
+    }
+}
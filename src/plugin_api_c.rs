@@ -0,0 +1,533 @@
+//! Plugin api v3: plugins with a plain C interface, see `include/best_bot_plugin.h`.
+//!
+//! Unlike api v1 and v2 nothing but C types cross the library boundary, so the plugin can be
+//! written in any language and built with any compiler. A C plugin is wrapped in `CPlugin`,
+//! which looks like a plugin using api v2 and `extension` to the rest of BEST-Bot.
+
+use template::Name;
+use template::channel_return::SenderReturn;
+use template::logger::{Log, LoggerSender};
+use template::plugin_api_v2;
+use template::plugin_api_v2::{Event, EventSubscribe, Plugin, Reply, Request};
+
+use extension;
+use lib::Library;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const EVENT_STANDARD_MESSAGE: u32 = 1;
+pub const EVENT_CONFIG_CHANGED: u32 = 2;
+
+pub const LOG_ERROR: u32 = 1;
+pub const LOG_WARN: u32 = 2;
+pub const LOG_INFO: u32 = 3;
+pub const LOG_DEBUG: u32 = 4;
+pub const LOG_TRACE: u32 = 5;
+
+pub const REQUEST_API_TOKEN: u32 = 1;
+pub const REQUEST_ADMIN_API_TOKEN: u32 = 2;
+pub const REQUEST_WEBHOOKS_INCOMING_TOKEN: u32 = 3;
+pub const REQUEST_WEBHOOKS_OUTGOING_TOKEN: u32 = 4;
+pub const REQUEST_CHANNEL_NAME: u32 = 5;
+pub const REQUEST_CONFIG_PATH: u32 = 6;
+
+/// `BestBotMessage`, a field is NULL if Slack did not send it
+#[repr(C)]
+pub struct CMessage {
+    pub channel: *const c_char,
+    pub user: *const c_char,
+    pub text: *const c_char,
+    pub ts: *const c_char,
+    pub thread_ts: *const c_char,
+}
+
+/// `BestBotEvent`, `message` is only set for `EVENT_STANDARD_MESSAGE`
+#[repr(C)]
+pub struct CEvent {
+    pub kind: u32,
+    pub message: *const CMessage,
+}
+
+/// `BestBotHostApi`, the services BEST-Bot offers a plugin
+#[repr(C)]
+pub struct HostApi {
+    /// The size of this struct, fields are only ever added at the end
+    pub size: u32,
+    pub host: *const Host,
+    pub log: extern "C" fn(*const Host, u32, *const c_char),
+    pub request: extern "C" fn(*const Host, u32, *const c_char, *mut c_char, usize) -> i64,
+    pub post_message: extern "C" fn(*const Host, *const c_char, *const c_char) -> i32,
+}
+
+/// What the host functions work on, the plugin only sees it as an opaque `BestBotHost`
+pub struct Host {
+    name: String,
+    logger: Mutex<LoggerSender>,
+    sender: Mutex<plugin_api_v2::Sender>,
+    /// Set in `on_extension_load`, before the plugin is created
+    extension: Mutex<Option<extension::Sender>>,
+    /// Set once `best_bot_plugin_new` has returned. Requests are only answered after BEST-Bot has connected
+    /// to Slack, so one sent from `best_bot_plugin_new` would block the loading of the plugins
+    created: AtomicBool,
+}
+
+type NameFn = unsafe extern "C" fn() -> *const c_char;
+type SubscriptionsFn = unsafe extern "C" fn() -> u32;
+type NewFn = unsafe extern "C" fn(*const HostApi) -> *mut c_void;
+type EventFn = unsafe extern "C" fn(*mut c_void, *const CEvent);
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+
+/// A plugin using api v3. The `Library` it is opened from has to outlive it
+pub struct CPlugin {
+    name: String,
+    subscriptions: u32,
+    new: NewFn,
+    event: EventFn,
+    free: FreeFn,
+    /// What `best_bot_plugin_new` returned, NULL until `on_extension_load`
+    instance: *mut c_void,
+    /// Boxed so the pointers given to the plugin stay valid until it is freed
+    host: Option<(Box<Host>, Box<HostApi>)>,
+}
+
+// The header requires `best_bot_plugin_event` to be thread safe, it is called from several threads
+unsafe impl Send for CPlugin {}
+unsafe impl Sync for CPlugin {}
+
+impl CPlugin {
+    /// Looks up the functions every plugin using api v3 exports
+    pub fn open(lib: &Library) -> Result<CPlugin, String> {
+        unsafe {
+            let name: NameFn = *lib.get::<NameFn>(b"best_bot_plugin_name\0").map_err(|_| missing("best_bot_plugin_name"))?;
+            let subscriptions: SubscriptionsFn = *lib.get::<SubscriptionsFn>(b"best_bot_plugin_subscriptions\0").map_err(|_| missing("best_bot_plugin_subscriptions"))?;
+
+            let name = to_string(name()).ok_or(String::from("best_bot_plugin_name returned NULL"))?;
+            Ok(CPlugin {
+                name: name,
+                subscriptions: subscriptions(),
+                new: *lib.get::<NewFn>(b"best_bot_plugin_new\0").map_err(|_| missing("best_bot_plugin_new"))?,
+                event: *lib.get::<EventFn>(b"best_bot_plugin_event\0").map_err(|_| missing("best_bot_plugin_event"))?,
+                free: *lib.get::<FreeFn>(b"best_bot_plugin_free\0").map_err(|_| missing("best_bot_plugin_free"))?,
+                instance: ptr::null_mut(),
+                host: None,
+            })
+        }
+    }
+}
+
+impl Name for CPlugin {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl Plugin for CPlugin {
+    /// The plugin itself is created in `on_extension_load`, when every host function works
+    fn on_plugin_load(&mut self, logger: LoggerSender, sender: plugin_api_v2::Sender) {
+        let host = Box::new(Host {
+            name: self.name.clone(),
+            logger: Mutex::new(logger),
+            sender: Mutex::new(sender),
+            extension: Mutex::new(None),
+            created: AtomicBool::new(false),
+        });
+        let api = Box::new(HostApi {
+            size: ::std::mem::size_of::<HostApi>() as u32,
+            host: &*host,
+            log: host_log,
+            request: host_request,
+            post_message: host_post_message,
+        });
+        self.host = Some((host, api));
+    }
+
+    fn event_subscript(&self) -> Vec<EventSubscribe> {
+        let mut subscriptions = Vec::new();
+        if self.subscriptions & (1 << EVENT_STANDARD_MESSAGE) != 0 {
+            subscriptions.push(EventSubscribe::StandardMessage);
+        }
+        subscriptions
+    }
+
+    fn event(&self, event: Event) {
+        if self.instance.is_null() {
+            return;
+        }
+
+        match event {
+            Event::StandardMessage(message) => {
+                // Kept alive until the call returns
+                let fields: Vec<Option<CString>> = [&message.channel, &message.user, &message.text, &message.ts, &message.thread_ts]
+                    .iter()
+                    .map(|field| field.as_ref().map(|value| to_c_string(value)))
+                    .collect();
+                let pointer = |i: usize| fields[i].as_ref().map_or(ptr::null(), |value| value.as_ptr());
+
+                let c_message = CMessage {
+                    channel: pointer(0),
+                    user: pointer(1),
+                    text: pointer(2),
+                    ts: pointer(3),
+                    thread_ts: pointer(4),
+                };
+                let c_event = CEvent {
+                    kind: EVENT_STANDARD_MESSAGE,
+                    message: &c_message,
+                };
+                unsafe { (self.event)(self.instance, &c_event) };
+            },
+        }
+    }
+}
+
+impl extension::Plugin for CPlugin {
+    fn on_extension_load(&mut self, sender: extension::Sender) {
+        let instance = match self.host {
+            Some((ref host, ref api)) => {
+                *host.extension.lock().unwrap() = Some(sender);
+                let instance = unsafe { (self.new)(&**api) };
+                host.created.store(true, Ordering::SeqCst);
+                instance
+            },
+            None => return,
+        };
+        if instance.is_null() {
+            error!("The plugin '{}' returned NULL from best_bot_plugin_new, it gets no events", self.name);
+        }
+        self.instance = instance;
+    }
+
+    fn extension_subscript(&self) -> Vec<extension::EventSubscribe> {
+        let mut subscriptions = Vec::new();
+        if self.subscriptions & (1 << EVENT_CONFIG_CHANGED) != 0 {
+            subscriptions.push(extension::EventSubscribe::ConfigChanged);
+        }
+        subscriptions
+    }
+
+    fn extension_event(&self, event: extension::Event) {
+        if self.instance.is_null() {
+            return;
+        }
+
+        match event {
+            extension::Event::ConfigChanged => {
+                let c_event = CEvent {
+                    kind: EVENT_CONFIG_CHANGED,
+                    message: ptr::null(),
+                };
+                unsafe { (self.event)(self.instance, &c_event) };
+            },
+            // The bus is not part of the C api
+            extension::Event::BusMessage(_) => (),
+        }
+    }
+}
+
+impl Drop for CPlugin {
+    fn drop(&mut self) {
+        if !self.instance.is_null() {
+            unsafe { (self.free)(self.instance) };
+        }
+    }
+}
+
+fn missing(symbol: &str) -> String {
+    format!("the function '{}' is missing", symbol)
+}
+
+/// A C string can not contain NUL, they are left out
+fn to_c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap_or_default()
+}
+
+unsafe fn to_string(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+    }
+}
+
+/// Sends a request the way a plugin using api v2 does, so it is answered by the request handler
+fn send(host: &Host, request: Request) -> Option<Reply> {
+    let sender = match host.sender.lock() {
+        Ok(sender) => sender.clone(),
+        Err(_) => return None,
+    };
+    sender.send(request).ok()
+}
+
+extern "C" fn host_log(host: *const Host, level: u32, message: *const c_char) {
+    let (host, message) = match unsafe { (host.as_ref(), to_string(message)) } {
+        (Some(host), Some(message)) => (host, message),
+        _ => return,
+    };
+    let log = match level {
+        LOG_ERROR => Log::Error(message),
+        LOG_WARN => Log::Warn(message),
+        LOG_DEBUG => Log::Debug(message),
+        LOG_TRACE => Log::Trace(message),
+        _ => Log::Info(message),
+    };
+    if let Ok(logger) = host.logger.lock() {
+        let _ = logger.send((host.name.clone(), log));
+    }
+}
+
+/// Writes the answer to `buffer` with a NUL after it, cut to fit in `len` bytes.
+/// Returns the length of the whole answer, or -1 if there is none
+extern "C" fn host_request(host: *const Host, kind: u32, argument: *const c_char, buffer: *mut c_char, len: usize) -> i64 {
+    let host = match unsafe { host.as_ref() } {
+        Some(host) => host,
+        None => return -1,
+    };
+    if !host.created.load(Ordering::SeqCst) {
+        warn!("The plugin '{}' sent a request from best_bot_plugin_new, it is only answered in best_bot_plugin_event", host.name);
+        return -1;
+    }
+    let request = match kind {
+        REQUEST_API_TOKEN => Request::ApiToken,
+        REQUEST_ADMIN_API_TOKEN => Request::AdminApiToken,
+        REQUEST_WEBHOOKS_INCOMING_TOKEN => Request::WebHooksIncomingToken,
+        REQUEST_WEBHOOKS_OUTGOING_TOKEN => Request::WebHooksOutgoingToken,
+        REQUEST_CHANNEL_NAME => match unsafe { to_string(argument) } {
+            Some(id) => Request::GetChannelName(id),
+            None => return -1,
+        },
+        REQUEST_CONFIG_PATH => Request::ConfigPath,
+        _ => return -1,
+    };
+
+    let answer = match send(host, request) {
+        Some(Reply::ApiToken(value)) |
+        Some(Reply::AdminApiToken(value)) |
+        Some(Reply::WebHooksIncomingToken(value)) |
+        Some(Reply::WebHooksOutgoingToken(value)) |
+        Some(Reply::ChannelName(value)) => value,
+        Some(Reply::ConfigPath(path)) => path.to_string_lossy().into_owned(),
+        _ => return -1,
+    };
+
+    if !buffer.is_null() && len > 0 {
+        let copied = answer.len().min(len - 1);
+        unsafe {
+            ptr::copy_nonoverlapping(answer.as_ptr() as *const c_char, buffer, copied);
+            *buffer.offset(copied as isize) = 0;
+        }
+    }
+    answer.len() as i64
+}
+
+/// Returns 0 if the message was posted
+extern "C" fn host_post_message(host: *const Host, channel: *const c_char, text: *const c_char) -> i32 {
+    let (host, channel, text) = match unsafe { (host.as_ref(), to_string(channel), to_string(text)) } {
+        (Some(host), Some(channel), Some(text)) => (host, channel, text),
+        _ => return -1,
+    };
+    let sender = match host.extension.lock().ok().and_then(|sender| sender.clone()) {
+        Some(sender) => sender,
+        None => return -1,
+    };
+    match sender.send(extension::Request::PostMessage { channel: channel, text: text }) {
+        Ok(extension::Reply::MessagePosted(Ok(()))) => 0,
+        Ok(extension::Reply::MessagePosted(Err(e))) => {
+            warn!("The plugin '{}' could not post a message ({})", host.name, e);
+            -1
+        },
+        _ => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+    use plugin_manager::PluginManager;
+    use template::channel_return::{unbounded, ReceiverReturn};
+    use template::slack::api::MessageStandard;
+
+    use std::env;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Answers like a config with only the api token, every channel is named `general`
+    fn answering_sender() -> plugin_api_v2::Sender {
+        let (sender, receiver) = unbounded::<plugin_api_v2::Channel>();
+        thread::spawn(move || loop {
+            let result = ReceiverReturn::recv(&receiver, |request: Request| match request {
+                Request::ApiToken => Reply::ApiToken(String::from("xoxb-test")),
+                Request::GetChannelName(_) => Reply::ChannelName(String::from("general")),
+                _ => Reply::NotConfigured,
+            });
+            if result.is_err() {
+                break;
+            }
+        });
+        sender
+    }
+
+    fn host() -> Host {
+        let (logger, _) = mpsc::channel();
+        Host {
+            name: String::from("test"),
+            logger: Mutex::new(logger),
+            sender: Mutex::new(answering_sender()),
+            extension: Mutex::new(None),
+            created: AtomicBool::new(true),
+        }
+    }
+
+    /// Asks for the api token with a buffer of `len` bytes, filled with `x` before. Returns what `host_request` did with the buffer
+    fn request_token(len: usize) -> (i64, Vec<u8>) {
+        let host = host();
+        let mut buffer = vec![b'x' as c_char; len.max(1)];
+        let length = host_request(&host, REQUEST_API_TOKEN, ptr::null(), buffer.as_mut_ptr(), len);
+        (length, buffer.iter().map(|&c| c as u8).collect())
+    }
+
+    #[test]
+    fn cuts_the_answer_to_the_buffer() {
+        assert_eq!(request_token(0), (9, b"x".to_vec()));
+        assert_eq!(request_token(1), (9, b"\0".to_vec()));
+        assert_eq!(request_token(5), (9, b"xoxb\0".to_vec()));
+        assert_eq!(request_token(10), (9, b"xoxb-test\0".to_vec()));
+        assert_eq!(request_token(12), (9, b"xoxb-test\0xx".to_vec()));
+        assert_eq!(host_request(&host(), REQUEST_API_TOKEN, ptr::null(), ptr::null_mut(), 10), 9);
+    }
+
+    #[test]
+    fn fails_without_an_answer() {
+        let host = host();
+        let mut buffer = [0 as c_char; 16];
+        assert_eq!(host_request(&host, REQUEST_WEBHOOKS_INCOMING_TOKEN, ptr::null(), buffer.as_mut_ptr(), 16), -1);
+        assert_eq!(host_request(&host, REQUEST_CHANNEL_NAME, ptr::null(), buffer.as_mut_ptr(), 16), -1);
+        assert_eq!(host_request(&host, 99, ptr::null(), buffer.as_mut_ptr(), 16), -1);
+        assert_eq!(host_request(ptr::null(), REQUEST_API_TOKEN, ptr::null(), buffer.as_mut_ptr(), 16), -1);
+
+        // From `best_bot_plugin_new` it would wait for BEST-Bot to connect
+        host.created.store(false, Ordering::SeqCst);
+        assert_eq!(host_request(&host, REQUEST_API_TOKEN, ptr::null(), buffer.as_mut_ptr(), 16), -1);
+    }
+
+    #[test]
+    fn leaves_nul_out_of_c_strings() {
+        assert_eq!(to_c_string("a\0b").to_str(), Ok("ab"));
+        assert_eq!(to_c_string("").to_str(), Ok(""));
+    }
+
+    unsafe extern "C" fn no_new(_: *const HostApi) -> *mut c_void {
+        ptr::null_mut()
+    }
+
+    unsafe extern "C" fn no_event(_: *mut c_void, _: *const CEvent) {}
+
+    unsafe extern "C" fn no_free(_: *mut c_void) {}
+
+    /// How many api v2 subscriptions a plugin with `bits` has, and its `extension` subscriptions
+    fn subscribed(bits: u32) -> (usize, Vec<extension::EventSubscribe>) {
+        let plugin = CPlugin {
+            name: String::from("test"),
+            subscriptions: bits,
+            new: no_new,
+            event: no_event,
+            free: no_free,
+            instance: ptr::null_mut(),
+            host: None,
+        };
+        (plugin.event_subscript().len(), extension::Plugin::extension_subscript(&plugin))
+    }
+
+    #[test]
+    fn subscribes_by_bit() {
+        use extension::EventSubscribe::ConfigChanged;
+
+        assert_eq!(subscribed(0), (0, vec![]));
+        assert_eq!(subscribed(1 << EVENT_STANDARD_MESSAGE), (1, vec![]));
+        assert_eq!(subscribed(1 << EVENT_CONFIG_CHANGED), (0, vec![ConfigChanged]));
+        assert_eq!(subscribed((1 << EVENT_STANDARD_MESSAGE) | (1 << EVENT_CONFIG_CHANGED)), (1, vec![ConfigChanged]));
+        // The event number itself is not a subscription
+        assert_eq!(subscribed(EVENT_STANDARD_MESSAGE), (0, vec![]));
+    }
+
+    /// Builds `tests/fixtures/echo_plugin.c` with the C compiler in `CC`, or `cc`
+    fn build_fixture() -> PathBuf {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let library = env::temp_dir().join(format!("best-bot-c-echo-{}.so", ::std::process::id()));
+        let status = Command::new(env::var_os("CC").unwrap_or("cc".into()))
+            .args(&["-shared", "-fPIC", "-I"])
+            .arg(root.join("include"))
+            .arg("-o")
+            .arg(&library)
+            .arg(root.join("tests").join("fixtures").join("echo_plugin.c"))
+            .status()
+            .expect("a C compiler is needed for the C plugin fixture, set CC");
+        assert!(status.success(), "the C plugin fixture did not build");
+        library
+    }
+
+    /// A message posted by the fixture, with the plugin it came from
+    fn post(channel: &str, text: &str) -> (String, extension::Request) {
+        let request = extension::Request::PostMessage {
+            channel: channel.to_string(),
+            text: text.to_string(),
+        };
+        (String::from("c-echo"), request)
+    }
+
+    #[test]
+    fn loads_a_c_plugin() {
+        let library = build_fixture();
+        let (logger, logs) = mpsc::channel();
+        let (extension_host, extension_requests) = extension::channel();
+        let (posted, posts) = mpsc::channel();
+        thread::spawn(move || for envelope in extension_requests {
+            let _ = posted.send((envelope.plugin.clone(), envelope.request.clone()));
+            let _ = envelope.reply.send(extension::Reply::MessagePosted(Ok(())));
+        });
+        let mut manager = PluginManager::new(logger, answering_sender(), extension_host);
+        manager.load_plugin(&library);
+        assert!(manager.failed().is_empty(), "{:?}", manager.failed());
+        assert_eq!(posts.recv_timeout(Duration::from_secs(5)).ok(), Some(post("C0TEST", "created")));
+
+        {
+            let plugin = manager.extension_plugin("c-echo").expect("the plugin is not loaded");
+            assert_eq!(plugin.extension_subscript(), vec![extension::EventSubscribe::ConfigChanged]);
+
+            let message: MessageStandard = serde_json::from_str(r#"{"type":"message","channel":"C0TEST","user":"U0TEST","text":"hello","ts":"1.2"}"#).unwrap();
+            plugin.event(Event::StandardMessage(&message));
+            assert_eq!(posts.recv_timeout(Duration::from_secs(5)).ok(), Some(post("C0TEST", "hello in #general")));
+
+            plugin.extension_event(extension::Event::ConfigChanged);
+        }
+
+        let logs: Vec<(String, Log)> = logs.try_iter().collect();
+        let logged = |wanted: &str| logs.iter().any(|&(ref plugin, ref log)| match *log {
+            Log::Info(ref message) | Log::Error(ref message) => plugin == "c-echo" && message == wanted,
+            _ => false,
+        });
+        assert!(logged("config changed"));
+        assert!(!logged("request answered in best_bot_plugin_new"));
+
+        // Reloading opens the file again once the old plugin is gone
+        let old = manager.take("c-echo").unwrap();
+        let path = old.path().to_path_buf();
+        drop(old);
+        let loaded = manager.loader().open(path.clone());
+        manager.add(path, loaded).unwrap();
+        assert_eq!(posts.recv_timeout(Duration::from_secs(5)).ok(), Some(post("C0TEST", "created")));
+        assert!(manager.take("c-echo").is_ok());
+        assert!(manager.take("c-echo").is_err());
+
+        drop(manager);
+        let _ = ::std::fs::remove_file(library);
+    }
+}
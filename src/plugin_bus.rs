@@ -0,0 +1,203 @@
+extern crate serde_json;
+
+use extension::{BusMessage, Event, Plugin};
+use template::Name;
+
+use plugin_manager::PluginType;
+use plugin_worker;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+
+/// Publish/subscribe bus there plugins can use to share data with each other.
+/// Plugins publish and subscribe with the `extension::Sender` they get when they are loaded,
+/// and every message is delivered on the subscriber's own worker thread, see `plugin_worker`.
+pub struct PluginBus {
+    plugins: RwLock<BTreeMap<String, PluginType<Plugin>>>,
+    topics: RwLock<BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl PluginBus {
+    pub fn new() -> Arc<PluginBus> {
+        Arc::new(PluginBus {
+            plugins: RwLock::new(BTreeMap::new()),
+            topics: RwLock::new(BTreeMap::new()),
+        })
+    }
+
+    /// Makes a plugin reachable for messages on the bus
+    pub fn register(&self, plugin: &PluginType<Plugin>) {
+        self.plugins.write().unwrap().insert(plugin.name(), plugin.clone());
+    }
+
+    /// Makes a plugin unreachable, e.g. while it is disabled or reloaded. Its subscriptions are kept
+    pub fn unregister(&self, plugin: &str) {
+        self.plugins.write().unwrap().remove(plugin);
+    }
+
+    /// Subscribe the plugin to a topic
+    pub fn subscribe(&self, plugin: &str, topic: &str) {
+        debug!("bus: '{}' subscribed to '{}'", plugin, topic);
+        self.topics.write().unwrap()
+            .entry(topic.to_string())
+            .or_insert_with(BTreeSet::new)
+            .insert(plugin.to_string());
+    }
+
+    /// Unsubscribe the plugin from a topic
+    pub fn unsubscribe(&self, plugin: &str, topic: &str) {
+        debug!("bus: '{}' unsubscribed from '{}'", plugin, topic);
+        let mut topics = self.topics.write().unwrap();
+        let empty = match topics.get_mut(topic) {
+            Some(subscribers) => {
+                subscribers.remove(plugin);
+                subscribers.is_empty()
+            },
+            None => false,
+        };
+        if empty {
+            topics.remove(topic);
+        }
+    }
+
+    /// Queue the message for every subscriber of the topic, except the publisher itself.
+    /// Returns the number of subscribers the message was queued for.
+    pub fn publish(&self, message: BusMessage) -> usize {
+        let subscribers = self.route(&message);
+
+        debug!("bus: '{}' published '{}' to {} subscriber(s)", message.publisher, message.topic, subscribers.len());
+        trace!("bus: payload of '{}': {}", message.topic, serde_json::to_string(&message.payload).unwrap_or_default());
+
+        let message = Arc::new(message);
+        for &(ref name, ref plugin) in &subscribers {
+            let (subscriber, plugin, message) = (name.clone(), plugin.clone(), message.clone());
+            plugin_worker::submit(name, move || {
+                trace!("bus: delivering '{}' from '{}' to '{}'", message.topic, message.publisher, subscriber);
+                plugin.extension_event(Event::BusMessage(&message));
+                trace!("bus: delivered '{}' to '{}'", message.topic, subscriber);
+            });
+        }
+        subscribers.len()
+    }
+
+    /// The loaded subscribers the message goes to
+    fn route(&self, message: &BusMessage) -> Vec<(String, PluginType<Plugin>)> {
+        let names: Vec<String> = match self.topics.read().unwrap().get(&message.topic) {
+            Some(subscribers) => subscribers.iter().filter(|s| **s != message.publisher).cloned().collect(),
+            None => Vec::new(),
+        };
+
+        let plugins = self.plugins.read().unwrap();
+        names.into_iter()
+            .filter_map(|name| match plugins.get(&name) {
+                Some(plugin) => Some((name, plugin.clone())),
+                None => {
+                    warn!("bus: the subscriber '{}' of '{}' is not loaded or disabled", name, message.topic);
+                    None
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use extension;
+    use template::logger::LoggerSender;
+    use template::plugin_api_v2;
+
+    use super::serde_json::Value;
+
+    struct Subscriber(&'static str);
+
+    impl Name for Subscriber {
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    impl plugin_api_v2::Plugin for Subscriber {
+        fn on_plugin_load(&mut self, _: LoggerSender, _: plugin_api_v2::Sender) {}
+
+        fn event_subscript(&self) -> Vec<plugin_api_v2::EventSubscribe> {
+            Vec::new()
+        }
+
+        fn event(&self, _: plugin_api_v2::Event) {}
+    }
+
+    impl Plugin for Subscriber {
+        fn on_extension_load(&mut self, _: extension::Sender) {}
+
+        fn extension_subscript(&self) -> Vec<extension::EventSubscribe> {
+            Vec::new()
+        }
+
+        fn extension_event(&self, _: Event) {}
+    }
+
+    fn bus(names: &[&'static str]) -> Arc<PluginBus> {
+        let bus = PluginBus::new();
+        for name in names {
+            let plugin: Box<Plugin> = Box::new(Subscriber(name));
+            bus.register(&Arc::new(plugin));
+        }
+        bus
+    }
+
+    fn message(publisher: &str, topic: &str) -> BusMessage {
+        BusMessage {
+            topic: topic.to_string(),
+            publisher: publisher.to_string(),
+            payload: Value::Null,
+        }
+    }
+
+    fn routed(bus: &PluginBus, publisher: &str, topic: &str) -> Vec<String> {
+        bus.route(&message(publisher, topic)).into_iter().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn routes_to_the_subscribers_of_the_topic() {
+        let bus = bus(&["calendar", "reminder", "karma"]);
+        bus.subscribe("reminder", "events");
+        bus.subscribe("karma", "events");
+        bus.subscribe("karma", "scores");
+
+        assert_eq!(routed(&bus, "calendar", "events"), vec!["karma", "reminder"]);
+        assert_eq!(routed(&bus, "calendar", "scores"), vec!["karma"]);
+        assert!(routed(&bus, "calendar", "nothing").is_empty());
+
+        bus.unsubscribe("karma", "events");
+        assert_eq!(routed(&bus, "calendar", "events"), vec!["reminder"]);
+        bus.unsubscribe("reminder", "events");
+        assert!(routed(&bus, "calendar", "events").is_empty());
+    }
+
+    #[test]
+    fn does_not_send_a_message_back_to_its_publisher() {
+        let bus = bus(&["calendar", "reminder"]);
+        bus.subscribe("calendar", "events");
+        bus.subscribe("reminder", "events");
+
+        assert_eq!(routed(&bus, "calendar", "events"), vec!["reminder"]);
+        assert_eq!(routed(&bus, "reminder", "events"), vec!["calendar"]);
+    }
+
+    #[test]
+    fn counts_only_the_loaded_subscribers() {
+        let bus = bus(&["calendar", "reminder"]);
+        bus.subscribe("reminder", "events");
+        bus.subscribe("gone", "events");
+        assert_eq!(routed(&bus, "calendar", "events"), vec!["reminder"]);
+
+        bus.unregister("reminder");
+        // Nothing is queued, so this does not start a worker
+        assert_eq!(bus.publish(message("calendar", "events")), 0);
+
+        // The subscription is kept while a plugin is reloaded
+        bus.register(&Arc::new(Box::new(Subscriber("reminder")) as Box<Plugin>));
+        assert_eq!(routed(&bus, "calendar", "events"), vec!["reminder"]);
+    }
+}
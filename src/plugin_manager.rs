@@ -1,6 +1,9 @@
+use abi;
 use config::CONFIG;
 use lib::{Symbol, Library, Result};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use template::Name;
@@ -8,30 +11,111 @@ use template::logger::LoggerSender;
 use template::plugin_api_v1;
 use template::plugin_api_v2;
 
+use extension;
+
+use plugin_api_c::CPlugin;
+
 pub type RefCounter<T> = Arc<T>;
 pub type PluginType<V> = RefCounter<Box<V>>;
 pub type PluginFunc<V> = unsafe fn() -> *mut V;
 
 pub struct PluginApi<V: ?Sized> {
     pub plugin: PluginType<V>,
+    /// The file the plugin was loaded from, it is loaded from there again by `reload`
+    pub path: PathBuf,
+    /// A disabled plugin stays loaded but gets no events
+    pub enabled: bool,
+    /// The api version the plugin was loaded with, a C plugin (v3) is kept with the v2 plugins
+    pub api: u32,
+    /// The same plugin as `plugin` if it uses `extension` too, see `Extended`
+    pub extension: Option<PluginType<extension::Plugin>>,
+    /// The last field, so it is dropped after both `plugin` and `extension`: their code is in it
     pub loaded_libraries: Library,
 }
 
-pub struct PluginManager {
+/// A plugin using `extension`, seen as a plugin using api v2. Both share the same object
+struct Extended(PluginType<extension::Plugin>);
+
+impl Name for Extended {
+    fn name(&self) -> String {
+        self.0.name()
+    }
+}
+
+impl plugin_api_v2::Plugin for Extended {
+    /// Only called before the plugin is shared, see `Loader::load_plugin_extended`
+    fn on_plugin_load(&mut self, logger: LoggerSender, sender: plugin_api_v2::Sender) {
+        if let Some(plugin) = Arc::get_mut(&mut self.0) {
+            plugin.on_plugin_load(logger, sender);
+        }
+    }
+
+    fn event_subscript(&self) -> Vec<plugin_api_v2::EventSubscribe> {
+        self.0.event_subscript()
+    }
+
+    fn event(&self, event: plugin_api_v2::Event) {
+        self.0.event(event)
+    }
+}
+
+/// A summary of a loaded plugin, see `PluginManager::describe`
+pub struct PluginInfo {
+    pub name: String,
+    pub api: u32,
+    pub enabled: bool,
+    pub subscriptions: Vec<&'static str>,
+}
+
+/// A plugin loaded by `Loader::open`, that is not in a `PluginManager` yet
+pub enum Loaded {
+    V1(PluginApi<plugin_api_v1::Plugin>),
+    V2(PluginApi<plugin_api_v2::Plugin>),
+}
+
+impl Loaded {
+    pub fn path(&self) -> &Path {
+        match *self {
+            Loaded::V1(ref plugin) => &plugin.path,
+            Loaded::V2(ref plugin) => &plugin.path,
+        }
+    }
+}
+
+/// Opens the plugin libraries and calls their `on_plugin_load`. It is separate from the
+/// `PluginManager`, so a plugin can be loaded without holding the lock on the manager
+#[derive(Clone)]
+pub struct Loader {
     logger_sender: LoggerSender,
     plugin_sender: plugin_api_v2::Sender,
+    extension_host: extension::Host,
+}
+
+pub struct PluginManager {
+    loader: Loader,
     plugins_api_1: Vec<PluginApi<plugin_api_v1::Plugin>>,
     plugins_api_2: Vec<PluginApi<plugin_api_v2::Plugin>>,
+    /// The files that could not be loaded, with the reason
+    failed: BTreeMap<PathBuf, String>,
 }
 
-impl PluginManager {
-    /// Create Plugin Manager object
-    pub fn new(logger_sender: LoggerSender, plugin_sender: plugin_api_v2::Sender) -> PluginManager {
-        PluginManager {
-            logger_sender: logger_sender,
-            plugin_sender: plugin_sender,
-            plugins_api_1: Vec::new(),
-            plugins_api_2: Vec::new(),
+impl Loader {
+    /// Loads the plugin in the file, whatever api version it uses
+    pub fn open(&self, path: PathBuf) -> ::std::result::Result<Loaded, String> {
+        let lib = Library::new(&path).map_err(|e| format!("Unable to load the plugin '{}' ({:?})", path.display(), e))?;
+
+        let version = self.api_version(&lib);
+        match version {
+            // Api v3 is plain C, it does not depend on how the plugin is built
+            Ok(1) | Ok(2) => check_abi(&lib, &path)?,
+            _ => (),
+        }
+        match version {
+            Ok(1) => Ok(Loaded::V1(self.load_plugin_api_v1(lib, path))),
+            Ok(2) => Ok(Loaded::V2(self.load_plugin_api_v2(lib, path))),
+            Ok(3) => Ok(Loaded::V2(self.load_plugin_api_c(lib, path)?)),
+            Ok(version) => Err(format!("The api version {} is not supported", version)),
+            Err(e) => Err(format!("The plugin '{}' does not work. Error: '{:?}'", path.display(), e)),
         }
     }
 
@@ -45,7 +129,7 @@ impl PluginManager {
     }
 
     /// Loads plugins using API v1
-    fn load_plugin_api_v1(&mut self, lib: Library) {
+    fn load_plugin_api_v1(&self, lib: Library, path: PathBuf) -> PluginApi<plugin_api_v1::Plugin> {
         let mut obj = unsafe {
             // TODO: Make it so that a error is written to the log, instead of stopping the program if the function "load" is not present.
             let constructor: Symbol<PluginFunc<plugin_api_v1::Plugin>> = lib.get(b"load\0")
@@ -58,10 +142,10 @@ impl PluginManager {
         // makes the first call after api object is loaded. This is the only call there the object can be modified my the plugin itself
         (&mut obj).on_plugin_load(
             plugin_api_v1::Slack {
-                api_token: CONFIG.slack.api_token.clone(),
-                admin_api_token: CONFIG.slack.admin_api_token.clone()
+                api_token: CONFIG.get().slack.api_token.clone(),
+                admin_api_token: CONFIG.get().slack.admin_api_token.clone()
             },
-            CONFIG.plugin_config_path().clone()
+            CONFIG.get().plugin_config_path().clone()
         );
 
         // Both the api object pointer and the Library object are saved for later use.
@@ -69,17 +153,22 @@ impl PluginManager {
         // The Library object are saved to preserve it lifetime, because then the plugin is dropped
         // the plugin is unloaded and can no longer be called (BEST-Bot will crash if a call is make
         // to the plugin after the plugin has been unloaded).
-        self.plugins_api_1.push(PluginApi::<plugin_api_v1::Plugin> {
+        PluginApi::<plugin_api_v1::Plugin> {
             plugin: RefCounter::new(obj),
             loaded_libraries: lib,
-        });
-
-//        Ok(())
+            path: path,
+            enabled: true,
+            api: 1,
+            extension: None,
+        }
     }
 
-    /// Loads plugins using API v2
-    fn load_plugin_api_v2(&mut self, lib: Library) {
-        let mut obj = load::<plugin_api_v2::Plugin>(&lib);
+    /// Loads plugins using API v2, with `extension` if the plugin exports `load_extended`
+    fn load_plugin_api_v2(&self, lib: Library, path: PathBuf) -> PluginApi<plugin_api_v2::Plugin> {
+        if unsafe { lib.get::<PluginFunc<extension::Plugin>>(b"load_extended\0") }.is_ok() {
+            return self.load_plugin_extended(lib, path);
+        }
+        let mut obj = load::<plugin_api_v2::Plugin>(&lib, b"load\0");
 
         // makes the first call after api object is loaded. This is the only call there the object can be modified my the plugin itself
         (&mut obj).on_plugin_load(
@@ -92,12 +181,81 @@ impl PluginManager {
         // The Library object are saved to preserve it lifetime, because then the plugin is dropped
         // the plugin is unloaded and can no longer be called (BEST-Bot will crash if a call is make
         // to the plugin after the plugin has been unloaded).
-        self.plugins_api_2.push(PluginApi::<plugin_api_v2::Plugin> {
+        PluginApi::<plugin_api_v2::Plugin> {
             plugin: RefCounter::new(obj),
             loaded_libraries: lib,
-        });
+            path: path,
+            enabled: true,
+            api: 2,
+            extension: None,
+        }
+    }
+
+    /// Loads plugins using API v2 and `extension`
+    fn load_plugin_extended(&self, lib: Library, path: PathBuf) -> PluginApi<plugin_api_v2::Plugin> {
+        let mut obj = load::<extension::Plugin>(&lib, b"load_extended\0");
+
+        (&mut obj).on_plugin_load(
+            self.logger_sender.clone(),
+            self.plugin_sender.clone()
+        );
+        let sender = self.extension_host.sender(&obj.name());
+        (&mut obj).on_extension_load(sender);
+
+        let obj = RefCounter::new(obj);
+        PluginApi::<plugin_api_v2::Plugin> {
+            plugin: RefCounter::new(Box::new(Extended(obj.clone()))),
+            loaded_libraries: lib,
+            path: path,
+            enabled: true,
+            api: 2,
+            extension: Some(obj),
+        }
+    }
+
+    /// Loads plugins using the C api (v3), they are kept with the api v2 plugins
+    fn load_plugin_api_c(&self, lib: Library, path: PathBuf) -> ::std::result::Result<PluginApi<plugin_api_v2::Plugin>, String> {
+        let plugin = CPlugin::open(&lib).map_err(|e| format!("The plugin '{}' does not work ({})", path.display(), e))?;
+        let mut obj: Box<extension::Plugin> = Box::new(plugin);
+        info!("Loaded plugin v3: {}", obj.name());
+
+        (&mut obj).on_plugin_load(
+            self.logger_sender.clone(),
+            self.plugin_sender.clone()
+        );
+        let sender = self.extension_host.sender(&obj.name());
+        (&mut obj).on_extension_load(sender);
 
-//        Ok(())
+        let obj = RefCounter::new(obj);
+        Ok(PluginApi::<plugin_api_v2::Plugin> {
+            plugin: RefCounter::new(Box::new(Extended(obj.clone()))),
+            loaded_libraries: lib,
+            path: path,
+            enabled: true,
+            api: 3,
+            extension: Some(obj),
+        })
+    }
+}
+
+impl PluginManager {
+    /// Create Plugin Manager object
+    pub fn new(logger_sender: LoggerSender, plugin_sender: plugin_api_v2::Sender, extension_host: extension::Host) -> PluginManager {
+        PluginManager {
+            loader: Loader {
+                logger_sender: logger_sender,
+                plugin_sender: plugin_sender,
+                extension_host: extension_host,
+            },
+            plugins_api_1: Vec::new(),
+            plugins_api_2: Vec::new(),
+            failed: BTreeMap::new(),
+        }
+    }
+
+    /// Loads plugins the same way as this manager, without borrowing it
+    pub fn loader(&self) -> Loader {
+        self.loader.clone()
     }
 
     /// returns a list of all plugins using api v1
@@ -112,26 +270,172 @@ impl PluginManager {
 
     /// figure out what api version the plugin uses and then loads the plugin
     pub fn load_plugin<P: AsRef<OsStr>>(&mut self, filename: P) {
-                let lib = Library::new(filename.as_ref()).expect("Unable to load the plugin");
-
-                let version = self.api_version(&lib);
-        if version.is_ok() {
-            let version = version.unwrap();
-            match version {
-                1 => self.load_plugin_api_v1(lib),
-                2 => self.load_plugin_api_v2(lib),
-                _ => error!("The api version {} is not supported", &version),
+        if let Err(e) = self.open(PathBuf::from(filename.as_ref())) {
+            error!("{}", e);
+        }
+    }
+
+    /// Loads the plugin and remembers if it failed, see `failed`
+    fn open(&mut self, path: PathBuf) -> ::std::result::Result<(), String> {
+        let loaded = self.loader.open(path.clone());
+        self.add(path, loaded)
+    }
+
+    /// Adds a plugin from `Loader::open` to the loaded plugins, or remembers why it failed
+    pub fn add(&mut self, path: PathBuf, loaded: ::std::result::Result<Loaded, String>) -> ::std::result::Result<(), String> {
+        match loaded {
+            Ok(Loaded::V1(plugin)) => self.plugins_api_1.push(plugin),
+            Ok(Loaded::V2(plugin)) => self.plugins_api_2.push(plugin),
+            Err(e) => {
+                self.failed.insert(path, e.clone());
+                return Err(e);
+            },
+        }
+        self.failed.remove(&path);
+        Ok(())
+    }
+
+    /// Why the plugins that could not be loaded failed
+    pub fn failed(&self) -> Vec<String> {
+        self.failed.values().cloned().collect()
+    }
+
+    /// Name, api version, status and subscriptions of every loaded plugin
+    pub fn describe(&self) -> Vec<PluginInfo> {
+        let v1 = self.plugins_api_1.iter().map(|p| PluginInfo {
+            name: p.plugin.name(),
+            api: 1,
+            enabled: p.enabled,
+            subscriptions: p.plugin.event_subscript().iter().map(|sub| match *sub {
+                plugin_api_v1::EventSubscribe::StandardMessage => "StandardMessage",
+            }).collect(),
+        });
+        let v2 = self.plugins_api_2.iter().map(|p| PluginInfo {
+            name: p.plugin.name(),
+            api: p.api,
+            enabled: p.enabled,
+            subscriptions: p.plugin.event_subscript().iter().map(|sub| match *sub {
+                plugin_api_v2::EventSubscribe::StandardMessage => "StandardMessage",
+            }).chain(p.extension.iter().flat_map(|e| e.extension_subscript()).map(|sub| match sub {
+                extension::EventSubscribe::ConfigChanged => "ConfigChanged",
+            })).collect(),
+        });
+        v1.chain(v2).collect()
+    }
+
+    /// The enabled plugins using api v1 subscribed to an event, `wanted` picks the event
+    pub fn subscribers_v1<F: Fn(&plugin_api_v1::EventSubscribe) -> bool>(&self, wanted: F) -> Vec<PluginType<plugin_api_v1::Plugin>> {
+        self.plugins_api_1.iter()
+            .filter(|p| p.enabled && p.plugin.event_subscript().iter().any(|sub| wanted(sub)))
+            .map(|p| p.plugin.clone())
+            .collect()
+    }
+
+    /// The enabled plugins using api v2 subscribed to an event, `wanted` picks the event
+    pub fn subscribers_v2<F: Fn(&plugin_api_v2::EventSubscribe) -> bool>(&self, wanted: F) -> Vec<PluginType<plugin_api_v2::Plugin>> {
+        self.plugins_api_2.iter()
+            .filter(|p| p.enabled && p.plugin.event_subscript().iter().any(|sub| wanted(sub)))
+            .map(|p| p.plugin.clone())
+            .collect()
+    }
+
+    /// The enabled plugins using `extension` subscribed to an event, `wanted` picks the event
+    pub fn subscribers_extension<F: Fn(&extension::EventSubscribe) -> bool>(&self, wanted: F) -> Vec<PluginType<extension::Plugin>> {
+        self.plugins_api_2.iter()
+            .filter(|p| p.enabled)
+            .filter_map(|p| p.extension.clone())
+            .filter(|e| e.extension_subscript().iter().any(|sub| wanted(sub)))
+            .collect()
+    }
+
+    /// The plugin using `extension` with this name
+    pub fn extension_plugin(&self, name: &str) -> Option<PluginType<extension::Plugin>> {
+        self.plugins_api_2.iter().find(|p| p.plugin.name() == name).and_then(|p| p.extension.clone())
+    }
+
+    /// Turns the delivery of events to a plugin on or off
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> ::std::result::Result<(), String> {
+        if let Some(p) = self.plugins_api_1.iter_mut().find(|p| p.plugin.name() == name) {
+            p.enabled = enabled;
+            return Ok(());
+        }
+        if let Some(p) = self.plugins_api_2.iter_mut().find(|p| p.plugin.name() == name) {
+            p.enabled = enabled;
+            return Ok(());
+        }
+        Err(format!("there is no plugin named '{}'", name))
+    }
+
+    /// Takes the plugin out of the manager, its library is unloaded when the result is dropped.
+    /// Every other reference to the plugin (the bus, a running event) must be gone first
+    pub fn take(&mut self, name: &str) -> ::std::result::Result<Loaded, String> {
+        let in_use = || format!("the plugin '{}' is still in use, try again", name);
+
+        if let Some(i) = self.plugins_api_1.iter().position(|p| p.plugin.name() == name) {
+            if Arc::strong_count(&self.plugins_api_1[i].plugin) > 1 {
+                return Err(in_use());
             }
+            Ok(Loaded::V1(self.plugins_api_1.remove(i)))
+        } else if let Some(i) = self.plugins_api_2.iter().position(|p| p.plugin.name() == name) {
+            // An extended plugin is also referenced from its `Extended`
+            let plugin = &self.plugins_api_2[i];
+            if Arc::strong_count(&plugin.plugin) > 1 || plugin.extension.as_ref().map_or(false, |e| Arc::strong_count(e) > 2) {
+                return Err(in_use());
+            }
+            Ok(Loaded::V2(self.plugins_api_2.remove(i)))
         } else {
-            error!("The plugin '{}' does not work. Error: '{:?}'", &filename.as_ref().to_str().unwrap(), version.err().unwrap())
+            Err(format!("there is no plugin named '{}'", name))
         }
     }
 }
 
-fn load<T: ?Sized + Name>(lib: &Library) -> Box<T> {
+/// Refuses a plugin built with another `template`, `best_bot_extension`, rustc or target than BEST-Bot, see `abi`.
+/// Plugins from before the fingerprint are refused too, unless `allow_unchecked_plugins` is set
+fn check_abi(lib: &Library, path: &Path) -> ::std::result::Result<(), String> {
+    let theirs = unsafe {
+        let func: Symbol<unsafe extern "C" fn(*mut u8, usize) -> usize> = match lib.get(abi::SYMBOL) {
+            Ok(func) => func,
+            Err(_) => return unchecked(path, CONFIG.get().allow_unchecked_plugins()),
+        };
+        let mut buffer = vec![0u8; 512];
+        let len = func(buffer.as_mut_ptr(), buffer.len());
+        if len > buffer.len() {
+            buffer.resize(len, 0);
+            func(buffer.as_mut_ptr(), buffer.len());
+        }
+        buffer.truncate(len);
+        String::from_utf8_lossy(&buffer).into_owned()
+    };
+
+    let differences = abi::differences(&abi::fingerprint(), &theirs);
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "The plugin '{}' is not built like BEST-Bot and cannot be loaded safely, build it again with the same toolchain and template ({})",
+            path.display(),
+            differences.join("; ")
+        ))
+    }
+}
+
+/// What happens to a plugin without an ABI fingerprint
+fn unchecked(path: &Path, allowed: bool) -> ::std::result::Result<(), String> {
+    if allowed {
+        warn!("The plugin '{}' has no ABI fingerprint, it is loaded unchecked since allow_unchecked_plugins is set", path.display());
+        Ok(())
+    } else {
+        Err(format!(
+            "The plugin '{}' has no ABI fingerprint and cannot be loaded safely, build it again with this BEST-Bot's template or set allow_unchecked_plugins",
+            path.display()
+        ))
+    }
+}
+
+fn load<T: ?Sized + Name>(lib: &Library, symbol: &[u8]) -> Box<T> {
     let obj = unsafe {
         // TODO: Make it so that a error is written to the log, instead of stopping the program if the function "load" is not present.
-        let constructor: Symbol<PluginFunc<T>> = lib.get(b"load\0")
+        let constructor: Symbol<PluginFunc<T>> = lib.get(symbol)
             .expect("The `load` symbol wasn't found.");
         let boxed_raw = constructor();
         Box::from_raw(boxed_raw)
@@ -0,0 +1,413 @@
+use config::{CONFIG, Workers};
+use metrics;
+use queue::BoundedQueue;
+
+use std::collections::BTreeMap;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A call into a plugin, run on the plugin's worker thread
+type Job = Box<FnOnce() + Send>;
+
+/// What a worker thread is doing, for the watchdog
+#[derive(Default)]
+struct Busy {
+    /// When the current event was started, `None` while the worker waits
+    since: Option<Instant>,
+    /// The current event went over the timeout, the plugin's events are dropped until it is done
+    reported: bool,
+}
+
+struct Worker {
+    queue: Arc<BoundedQueue<Job>>,
+    busy: Arc<Mutex<Busy>>,
+    /// Events queued or being handled
+    pending: Arc<AtomicUsize>,
+}
+
+/// A worker thread for every plugin that has been sent an event, with the `workers` settings
+pub struct Pool {
+    settings: Mutex<Workers>,
+    /// By plugin name
+    workers: Mutex<BTreeMap<String, Worker>>,
+}
+
+lazy_static!(
+    /// The workers of the plugins, with the settings from the config
+    static ref POOL: Pool = Pool::new(CONFIG.get().workers());
+);
+
+static WATCHDOG: Once = Once::new();
+
+/// Queues `job` for the worker thread of `plugin`, see `Pool::submit`
+pub fn submit<F: FnOnce() + Send + 'static>(plugin: &str, job: F) {
+    WATCHDOG.call_once(|| {
+        thread::spawn(watchdog);
+    });
+    POOL.submit(plugin, job);
+}
+
+/// Applies the queue settings of a reloaded config to every worker
+pub fn reconfigure() {
+    POOL.configure(CONFIG.get().workers());
+}
+
+/// Waits up to `timeout` for every worker to handle the events queued so far, see `Pool::wait_idle`
+pub fn wait_idle(timeout: Duration) -> bool {
+    POOL.wait_idle(timeout)
+}
+
+impl Pool {
+    pub fn new(settings: Workers) -> Pool {
+        Pool {
+            settings: Mutex::new(settings),
+            workers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Queues `job` for the worker thread of `plugin`, the thread is started the first time.
+    /// The events of a plugin are handled in order, and a slow plugin only delays itself.
+    /// When its queue is full `workers.overflow` applies, with `block` this waits for the plugin,
+    /// which holds up the caller (the RTM thread) until there is room or the plugin's timeout
+    /// clears its queue. While the plugin is over its timeout the event is dropped
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, plugin: &str, job: F) {
+        let (queue, busy, pending) = {
+            let mut workers = self.workers.lock().unwrap();
+            if !workers.contains_key(plugin) {
+                match self.start(plugin) {
+                    Some(worker) => {
+                        workers.insert(plugin.to_string(), worker);
+                    },
+                    None => {
+                        // Tried again with the next event
+                        metrics::inc("best_bot_plugin_events_dropped_total", &[("plugin", plugin)]);
+                        return;
+                    },
+                }
+            }
+            let worker = &workers[plugin];
+            (worker.queue.clone(), worker.busy.clone(), worker.pending.clone())
+        };
+
+        if busy.lock().unwrap().reported {
+            metrics::inc("best_bot_plugin_events_dropped_total", &[("plugin", plugin)]);
+            debug!("The plugin '{}' is over its timeout, an event was dropped", plugin);
+            return;
+        }
+
+        pending.fetch_add(1, Ordering::SeqCst);
+        if !queue.push(Box::new(job)) {
+            // The queue holds as many events as before
+            pending.fetch_sub(1, Ordering::SeqCst);
+            metrics::inc("best_bot_plugin_events_dropped_total", &[("plugin", plugin)]);
+            debug!("The queue of the plugin '{}' is full, an event was dropped", plugin);
+        }
+        metrics::set("best_bot_plugin_queue_depth", &[("plugin", plugin)], queue.len() as f64);
+    }
+
+    /// Applies new queue settings and timeouts to every worker
+    pub fn configure(&self, settings: Workers) {
+        *self.settings.lock().unwrap() = settings.clone();
+        for worker in self.workers.lock().unwrap().values() {
+            worker.queue.configure(settings.queue_size(), settings.overflow());
+        }
+    }
+
+    /// Waits up to `timeout` for every worker to handle the events queued so far.
+    /// Returns false if one is still busy, used where there is no Slack connection to keep running
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        loop {
+            let idle = self.workers.lock().unwrap().values().all(|worker| worker.pending.load(Ordering::SeqCst) == 0);
+            if idle {
+                return true;
+            }
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Starts the worker thread of `plugin`, `None` if the thread cannot be started
+    fn start(&self, plugin: &str) -> Option<Worker> {
+        let settings = self.settings.lock().unwrap().clone();
+        let worker = Worker {
+            queue: Arc::new(BoundedQueue::new(settings.queue_size(), settings.overflow())),
+            busy: Arc::new(Mutex::new(Busy::default())),
+            pending: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let queue = worker.queue.clone();
+        let busy = worker.busy.clone();
+        let pending = worker.pending.clone();
+        let name = plugin.to_string();
+        let spawned = thread::Builder::new().name(format!("plugin:{}", plugin)).spawn(move || {
+            while let Some(job) = queue.pop() {
+                metrics::set("best_bot_plugin_queue_depth", &[("plugin", &name)], queue.len() as f64);
+                let start = Instant::now();
+                busy.lock().unwrap().since = Some(start);
+
+                dispatch(&name, job);
+
+                let reported = {
+                    let mut busy = busy.lock().unwrap();
+                    busy.since = None;
+                    ::std::mem::replace(&mut busy.reported, false)
+                };
+                pending.fetch_sub(1, Ordering::SeqCst);
+                if reported {
+                    info!("The plugin '{}' finished the slow event after {}s, its events are queued again", name, start.elapsed().as_secs());
+                }
+            }
+        });
+        if let Err(e) = spawned {
+            error!("Cannot start the worker thread of the plugin '{}', its event is dropped ({})", plugin, e);
+            return None;
+        }
+
+        debug!("Started the worker thread of the plugin '{}'", plugin);
+        Some(worker)
+    }
+
+    /// Stops queueing for every plugin that has taken longer than its timeout on the current event.
+    /// A plugin cannot be stopped, so the events waiting for it are dropped, and so are new ones until it is done
+    fn check_timeouts(&self) {
+        let settings = self.settings.lock().unwrap().clone();
+        for (name, worker) in self.workers.lock().unwrap().iter() {
+            let mut busy = worker.busy.lock().unwrap();
+            let timeout = settings.timeout(name);
+            match busy.since {
+                Some(since) if !busy.reported && since.elapsed() > timeout => {
+                    busy.reported = true;
+                    let dropped = worker.queue.clear();
+                    worker.pending.fetch_sub(dropped, Ordering::SeqCst);
+                    for _ in 0..dropped {
+                        metrics::inc("best_bot_plugin_events_dropped_total", &[("plugin", name)]);
+                    }
+                    metrics::inc("best_bot_plugin_timeouts_total", &[("plugin", name)]);
+                    metrics::set("best_bot_plugin_queue_depth", &[("plugin", name)], 0.0);
+                    warn!(
+                        "The plugin '{}' has been handling an event for more than {}s, its events are dropped until it is done ({} were waiting)",
+                        name,
+                        timeout.as_secs(),
+                        dropped
+                    );
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Drop for Pool {
+    /// Lets the worker threads end once they have handled their queues
+    fn drop(&mut self) {
+        for worker in self.workers.lock().unwrap().values() {
+            worker.queue.close();
+        }
+    }
+}
+
+/// Runs a plugin's event handler, timing it and catching a panic so one plugin cannot take the bot down
+fn dispatch(plugin: &str, job: Job) {
+    let start = Instant::now();
+    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+        metrics::inc("best_bot_plugin_panics_total", &[("plugin", plugin)]);
+        error!("The plugin '{}' panicked while handling an event", plugin);
+    }
+    metrics::observe("best_bot_plugin_dispatch_seconds", &[("plugin", plugin)], start.elapsed());
+}
+
+fn watchdog() {
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        POOL.check_timeouts();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn settings(queue_size: usize, overflow: &str) -> Workers {
+        Workers {
+            queue_size: Some(queue_size),
+            overflow: Some(overflow.to_string()),
+            timeout_seconds: Some(10),
+            timeouts: None,
+        }
+    }
+
+    /// Queues an event that takes until the returned sender is used, and waits for the worker to start on it
+    fn hold(pool: &Pool, plugin: &str) -> mpsc::Sender<()> {
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        pool.submit(plugin, move || {
+            started.send(()).unwrap();
+            wait_release.recv().ok();
+        });
+        wait_started.recv().unwrap();
+        release
+    }
+
+    fn record(pool: &Pool, plugin: &str, handled: &Arc<Mutex<Vec<usize>>>, event: usize) {
+        let handled = handled.clone();
+        pool.submit(plugin, move || handled.lock().unwrap().push(event));
+    }
+
+    fn pending(pool: &Pool, plugin: &str) -> usize {
+        pool.workers.lock().unwrap()[plugin].pending.load(Ordering::SeqCst)
+    }
+
+    fn dropped(plugin: &str) -> usize {
+        let prefix = format!("best_bot_plugin_events_dropped_total{{plugin=\"{}\"}} ", plugin);
+        metrics::render().lines()
+            .find(|line| line.starts_with(&prefix))
+            .map_or(0, |line| line[prefix.len()..].parse().unwrap())
+    }
+
+    #[test]
+    fn handles_events_in_order() {
+        let pool = Pool::new(settings(100, "block"));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        for event in 0..50 {
+            record(&pool, "worker-order", &handled, event);
+        }
+
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        assert_eq!(*handled.lock().unwrap(), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest_events() {
+        let pool = Pool::new(settings(2, "drop_oldest"));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let release = hold(&pool, "worker-drop-oldest");
+        for event in 1..4 {
+            record(&pool, "worker-drop-oldest", &handled, event);
+        }
+
+        // The held event and the two in the queue
+        assert_eq!(pending(&pool, "worker-drop-oldest"), 3);
+        assert!(!pool.wait_idle(Duration::from_millis(50)));
+        assert_eq!(dropped("worker-drop-oldest"), 1);
+
+        release.send(()).unwrap();
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        assert_eq!(*handled.lock().unwrap(), vec![2, 3]);
+    }
+
+    #[test]
+    fn sample_keeps_one_in_n() {
+        let pool = Pool::new(settings(1, "sample:2"));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let release = hold(&pool, "worker-sample");
+        for event in 1..6 {
+            record(&pool, "worker-sample", &handled, event);
+        }
+
+        // 1 fills the queue, 3 and 5 replace the event in it, 2 and 4 are dropped
+        assert_eq!(pending(&pool, "worker-sample"), 2);
+        assert_eq!(dropped("worker-sample"), 4);
+
+        release.send(()).unwrap();
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        assert_eq!(*handled.lock().unwrap(), vec![5]);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let pool = Arc::new(Pool::new(settings(1, "block")));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let release = hold(&pool, "worker-block");
+        record(&pool, "worker-block", &handled, 1);
+
+        let producer = {
+            let (pool, handled) = (pool.clone(), handled.clone());
+            thread::spawn(move || record(&pool, "worker-block", &handled, 2))
+        };
+        let start = Instant::now();
+        while pending(&pool, "worker-block") < 3 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // The waiting producer's event counts, so `wait_idle` waits for it too
+        assert_eq!(pending(&pool, "worker-block"), 3);
+        assert!(!pool.wait_idle(Duration::from_millis(50)));
+
+        release.send(()).unwrap();
+        producer.join().unwrap();
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        assert_eq!(*handled.lock().unwrap(), vec![1, 2]);
+        assert_eq!(dropped("worker-block"), 0);
+    }
+
+    #[test]
+    fn drops_events_while_over_the_timeout() {
+        let mut timeouts = BTreeMap::new();
+        timeouts.insert(String::from("worker-timeout"), 0);
+        let pool = Pool::new(Workers { timeouts: Some(timeouts), ..settings(10, "block") });
+        let handled = Arc::new(Mutex::new(Vec::new()));
+
+        let release = hold(&pool, "worker-timeout");
+        record(&pool, "worker-timeout", &handled, 1);
+        thread::sleep(Duration::from_millis(5));
+        pool.check_timeouts();
+
+        // The waiting event is dropped, and so is a new one
+        assert_eq!(pending(&pool, "worker-timeout"), 1);
+        record(&pool, "worker-timeout", &handled, 2);
+        assert_eq!(pending(&pool, "worker-timeout"), 1);
+        assert_eq!(dropped("worker-timeout"), 2);
+
+        release.send(()).unwrap();
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        record(&pool, "worker-timeout", &handled, 3);
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        assert_eq!(*handled.lock().unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn the_timeout_ends_a_blocked_submit() {
+        let mut timeouts = BTreeMap::new();
+        timeouts.insert(String::from("worker-block-timeout"), 0);
+        let pool = Arc::new(Pool::new(Workers { timeouts: Some(timeouts), ..settings(1, "block") }));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let release = hold(&pool, "worker-block-timeout");
+        record(&pool, "worker-block-timeout", &handled, 1);
+
+        let (submitted, wait_submitted) = mpsc::channel();
+        {
+            let (pool, handled) = (pool.clone(), handled.clone());
+            thread::spawn(move || {
+                record(&pool, "worker-block-timeout", &handled, 2);
+                submitted.send(()).unwrap();
+            });
+        }
+        assert!(wait_submitted.recv_timeout(Duration::from_millis(50)).is_err());
+
+        pool.check_timeouts();
+        assert!(wait_submitted.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(dropped("worker-block-timeout"), 1);
+
+        release.send(()).unwrap();
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        assert_eq!(*handled.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn survives_a_panicking_plugin() {
+        let pool = Pool::new(settings(10, "block"));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        pool.submit("worker-panic", || panic!("the plugin failed"));
+        record(&pool, "worker-panic", &handled, 1);
+
+        assert!(pool.wait_idle(Duration::from_secs(5)));
+        assert_eq!(*handled.lock().unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What happens when something is pushed to a full queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    /// The oldest item is dropped to make room
+    DropOldest,
+    /// The caller waits until there is room
+    Block,
+    /// One in every n items replaces the oldest item, the rest are dropped
+    Sample(usize),
+}
+
+impl Overflow {
+    /// Parses "drop_oldest", "block", "sample" or "sample:<n>"
+    pub fn parse(policy: &str) -> Result<Overflow, String> {
+        let policy = policy.trim().to_lowercase();
+        match policy.as_str() {
+            "drop_oldest" => Ok(Overflow::DropOldest),
+            "block" => Ok(Overflow::Block),
+            "sample" => Ok(Overflow::Sample(10)),
+            _ if policy.starts_with("sample:") => match policy["sample:".len()..].parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Overflow::Sample(n)),
+                _ => Err(format!("'{}' must be sample:<n> with n above 0", policy)),
+            },
+            _ => Err(format!("unknown overflow policy '{}', use drop_oldest, block or sample:<n>", policy)),
+        }
+    }
+}
+
+struct Inner<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    policy: Overflow,
+    overflowed: usize,
+    closed: bool,
+}
+
+/// A queue with a maximum length shared between threads, and a count of the items dropped
+/// because it was full
+pub struct BoundedQueue<T> {
+    inner: Mutex<Inner<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    dropped: AtomicUsize,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(capacity: usize, policy: Overflow) -> BoundedQueue<T> {
+        BoundedQueue {
+            inner: Mutex::new(Inner {
+                items: VecDeque::new(),
+                capacity: capacity.max(1),
+                policy: policy,
+                overflowed: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Changes the size and the overflow policy, items above the new size stay in the queue
+    pub fn configure(&self, capacity: usize, policy: Overflow) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity.max(1);
+        inner.policy = policy;
+        self.not_full.notify_all();
+    }
+
+    /// Adds an item following the overflow policy. Returns false if an item was dropped or the queue is closed
+    pub fn push(&self, item: T) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return false;
+        }
+
+        if inner.items.len() >= inner.capacity {
+            match inner.policy {
+                Overflow::Block => {
+                    while inner.items.len() >= inner.capacity && !inner.closed {
+                        inner = self.not_full.wait(inner).unwrap();
+                    }
+                    if inner.closed {
+                        return false;
+                    }
+                },
+                Overflow::DropOldest => {
+                    inner.items.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    inner.items.push_back(item);
+                    self.not_empty.notify_one();
+                    return false;
+                },
+                Overflow::Sample(n) => {
+                    inner.overflowed += 1;
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    if inner.overflowed % n == 0 {
+                        inner.items.pop_front();
+                        inner.items.push_back(item);
+                        self.not_empty.notify_one();
+                    }
+                    return false;
+                },
+            }
+        }
+
+        inner.items.push_back(item);
+        self.not_empty.notify_one();
+        true
+    }
+
+    /// Adds an item even if the queue is full, used for control messages that must not be lost
+    pub fn push_always(&self, item: T) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.items.push_back(item);
+        self.not_empty.notify_one();
+    }
+
+    /// Waits for the next item. Returns `None` once the queue is closed and empty
+    pub fn pop(&self) -> Option<T> {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if let Some(item) = inner.items.pop_front() {
+                self.not_full.notify_one();
+                return Some(item);
+            }
+            if inner.closed {
+                return None;
+            }
+            inner = self.not_empty.wait(inner).unwrap();
+        }
+    }
+
+    /// Removes every item in the queue, returns how many there were
+    pub fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let cleared = inner.items.len();
+        inner.items.clear();
+        self.not_full.notify_all();
+        cleared
+    }
+
+    /// No more items are accepted, the ones in the queue can still be popped
+    pub fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock().unwrap().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().items.len()
+    }
+
+    /// How many items have been dropped because the queue was full
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn drop_oldest() {
+        let queue = BoundedQueue::new(2, Overflow::DropOldest);
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(!queue.push(3));
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+
+        queue.close();
+        assert!(!queue.push(4));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn sample() {
+        let queue = BoundedQueue::new(1, Overflow::Sample(3));
+        for i in 0..7 {
+            queue.push(i);
+        }
+        // 0 fills the queue, of the 6 overflowing items 3 and 6 are kept
+        assert_eq!(queue.dropped(), 6);
+        assert_eq!(queue.pop(), Some(6));
+    }
+
+    #[test]
+    fn block() {
+        let queue = Arc::new(BoundedQueue::new(1, Overflow::Block));
+        queue.push(1);
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(2))
+        };
+        assert_eq!(queue.pop(), Some(1));
+        assert!(producer.join().unwrap());
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn clear() {
+        let queue = BoundedQueue::new(2, Overflow::Block);
+        queue.push(1);
+        queue.push(2);
+        assert_eq!(queue.clear(), 2);
+        assert!(queue.push(3));
+        assert_eq!(queue.pop(), Some(3));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Overflow::parse("drop_oldest"), Ok(Overflow::DropOldest));
+        assert_eq!(Overflow::parse("Block"), Ok(Overflow::Block));
+        assert_eq!(Overflow::parse("sample:5"), Ok(Overflow::Sample(5)));
+        assert!(Overflow::parse("sample:0").is_err());
+        assert!(Overflow::parse("newest").is_err());
+    }
+}
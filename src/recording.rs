@@ -0,0 +1,185 @@
+extern crate chrono;
+extern crate serde_json;
+
+use self::chrono::Local;
+use self::serde_json::{Map, Number, Value};
+
+use template::slack::Event;
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Writes the websocket frames from Slack to a file as they were received, one JSON object per line:
+/// `{"time": "...", "elapsed_ms": 1200, "frame": {...}}`. `elapsed_ms` is counted from the start
+/// of the recording, every recording appended to a file starts with a `{"time": "...", "session": "start"}` line.
+///
+/// The frames are replayed through `Event::from_json`, the same way they are read from Slack.
+pub struct Recorder {
+    file: File,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Appends to `path`, it is created if it does not exist
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        let mut recorder = Recorder {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+            start: Instant::now(),
+        };
+
+        let mut session = Map::new();
+        session.insert(String::from("time"), Value::String(Local::now().to_rfc3339()));
+        session.insert(String::from("session"), Value::String(String::from("start")));
+        recorder.write(Value::Object(session))?;
+        Ok(recorder)
+    }
+
+    /// Records a text frame from the RTM websocket
+    pub fn record(&mut self, frame: &str) -> io::Result<()> {
+        let elapsed = self.start.elapsed();
+        let elapsed_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+        let mut object = Map::new();
+        object.insert(String::from("time"), Value::String(Local::now().to_rfc3339()));
+        object.insert(String::from("elapsed_ms"), Value::Number(Number::from(elapsed_ms)));
+        // Kept as a string if it is not JSON, so nothing Slack sent is lost
+        let frame = serde_json::from_str(frame).unwrap_or_else(|_| Value::String(frame.to_string()));
+        object.insert(String::from("frame"), frame);
+        self.write(Value::Object(object))
+    }
+
+    fn write(&mut self, record: Value) -> io::Result<()> {
+        let mut line = record.to_string();
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()
+    }
+}
+
+/// Sends the frames recorded in `path` to `handle` again, as the events they are read as.
+/// With `speed` 1 the recorded pauses are kept, 10 replays ten times as fast and 0 does not wait at all.
+/// Returns the number of events replayed
+pub fn replay<F: FnMut(Event)>(path: &Path, speed: f64, mut handle: F) -> Result<usize, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open the recording '{}' ({})", path.display(), e))?;
+    let mut start = Instant::now();
+    let mut last_elapsed_ms = 0;
+    let mut replayed = 0;
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(&line).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+
+        // A new recording appended to the file counts its time from 0 again
+        if record.get("session").is_some() {
+            start = Instant::now();
+            last_elapsed_ms = 0;
+            continue;
+        }
+        let frame = match record.get("frame") {
+            Some(&Value::String(ref frame)) => frame.clone(),
+            Some(frame) => frame.to_string(),
+            None => return Err(format!("{}:{}: the line has no frame", path.display(), i + 1)),
+        };
+        let event = match Event::from_json(&frame) {
+            Ok(event) => event,
+            Err(e) => {
+                debug!("replay: skipping the frame on line {} ({:?})", i + 1, e);
+                continue;
+            },
+        };
+
+        if speed > 0.0 {
+            let elapsed_ms = record.get("elapsed_ms").and_then(|e| e.as_u64()).unwrap_or(0);
+            // Recordings from before the session line went back to 0 without one
+            if elapsed_ms < last_elapsed_ms {
+                start = Instant::now();
+            }
+            last_elapsed_ms = elapsed_ms;
+
+            let due = Duration::from_millis((elapsed_ms as f64 / speed) as u64);
+            let now = start.elapsed();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        handle(event);
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use template::slack::Message;
+
+    static MESSAGE: &'static str = r#"{"type":"message","channel":"C0TEST","user":"U0TEST","text":"!ping","ts":"1500000000.000100"}"#;
+
+    fn temp_path(name: &str) -> ::std::path::PathBuf {
+        let path = ::std::env::temp_dir().join(format!("best-bot-recording-{}-{}.jsonl", name, ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        path
+    }
+
+    /// The texts of the replayed messages and how many events were replayed, messages or not
+    fn texts(path: &Path, speed: f64) -> (Vec<String>, usize) {
+        let mut texts = Vec::new();
+        let mut handled = 0;
+        let replayed = replay(path, speed, |event| {
+            handled += 1;
+            if let Event::Message(message) = event {
+                if let Message::Standard(message) = *message {
+                    texts.push(message.text.unwrap_or_default());
+                }
+            }
+        }).unwrap();
+        assert_eq!(replayed, handled);
+        (texts, replayed)
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let path = temp_path("round-trip");
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            recorder.record(r#"{"type":"hello"}"#).unwrap();
+            recorder.record(MESSAGE).unwrap();
+            recorder.record("not json").unwrap();
+        }
+
+        // The hello is replayed too, the line that is not JSON is skipped
+        assert_eq!(texts(&path, 0.0), (vec![String::from("!ping")], 2));
+        let _ = ::std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn appended_recordings_start_their_own_session() {
+        let path = temp_path("append");
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            recorder.start -= Duration::from_millis(300);
+            recorder.record(MESSAGE).unwrap();
+        }
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            recorder.record(MESSAGE).unwrap();
+            recorder.start -= Duration::from_millis(300);
+            recorder.record(MESSAGE).unwrap();
+        }
+
+        // Timed from the start of the file the second recording would be over after 300 ms
+        let start = Instant::now();
+        assert_eq!(texts(&path, 1.0).0, vec!["!ping", "!ping", "!ping"]);
+        assert!(start.elapsed() >= Duration::from_millis(600));
+        let _ = ::std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,42 @@
+extern crate tungstenite;
+
+use self::tungstenite::Message;
+
+use template::api::rtm;
+
+use config::CONFIG;
+use slack_api;
+use slack_bot::MyHandler;
+
+/// Connects to the RTM websocket of the Slack in `slack.api_url` and sends its text frames through `handler`.
+/// slack-rs can only log in to Slack itself and does not hand on the frames, this does the same from `rtm.start` on.
+/// Returns when the websocket is closed
+pub fn run(handler: &mut MyHandler) -> Result<(), String> {
+    let client = slack_api::client()?;
+    let start = rtm::start(&client, &CONFIG.get().slack.api_token, &Default::default())
+        .map_err(|e| format!("rtm.start failed ({:?})", e))?;
+    let url = start.url.clone().ok_or(String::from("rtm.start returned no websocket url"))?;
+
+    let (mut socket, _) = tungstenite::connect(url.as_str()).map_err(|e| format!("cannot connect to '{}' ({})", url, e))?;
+    info!("Connected to the RTM websocket at '{}'", url);
+    handler.connected(&client, &start);
+
+    let result = loop {
+        let message = match socket.read() {
+            Ok(message) => message,
+            Err(tungstenite::Error::ConnectionClosed) => break Ok(()),
+            Err(e) => break Err(format!("the RTM websocket failed ({})", e)),
+        };
+        if message.is_close() {
+            break Ok(());
+        }
+        if !message.is_text() {
+            continue;
+        }
+
+        handler.receive(message.to_text().unwrap_or(""));
+    };
+
+    handler.disconnected();
+    result
+}
@@ -0,0 +1,447 @@
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where the extension crate and the test kit are in the BEST-Bot sources this binary is built from
+static EXTENSION_PATH: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/extension");
+static TEST_KIT_PATH: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-kit");
+static BEST_BOT_GIT: &'static str = "https://github.com/BEST-Aalborg/BEST-Bot";
+static TEMPLATE_GIT: &'static str = "https://github.com/BEST-Aalborg/BEST-Bot_template";
+
+/// The ABI fingerprint has to be built the same way in the plugins, see `abi`
+static ABI_RS: &'static str = include_str!("abi.rs");
+static BUILD_RS: &'static str = include_str!("../build.rs");
+
+static GITIGNORE: &'static str = "\
+/target
+Cargo.lock
+";
+
+static CARGO_TOML: &'static str = "\
+[package]
+name = \"{{name}}\"
+version = \"0.1.0\"
+
+[lib]
+# BEST-Bot loads the plugin with dlopen, so it has to be a dynamic library
+crate-type = [\"cdylib\"]
+
+[dependencies]
+template = { git = \"{{template}}\" }
+{{dependencies}}{{dev_dependencies}}";
+
+static LIB_V2: &'static str = r#"extern crate best_bot_extension as extension;
+extern crate template;
+#[cfg(test)]
+extern crate best_bot_test_kit;
+
+// The same as in BEST-Bot, do not change it
+#[allow(dead_code)]
+mod abi;
+
+use template::Name;
+use template::logger::{Log, LoggerSender};
+use template::plugin_api_v2;
+use template::plugin_api_v2::{Event, EventSubscribe, Plugin};
+
+use std::sync::Mutex;
+
+/// BEST-Bot checks this first, it has to be `extern "C"` and return 2 for this api
+#[no_mangle]
+pub extern "C" fn api_version() -> u32 {
+    2
+}
+
+/// BEST-Bot compares this with its own fingerprint before calling `load_extended`, see abi.rs
+#[no_mangle]
+pub unsafe extern "C" fn abi_fingerprint(buffer: *mut u8, len: usize) -> usize {
+    let fingerprint = abi::fingerprint();
+    let bytes = fingerprint.as_bytes();
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len().min(len));
+    bytes.len()
+}
+
+/// BEST-Bot calls this once to get the plugin. It is a Rust function, not `extern "C"`,
+/// and the box is given to BEST-Bot, which frees it when the plugin is unloaded.
+/// It is `load_extended` and not `load` since the plugin uses `best_bot_extension` to post messages
+#[no_mangle]
+pub fn load_extended() -> *mut extension::Plugin {
+    Box::into_raw(Box::new({{struct}}::default()))
+}
+
+/// The plugin is shared between BEST-Bot's threads, so the channels are kept behind a lock.
+/// Keep the `plugin_api_v2::Sender` from `on_plugin_load` too to ask for e.g. channel names
+#[derive(Default)]
+pub struct {{struct}} {
+    logger: Mutex<Option<LoggerSender>>,
+    sender: Mutex<Option<extension::Sender>>,
+}
+
+impl {{struct}} {
+    fn log(&self, log: Log) {
+        if let Some(ref logger) = *self.logger.lock().unwrap() {
+            let _ = logger.send((self.name(), log));
+        }
+    }
+
+    fn post_message(&self, channel: &str, text: &str) {
+        let sender = match *self.sender.lock().unwrap() {
+            Some(ref sender) => sender.clone(),
+            None => return,
+        };
+        let request = extension::Request::PostMessage {
+            channel: channel.to_string(),
+            text: text.to_string(),
+        };
+        match sender.send(request) {
+            Ok(extension::Reply::MessagePosted(Err(e))) => self.log(Log::Error(format!("Could not post in {} ({})", channel, e))),
+            Err(e) => self.log(Log::Error(format!("BEST-Bot did not answer a request ({})", e))),
+            _ => (),
+        }
+    }
+}
+
+impl Name for {{struct}} {
+    fn name(&self) -> String {
+        String::from("{{name}}")
+    }
+}
+
+impl Plugin for {{struct}} {
+    /// The only call where the plugin can change itself, together with `on_extension_load`
+    fn on_plugin_load(&mut self, logger: LoggerSender, _: plugin_api_v2::Sender) {
+        *self.logger.lock().unwrap() = Some(logger);
+        self.log(Log::Info(String::from("loaded")));
+    }
+
+    /// The events `event` is called with
+    fn event_subscript(&self) -> Vec<EventSubscribe> {
+        vec![EventSubscribe::StandardMessage]
+    }
+
+    fn event(&self, event: Event) {
+        match event {
+            Event::StandardMessage(message) => {
+                if let Some(ref channel) = message.channel {
+                    if let Some(reply) = answer(message.text.as_ref().map_or("", |t| t.as_str())) {
+                        self.log(Log::Debug(format!("answering in {}", channel)));
+                        self.post_message(channel, reply);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl extension::Plugin for {{struct}} {
+    /// Called right after `on_plugin_load`, the sender is kept for posting messages
+    fn on_extension_load(&mut self, sender: extension::Sender) {
+        *self.sender.lock().unwrap() = Some(sender);
+    }
+
+    /// The events `extension_event` is called with, messages on the bus come after `Request::Subscribe`
+    fn extension_subscript(&self) -> Vec<extension::EventSubscribe> {
+        Vec::new()
+    }
+
+    fn extension_event(&self, _: extension::Event) {}
+}
+
+/// A sample command: answers `!ping` with `pong`
+fn answer(text: &str) -> Option<&'static str> {
+    match text.trim() {
+        "!ping" => Some("pong"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use best_bot_test_kit::{Level, MockHost};
+
+    #[test]
+    fn answers_ping() {
+        let host = MockHost::new();
+        let mut plugin = {{struct}}::default();
+        host.load_extended(&mut plugin);
+
+        host.message(&plugin, "U0TEST", "C0TEST", "!ping");
+        host.message(&plugin, "U0TEST", "C0TEST", "something else");
+
+        assert_eq!(host.take_extension_requests(), vec![extension::Request::PostMessage {
+            channel: String::from("C0TEST"),
+            text: String::from("pong"),
+        }]);
+        assert!(host.logged(Level::Info, "loaded"));
+    }
+}
+"#;
+
+static LIB_V1: &'static str = r#"extern crate template;
+
+// The same as in BEST-Bot, do not change it
+#[allow(dead_code)]
+mod abi;
+
+use template::Name;
+use template::api::{chat, requests};
+use template::plugin_api_v1;
+use template::plugin_api_v1::{Event, EventSubscribe, Plugin};
+
+use std::path::PathBuf;
+
+/// BEST-Bot checks this first, it has to be `extern "C"` and return 1 for this api
+#[no_mangle]
+pub extern "C" fn api_version() -> u32 {
+    1
+}
+
+/// BEST-Bot compares this with its own fingerprint before calling `load`, see abi.rs
+#[no_mangle]
+pub unsafe extern "C" fn abi_fingerprint(buffer: *mut u8, len: usize) -> usize {
+    let fingerprint = abi::fingerprint();
+    let bytes = fingerprint.as_bytes();
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len().min(len));
+    bytes.len()
+}
+
+/// BEST-Bot calls this once to get the plugin. It is a Rust function, not `extern "C"`,
+/// and the box is given to BEST-Bot, which frees it when the plugin is unloaded
+#[no_mangle]
+pub fn load() -> *mut Plugin {
+    Box::into_raw(Box::new({{struct}}::default()))
+}
+
+#[derive(Default)]
+pub struct {{struct}} {
+    api_token: String,
+    config_path: PathBuf,
+}
+
+impl Name for {{struct}} {
+    fn name(&self) -> String {
+        String::from("{{name}}")
+    }
+}
+
+impl Plugin for {{struct}} {
+    /// The only call where the plugin can change itself, the token and config folder are kept for later
+    fn on_plugin_load(&mut self, slack: plugin_api_v1::Slack, config_path: PathBuf) {
+        self.api_token = slack.api_token;
+        self.config_path = config_path;
+    }
+
+    /// The events `event` is called with
+    fn event_subscript(&self) -> Vec<EventSubscribe> {
+        vec![EventSubscribe::StandardMessage]
+    }
+
+    fn event(&self, event: Event) {
+        match event {
+            Event::StandardMessage(message) => {
+                if let Some(ref channel) = message.channel {
+                    if let Some(reply) = answer(message.text.as_ref().map_or("", |t| t.as_str())) {
+                        self.post_message(channel, reply);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl {{struct}} {
+    /// Api v1 plugins post with the Slack Web API and `api_token`
+    fn post_message(&self, channel: &str, text: &str) {
+        if let Ok(client) = requests::default_client() {
+            let _ = chat::post_message(&client, &self.api_token, &chat::PostMessageRequest {
+                channel: channel,
+                text: text,
+                ..Default::default()
+            });
+        }
+    }
+}
+
+/// A sample command: answers `!ping` with `pong`
+fn answer(text: &str) -> Option<&'static str> {
+    match text.trim() {
+        "!ping" => Some("pong"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribes_to_messages() {
+        let plugin = {{struct}}::default();
+        assert_eq!(plugin.name(), "{{name}}");
+        assert_eq!(plugin.event_subscript().len(), 1);
+    }
+
+    #[test]
+    fn answers_ping() {
+        assert_eq!(answer("!ping"), Some("pong"));
+        assert_eq!(answer(" !ping\n"), Some("pong"));
+        assert_eq!(answer("something else"), None);
+    }
+}
+"#;
+
+/// Writes a new plugin crate to the folder `name` in the current folder
+pub fn new_plugin(name: &str, api: u32, force: bool) -> i32 {
+    match create(Path::new(name), name, api, force) {
+        Ok(dir) => {
+            println!("Created the plugin '{}' in '{}'", name, dir.display());
+            println!("Build it with `cargo build --release` and copy target/release/lib{}.so to the plugin folder", name.replace('-', "_"));
+            0
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+fn create(dir: &Path, name: &str, api: u32, force: bool) -> Result<PathBuf, String> {
+    check_name(name)?;
+    if dir.exists() && !force {
+        return Err(format!("'{}' already exists, use --force to overwrite the files in it", dir.display()));
+    }
+
+    let lib = match api {
+        1 => LIB_V1,
+        2 => LIB_V2,
+        _ => return Err(format!("There is no plugin api v{}, use v1 or v2", api)),
+    };
+    let (dependencies, dev_dependencies) = if api == 2 {
+        (
+            format!("best-bot-extension = {}\n", dependency(EXTENSION_PATH)),
+            format!("\n[dev-dependencies]\nbest-bot-test-kit = {}\n", dependency(TEST_KIT_PATH)),
+        )
+    } else {
+        (String::new(), String::new())
+    };
+    let cargo_toml = CARGO_TOML
+        .replace("{{template}}", TEMPLATE_GIT)
+        .replace("{{dependencies}}", &dependencies)
+        .replace("{{dev_dependencies}}", &dev_dependencies);
+
+    fs::create_dir_all(dir.join("src")).map_err(|e| format!("Cannot create '{}' ({})", dir.display(), e))?;
+    write(&dir.join("Cargo.toml"), &fill(&cargo_toml, name))?;
+    write(&dir.join(".gitignore"), GITIGNORE)?;
+    write(&dir.join("build.rs"), BUILD_RS)?;
+    write(&dir.join("src").join("abi.rs"), ABI_RS)?;
+    write(&dir.join("src").join("lib.rs"), &fill(lib, name))?;
+    Ok(dir.to_path_buf())
+}
+
+/// A path dependency if the BEST-Bot sources are still where this binary was built, otherwise git
+fn dependency(path: &str) -> String {
+    if Path::new(path).is_dir() {
+        format!("{{ path = {:?} }}", path)
+    } else {
+        format!("{{ git = {:?} }}", BEST_BOT_GIT)
+    }
+}
+
+fn fill(template: &str, name: &str) -> String {
+    template.replace("{{name}}", name).replace("{{struct}}", &struct_name(name))
+}
+
+fn write(path: &Path, content: &str) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|e| format!("Cannot write '{}' ({})", path.display(), e))
+}
+
+/// A plugin name is also its crate name: lower case letters, digits, - and _, starting with a letter
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.chars().next().map_or(false, |c| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a plugin name, use lower case letters, digits, - and _, e.g. karma-bot", name))
+    }
+}
+
+/// `karma-bot` becomes `KarmaBot`
+fn struct_name(name: &str) -> String {
+    name.split(|c| c == '-' || c == '_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn names() {
+        assert_eq!(struct_name("karma"), "Karma");
+        assert_eq!(struct_name("karma-bot_2"), "KarmaBot2");
+        assert!(check_name("karma-bot").is_ok());
+        assert!(check_name("Karma").is_err());
+        assert!(check_name("2karma").is_err());
+        assert!(check_name("../karma").is_err());
+    }
+
+    #[test]
+    fn writes_the_crate() {
+        let dir = env::temp_dir().join(format!("best-bot-scaffold-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        create(&dir, "echo-bot", 2, false).unwrap();
+        let lib = fs::read_to_string(dir.join("src").join("lib.rs")).unwrap();
+        assert!(lib.contains("pub struct EchoBot {"));
+        assert!(lib.contains("String::from(\"echo-bot\")"));
+        assert!(!lib.contains("{{"));
+        assert!(lib.contains("pub unsafe extern \"C\" fn abi_fingerprint("));
+        assert!(dir.join("build.rs").is_file());
+        assert!(dir.join("src").join("abi.rs").is_file());
+        let cargo_toml = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(cargo_toml.contains("name = \"echo-bot\""));
+        assert!(cargo_toml.contains("best-bot-extension = { path = "));
+        assert!(cargo_toml.contains("best-bot-test-kit = { path = "));
+        assert!(lib.contains("pub fn load_extended() -> *mut extension::Plugin {"));
+
+        assert!(create(&dir, "echo-bot", 2, false).is_err());
+        assert!(create(&dir, "echo-bot", 3, true).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Builds and tests a new plugin of each api with cargo, it needs the network the first time.
+    /// Run it with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn new_plugins_pass_their_tests() {
+        let dir = env::temp_dir().join(format!("best-bot-scaffold-cargo-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cargo = env::var_os("CARGO").unwrap_or("cargo".into());
+
+        for &(name, api) in &[("ping-v1", 1), ("ping-v2", 2)] {
+            let crate_dir = create(&dir.join(name), name, api, false).unwrap();
+            let output = ::std::process::Command::new(&cargo)
+                .arg("test")
+                .current_dir(&crate_dir)
+                .env("CARGO_TARGET_DIR", dir.join("target"))
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "cargo test failed for api v{}:\n{}", api, String::from_utf8_lossy(&output.stderr));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
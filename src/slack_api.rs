@@ -0,0 +1,49 @@
+use template::api::requests;
+use template::api::requests::SlackWebRequestSender;
+
+use config::CONFIG;
+
+use std::borrow::Borrow;
+
+/// What the Slack Web API methods are called on, see `get_slack_url_for_method` in slack_api
+static SLACK_API: &'static str = "https://slack.com/api/";
+
+/// A Slack Web API client, sending to `slack.api_url` instead of Slack when it is set.
+/// It works with every method in `template::api`, so a fake Slack only has to serve the methods
+pub struct ApiClient {
+    client: requests::Client,
+    base: Option<String>,
+}
+
+/// A client for the Web API in the config
+pub fn client() -> Result<ApiClient, String> {
+    Ok(ApiClient {
+        client: requests::default_client().map_err(|e| format!("{:?}", e))?,
+        base: CONFIG.get().slack_api_url(),
+    })
+}
+
+impl ApiClient {
+    /// The url of a Web API method, e.g. `rtm.start`
+    pub fn url(&self, method_url: &str) -> String {
+        match self.base {
+            Some(ref base) if method_url.starts_with(SLACK_API) => format!("{}/{}", base, &method_url[SLACK_API.len()..]),
+            _ => method_url.to_string(),
+        }
+    }
+}
+
+impl SlackWebRequestSender for ApiClient {
+    type Error = <requests::Client as SlackWebRequestSender>::Error;
+
+    fn send<I, K, V, S>(&self, method_url: S, params: I) -> Result<String, Self::Error>
+    where
+        I: IntoIterator + Send,
+        K: AsRef<str>,
+        V: AsRef<str>,
+        I::Item: Borrow<(K, V)>,
+        S: AsRef<str> + Send,
+    {
+        self.client.send(self.url(method_url.as_ref()), params)
+    }
+}
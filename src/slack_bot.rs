@@ -2,76 +2,233 @@ extern crate serde_json;
 
 use template::slack;
 use template::slack::{Event, RtmClient, Message};
-use template::api::{Channel, conversations, requests, requests::Client};
+use template::api::{Channel, chat, conversations};
+use template::api::rtm::StartResponse;
 use template::plugin_api_v1;
 use template::plugin_api_v2;
+use template::Name;
 
+use extension;
+
+use admin;
 use config::CONFIG;
+use console;
+use health;
+use metrics;
+use rtm;
+use slack_api;
+use slack_api::ApiClient;
+use stats;
+
+use plugin_manager::PluginManager;
+use plugin_worker;
 
-use plugin_manager::PluginType;
-use plugin_manager::PluginApi;
+use plugin_bus::PluginBus;
+
+use recording::Recorder;
 
 use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
 use std::thread;
 use std::sync::{Arc, RwLock};
 
-enum PluginVersion {
-    _1(PluginType<plugin_api_v1::Plugin>),
-    _2(PluginType<plugin_api_v2::Plugin>),
+/// Posts a message to a channel with the bot's token
+pub fn post_message(channel: &str, text: &str) -> Result<(), String> {
+    if console::is_active() {
+        console::print_post(channel, text);
+        return Ok(());
+    }
+
+    let client = slack_api::client()?;
+    let result = chat::post_message(&client, &CONFIG.get().slack.api_token, &chat::PostMessageRequest {
+        channel: channel,
+        text: text,
+        ..Default::default()
+    });
+    api_call("chat.postMessage", &result);
+    result.map(|_| ()).map_err(|e| format!("{:?}", e))
+}
+
+/// Looks up a conversation and stores it in `conversation`
+pub fn fetch_conversation(client: &ApiClient, conversation: &RwLock<BTreeMap<String, Channel>>, id: &str) -> Result<(), String> {
+    if console::is_active() {
+        return Err(String::from("Slack cannot be reached in console mode, add the channel to the fixture"));
+    }
+
+    let result = conversations::info(client, &CONFIG.get().slack.api_token, &conversations::InfoRequest {
+        channel: id,
+        include_locale: None,
+    });
+    api_call("conversations.info", &result);
+    let result = result.map_err(|e| format!("{:?}", e))?;
+
+    let channel = result.channel.ok_or(format!("Slack returned no channel for '{}'", id))?;
+    let mut conversation = conversation.write().unwrap();
+    conversation.insert(id.to_string(), channel);
+    metrics::set("best_bot_conversation_cache_size", &[], conversation.len() as f64);
+    Ok(())
+}
+
+/// Counts a call to the Slack Web API, and if Slack answered 429 Too Many Requests
+fn api_call<T, E: RateLimited>(method: &'static str, result: &Result<T, E>) {
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    metrics::inc("best_bot_slack_api_calls_total", &[("method", method), ("result", outcome)]);
+
+    if let Err(ref e) = *result {
+        if e.rate_limited() {
+            metrics::inc("best_bot_slack_api_rate_limited_total", &[("method", method)]);
+        }
+    }
+}
+
+/// The error of a Web API method that can tell if Slack refused the call because of its rate limits.
+/// With 429 Too Many Requests Slack answers the error `ratelimited`, which slack-api does not know
+trait RateLimited {
+    fn rate_limited(&self) -> bool;
+}
+
+impl<E: Error> RateLimited for chat::PostMessageError<E> {
+    fn rate_limited(&self) -> bool {
+        match *self {
+            chat::PostMessageError::RateLimited => true,
+            chat::PostMessageError::Unknown(ref error) => error == "ratelimited",
+            _ => false,
+        }
+    }
+}
+
+impl<E: Error> RateLimited for conversations::InfoError<E> {
+    fn rate_limited(&self) -> bool {
+        match *self {
+            conversations::InfoError::Unknown(ref error) => error == "ratelimited",
+            _ => false,
+        }
+    }
+}
+
+/// The name of the event's variant, e.g. `Message` or `UserTyping`
+pub fn event_type(event: &Event) -> String {
+    let mut name = VariantName(String::new());
+    let _ = write!(name, "{:?}", event);
+    name.0
+}
+
+/// Keeps the start of a `Debug` output up to the first character that cannot be in a name,
+/// and stops the formatting there so the payload of the event is never formatted
+struct VariantName(String);
+
+impl fmt::Write for VariantName {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match s.find(|c: char| !c.is_alphanumeric() && c != '_') {
+            Some(end) => {
+                self.0.push_str(&s[..end]);
+                Err(fmt::Error)
+            },
+            None => {
+                self.0.push_str(s);
+                Ok(())
+            },
+        }
+    }
+}
+
+/// The name of a request from a plugin, for the metrics
+fn request_name(request: &plugin_api_v2::Request) -> &'static str {
+    use template::plugin_api_v2::Request;
+
+    match *request {
+        Request::ApiToken => "ApiToken",
+        Request::AdminApiToken => "AdminApiToken",
+        Request::WebHooksIncomingToken => "WebHooksIncomingToken",
+        Request::WebHooksOutgoingToken => "WebHooksOutgoingToken",
+        Request::GetChannelName(_) => "GetChannelName",
+        Request::ConfigPath => "ConfigPath",
+    }
+}
+
+/// The name of a request from a plugin using `extension`, for the metrics
+fn extension_request_name(request: &extension::Request) -> &'static str {
+    use extension::Request;
+
+    match *request {
+        Request::Publish { .. } => "Publish",
+        Request::Subscribe(_) => "Subscribe",
+        Request::Unsubscribe(_) => "Unsubscribe",
+        Request::PostMessage { .. } => "PostMessage",
+    }
+}
+
+/// Answers the `extension` requests of every plugin from its own thread.
+/// It is started before the plugins are loaded, so they can send requests from `on_extension_load`
+pub fn extension_handler(receiver: extension::Receiver, bus: Arc<PluginBus>) {
+    use extension::{BusMessage, Reply, Request};
+
+    thread::spawn(move || {
+        let _running = health::running("extension_handler");
+        for envelope in receiver {
+            let plugin = envelope.plugin;
+            metrics::inc("best_bot_plugin_requests_total", &[("type", extension_request_name(&envelope.request))]);
+            let reply = match envelope.request {
+                Request::Publish { topic, payload } => Reply::Published(bus.publish(BusMessage {
+                    topic: topic,
+                    publisher: plugin,
+                    payload: payload,
+                })),
+                // The plugin is the one the sender was made for, a plugin cannot subscribe another one
+                Request::Subscribe(topic) => {
+                    bus.subscribe(&plugin, &topic);
+                    Reply::Subscribed
+                },
+                Request::Unsubscribe(topic) => {
+                    bus.unsubscribe(&plugin, &topic);
+                    Reply::Unsubscribed
+                },
+                Request::PostMessage { channel, text } => Reply::MessagePosted(post_message(&channel, &text)),
+            };
+            // The plugin may have stopped waiting, e.g. while it is unloaded
+            let _ = envelope.reply.send(reply);
+        }
+    });
 }
 
 pub struct MyHandler {
     thread: Option<thread::JoinHandle<()>>,
     receiver: plugin_api_v2::Receiver,
+    bus: Arc<PluginBus>,
+    plugins: Arc<RwLock<PluginManager>>,
     conversation: Arc<RwLock<BTreeMap<String, Channel>>>,
-    message_standard: Vec<PluginVersion>,
+    recorder: Option<Recorder>,
 }
 
 pub trait MyEventHandler: slack::EventHandler {
-    fn new(receiver: plugin_api_v2::Receiver) -> MyHandler;
+    fn new(receiver: plugin_api_v2::Receiver, bus: Arc<PluginBus>, plugins: Arc<RwLock<PluginManager>>) -> MyHandler;
     fn init(&mut self) -> Result<(), slack::Error>;
-    fn subscript_to_v1(&mut self, plugin: &PluginApi<plugin_api_v1::Plugin>);
-    fn subscript_to_v2(&mut self, plugin: &PluginApi<plugin_api_v2::Plugin>);
     fn request_handler(&mut self);
-    fn conversation_info(&mut self, client: &Client, id: &str);
+    fn conversation_info(&mut self, client: &ApiClient, id: &str) -> bool;
 }
 
 #[allow(unused_variables)]
 impl MyEventHandler for MyHandler {
-    fn new(receiver: plugin_api_v2::Receiver) -> MyHandler {
+    fn new(receiver: plugin_api_v2::Receiver, bus: Arc<PluginBus>, plugins: Arc<RwLock<PluginManager>>) -> MyHandler {
         MyHandler {
             thread: None,
             receiver: receiver,
+            bus: bus,
+            plugins: plugins,
             conversation: Arc::new(RwLock::new(BTreeMap::new())),
-            message_standard: Vec::new(),
+            recorder: None,
         }
     }
 
-    /// Login to Slack and start The Slack Bot
+    /// Login to Slack and start The Slack Bot, or to the Slack in `slack.api_url` if it is set.
+    /// slack-rs only hands on parsed events, so `rtm` reads the websocket when the frames are recorded
     fn init(&mut self) -> Result<(), slack::Error> {
-        RtmClient::login_and_run::<MyHandler>(&CONFIG.slack.api_token, self)
-    }
-
-    /// Add a reference of the plugin to the different events lists that to plugin subscripted to
-    fn subscript_to_v1(&mut self, plugin: &PluginApi<plugin_api_v1::Plugin>) {
-                for sub in plugin.plugin.event_subscript() {
-            match sub {
-                plugin_api_v1::EventSubscribe::StandardMessage => {
-                    self.message_standard.push(PluginVersion::_1(plugin.plugin.clone()));
-                }
-            }
-        }
-    }
-
-    /// Add a reference of the plugin to the different events lists that to plugin subscripted to
-    fn subscript_to_v2(&mut self, plugin: &PluginApi<plugin_api_v2::Plugin>) {
-        for sub in plugin.plugin.event_subscript() {
-            match sub {
-                plugin_api_v2::EventSubscribe::StandardMessage => {
-                    self.message_standard.push(PluginVersion::_2(plugin.plugin.clone()));
-                }
-            }
+        if CONFIG.get().slack_api_url().is_some() || self.recorder.is_some() {
+            return rtm::run(self).map_err(slack::Error::Internal);
         }
+        RtmClient::login_and_run::<MyHandler>(&CONFIG.get().slack.api_token, self)
     }
 
     fn request_handler(&mut self) {
@@ -84,38 +241,43 @@ impl MyEventHandler for MyHandler {
             let conversation = self.conversation.clone();
 
             self.thread = Some(thread::spawn(move || {
-                let client = requests::default_client().unwrap();;
+                let _running = health::running("request_handler");
+                let client = slack_api::client().unwrap();
                 loop {
                     let result = ReceiverReturn::recv(&receiver, |request: Request| {
+                        metrics::inc("best_bot_plugin_requests_total", &[("type", request_name(&request))]);
                         match request {
-                            Request::ApiToken => Reply::ApiToken(CONFIG.slack.api_token.clone()),
-                            Request::AdminApiToken => Reply::AdminApiToken(CONFIG.slack.admin_api_token.clone()),
+                            Request::ApiToken => Reply::ApiToken(CONFIG.get().slack.api_token.clone()),
+                            Request::AdminApiToken => Reply::AdminApiToken(CONFIG.get().slack.admin_api_token.clone()),
 
-                            Request::WebHooksIncomingToken => CONFIG.slack.incoming_webhooks_token.as_ref().map_or(
+                            Request::WebHooksIncomingToken => CONFIG.get().slack.incoming_webhooks_token.as_ref().map_or(
                                 Reply::NotConfigured,
                                 |token| Reply::WebHooksIncomingToken(token.clone())
                             ),
-                            Request::WebHooksOutgoingToken => CONFIG.slack.outgoing_webhooks_token.as_ref().map_or(
+                            Request::WebHooksOutgoingToken => CONFIG.get().slack.outgoing_webhooks_token.as_ref().map_or(
                                 Reply::NotConfigured,
                                 |token| Reply::WebHooksOutgoingToken(token.clone())
                             ),
 
                             Request::GetChannelName(id) => {
-                                match conversation.read().unwrap().get(&id) {
-                                    Some(c) => Reply::ChannelName(c.name.clone().unwrap_or(String::new())),
+                                let cached = conversation.read().unwrap().get(&id).map(|c| c.name.clone().unwrap_or(String::new()));
+                                match cached {
+                                    Some(name) => {
+                                        metrics::inc("best_bot_conversation_cache_lookups_total", &[("result", "hit")]);
+                                        Reply::ChannelName(name)
+                                    },
                                     None => {
-                                        let result = conversations::info(&client, &CONFIG.slack.api_token, &conversations::InfoRequest {
-                                            channel: &id,
-                                            include_locale: None,
-                                        }).unwrap();
-                                        let r = Reply::ChannelName(result.channel.as_ref().unwrap().name.clone().unwrap_or(String::new()));
-                                        conversation.write().unwrap().insert(id.to_string(), result.channel.unwrap());
-                                        r
+                                        metrics::inc("best_bot_conversation_cache_lookups_total", &[("result", "miss")]);
+                                        if let Err(e) = fetch_conversation(&client, &conversation, &id) {
+                                            error!("Failed to look up the conversation '{}' ({})", id, e);
+                                        }
+                                        let name = conversation.read().unwrap().get(&id).and_then(|c| c.name.clone());
+                                        Reply::ChannelName(name.unwrap_or(String::new()))
                                     },
                                 }
                             },
 
-                            Request::ConfigPath => Reply::ConfigPath(CONFIG.plugin_config_path()),
+                            Request::ConfigPath => Reply::ConfigPath(CONFIG.get().plugin_config_path()),
                         }
                     });
                     if result.is_err() {
@@ -126,32 +288,64 @@ impl MyEventHandler for MyHandler {
         }
     }
 
-    fn conversation_info(&mut self, client: &Client, id: &str) {
-        let result = conversations::info(client, &CONFIG.slack.api_token, &conversations::InfoRequest {
-            channel: id,
-            include_locale: None,
-        });
-
-        self.conversation.write().unwrap().insert(id.to_string(), result.unwrap().channel.unwrap());
+    /// Looks up the conversation and returns if it worked
+    fn conversation_info(&mut self, client: &ApiClient, id: &str) -> bool {
+        match fetch_conversation(client, &self.conversation, id) {
+            Ok(_) => true,
+            Err(e) => {
+                error!("Failed to look up the conversation '{}' ({})", id, e);
+                false
+            },
+        }
     }
 }
 
-#[allow(unused_variables)]
-impl slack::EventHandler for MyHandler {
-    fn on_event(&mut self, client: &RtmClient, event: Event) {
+impl MyHandler {
+    /// Hands an event to the admin commands and the plugins subscribed to it.
+    /// Called for every event from Slack, and for the lines typed in `--console` mode
+    pub fn handle_event(&mut self, event: Event) {
         debug!("on_event(event: {:?})", event);
+        stats::count("events");
+        let kind = event_type(&event);
+        metrics::inc("best_bot_events_total", &[("type", kind.as_str())]);
         match event {
             Event::Message(message) => {
                 let message = *message;
                 match message {
                     Message::Standard(message) => {
                         debug!("Message::Standard - {:?}", &message);
-                        
-                        for version in &self.message_standard {
-                            match version {
-                                &PluginVersion::_1(ref plugin) => plugin.event(plugin_api_v1::Event::StandardMessage(&message)),
-                                &PluginVersion::_2(ref plugin) => plugin.event(plugin_api_v2::Event::StandardMessage(&message)),
-                            }
+                        stats::count("messages");
+
+                        let context = admin::Context {
+                            plugins: &self.plugins,
+                            bus: &self.bus,
+                            conversation: &self.conversation,
+                        };
+                        if admin::handle(&message, &context) {
+                            return;
+                        }
+
+                        // The lock is not held while the plugins run, so an admin command can reload one
+                        let (v1, v2) = {
+                            let plugins = self.plugins.read().unwrap();
+                            (
+                                plugins.subscribers_v1(|sub| match *sub {
+                                    plugin_api_v1::EventSubscribe::StandardMessage => true,
+                                }),
+                                plugins.subscribers_v2(|sub| match *sub {
+                                    plugin_api_v2::EventSubscribe::StandardMessage => true,
+                                }),
+                            )
+                        };
+                        // Every plugin gets the message on its own worker thread, see `plugin_worker`
+                        let message = Arc::new(message);
+                        for plugin in v1 {
+                            let (name, message) = (plugin.name(), message.clone());
+                            plugin_worker::submit(&name, move || plugin.event(plugin_api_v1::Event::StandardMessage(&message)));
+                        }
+                        for plugin in v2 {
+                            let (name, message) = (plugin.name(), message.clone());
+                            plugin_worker::submit(&name, move || plugin.event(plugin_api_v2::Event::StandardMessage(&message)));
                         }
                     },
                     _ => (),
@@ -161,36 +355,130 @@ impl slack::EventHandler for MyHandler {
         }
     }
 
-    fn on_close(&mut self, client: &RtmClient) {
-        info!("on_close");
-    }
+    /// A text frame from the RTM websocket: it is recorded if `--record` is given and handed on to `handle_event`
+    pub fn receive(&mut self, frame: &str) {
+        let failed = match self.recorder {
+            Some(ref mut recorder) => recorder.record(frame).err(),
+            None => None,
+        };
+        if let Some(e) = failed {
+            error!("Failed to record an event, recording is stopped ({})", e);
+            self.recorder = None;
+        }
 
-    fn on_connect(&mut self, rtm_client: &RtmClient) {
-        let client = requests::default_client().unwrap();
+        match Event::from_json(frame) {
+            Ok(event) => self.handle_event(event),
+            Err(e) => debug!("Ignoring the frame '{}' ({:?})", frame, e),
+        }
+    }
 
+    /// Looks up the channels and groups from the `rtm.start` response and starts answering the plugins
+    pub fn connected(&mut self, client: &ApiClient, start: &StartResponse) {
         info!("on_connect");
-        // find the general channel id from the `StartResponse`
-        let general_channel_id = rtm_client.start_response();
+        stats::count("connects");
+        health::set_connected(true);
 
-        match &general_channel_id.channels {
+        let mut failed = Vec::new();
+        match &start.channels {
             &Some(ref channels) => {
                 debug!("--- Channels ---");
                 for channel in channels {
-                    self.conversation_info(&client, channel.id.as_ref().expect("You have encounter a channel without a id, THIS SHOULD BE IMPOSSIBLE!!!"));
+                    let id = channel.id.as_ref().expect("You have encounter a channel without a id, THIS SHOULD BE IMPOSSIBLE!!!");
+                    if !self.conversation_info(client, id) {
+                        failed.push(id.clone());
+                    }
                 }
             }
             &None => error!("There are no channels!!!")
         }
-        match &general_channel_id.groups {
+        match &start.groups {
             &Some(ref groups) => {
                 debug!("--- Groups ---");
                 for group in groups {
-                    self.conversation_info(&client, group.id.as_ref().expect("You have encounter a channel without a id, THIS SHOULD BE IMPOSSIBLE!!!"));
+                    let id = group.id.as_ref().expect("You have encounter a channel without a id, THIS SHOULD BE IMPOSSIBLE!!!");
+                    if !self.conversation_info(client, id) {
+                        failed.push(id.clone());
+                    }
                 }
             },
             &None => info!("There are no groups")
         }
+        if !failed.is_empty() {
+            warn!("{} conversation(s) could not be looked up, BEST-Bot is not ready: {}", failed.len(), failed.join(", "));
+        }
+        health::set_bootstrapped(failed);
+
+        self.request_handler();
+    }
+
+    pub fn disconnected(&mut self) {
+        info!("on_close");
+        health::set_connected(false);
+    }
+
+    /// Writes every websocket frame from Slack to the recorder, see `recording`
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Adds a conversation to the cache, so it is not looked up
+    pub fn add_conversation(&self, channel: Channel) {
+        if let Some(id) = channel.id.clone() {
+            self.conversation.write().unwrap().insert(id, channel);
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl slack::EventHandler for MyHandler {
+    fn on_event(&mut self, client: &RtmClient, event: Event) {
+        self.handle_event(event);
+    }
 
-//        self.request_handler();
+    fn on_close(&mut self, client: &RtmClient) {
+        self.disconnected();
     }
-}
\ No newline at end of file
+
+    fn on_connect(&mut self, rtm_client: &RtmClient) {
+        match slack_api::client() {
+            Ok(client) => self.connected(&client, rtm_client.start_response()),
+            Err(e) => error!("Cannot look up the channels ({})", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    enum Sample {
+        Hello,
+        UserTyping { channel: String },
+        Message(Box<Sample>),
+    }
+
+    fn variant(sample: &Sample) -> String {
+        let mut name = VariantName(String::new());
+        let _ = write!(name, "{:?}", sample);
+        name.0
+    }
+
+    #[test]
+    fn names_the_variant() {
+        assert_eq!(variant(&Sample::Hello), "Hello");
+        assert_eq!(variant(&Sample::UserTyping { channel: String::from("C1") }), "UserTyping");
+        assert_eq!(variant(&Sample::Message(Box::new(Sample::Hello))), "Message");
+    }
+
+    #[test]
+    fn knows_when_slack_is_rate_limited() {
+        assert!(chat::PostMessageError::<io::Error>::RateLimited.rate_limited());
+        assert!(chat::PostMessageError::<io::Error>::Unknown(String::from("ratelimited")).rate_limited());
+        assert!(!chat::PostMessageError::<io::Error>::ChannelNotFound.rate_limited());
+        assert!(conversations::InfoError::<io::Error>::Unknown(String::from("ratelimited")).rate_limited());
+        assert!(!conversations::InfoError::<io::Error>::ChannelNotFound.rate_limited());
+    }
+}
@@ -0,0 +1,290 @@
+use log::Level;
+
+use slack_bot;
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+thread_local!(
+    /// Set on the thread posting to Slack, records logged there are never sent to Slack again
+    static IN_SLACK_SINK: Cell<bool> = Cell::new(false)
+);
+
+/// True on the thread posting the log records to Slack
+pub fn is_sink_thread() -> bool {
+    IN_SLACK_SINK.with(|flag| flag.get())
+}
+
+/// A log record on its way to Slack
+pub struct Alert {
+    pub level: Level,
+    pub plugin: String,
+    pub message: String,
+}
+
+/// How the records are posted, read from `config::Log`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub channel: String,
+    pub level: Level,
+    /// At most this many posts per minute, the rest are counted and reported in the next post
+    pub rate_limit: u32,
+    /// The same record is only posted once within this window
+    pub dedup: Duration,
+    /// If set, the records are collected and posted as one message this often
+    pub digest: Option<Duration>,
+}
+
+/// What has happened to a record since it was last posted
+struct Seen {
+    posted: Instant,
+    repeated: usize,
+}
+
+struct Sink {
+    settings: Settings,
+    /// Posts a text to a channel, `slack_bot::post_message` outside of the tests
+    post_message: Box<FnMut(&str, &str) -> Result<(), String>>,
+    seen: BTreeMap<(Level, String, String), Seen>,
+    window_start: Instant,
+    posted_in_window: u32,
+    suppressed: usize,
+    digest: Vec<String>,
+    digest_start: Instant,
+}
+
+/// Starts the thread posting the records, it stops when the returned sender is dropped
+pub fn start(settings: Settings) -> Sender<Alert> {
+    start_with(settings, slack_bot::post_message)
+}
+
+fn start_with<P>(settings: Settings, post_message: P) -> Sender<Alert>
+    where P: FnMut(&str, &str) -> Result<(), String> + Send + 'static
+{
+    let (sender, receiver) = channel::<Alert>();
+
+    thread::spawn(move || {
+        IN_SLACK_SINK.with(|flag| flag.set(true));
+
+        let mut sink = Sink::new(settings, Box::new(post_message));
+
+        loop {
+            match receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(alert) => sink.receive(alert),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => {
+                    sink.flush_digest();
+                    break;
+                },
+            }
+            sink.expire();
+
+            if let Some(every) = sink.settings.digest {
+                if sink.digest_start.elapsed() >= every {
+                    sink.flush_digest();
+                }
+            }
+        }
+    });
+
+    sender
+}
+
+impl Sink {
+    fn new(settings: Settings, post_message: Box<FnMut(&str, &str) -> Result<(), String>>) -> Sink {
+        Sink {
+            settings: settings,
+            post_message: post_message,
+            seen: BTreeMap::new(),
+            window_start: Instant::now(),
+            posted_in_window: 0,
+            suppressed: 0,
+            digest: Vec::new(),
+            digest_start: Instant::now(),
+        }
+    }
+
+    fn receive(&mut self, alert: Alert) {
+        let key = (alert.level, alert.plugin, alert.message);
+
+        if let Some(seen) = self.seen.get_mut(&key) {
+            seen.repeated += 1;
+            return;
+        }
+
+        let text = format!("*{}* [{}] {}", key.0, key.1, key.2);
+        self.seen.insert(key, Seen {
+            posted: Instant::now(),
+            repeated: 0,
+        });
+        self.send(text);
+    }
+
+    /// Forgets the records whose dedup window has passed, and reports the ones repeated in it
+    fn expire(&mut self) {
+        let dedup = self.settings.dedup;
+        let expired: Vec<(Level, String, String)> = self.seen.iter()
+            .filter(|&(_, seen)| seen.posted.elapsed() >= dedup)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            let seen = self.seen.remove(&key).unwrap();
+            if seen.repeated > 0 {
+                self.send(format!("*{}* [{}] {} _(repeated {} more times)_", key.0, key.1, key.2, seen.repeated));
+            }
+        }
+    }
+
+    /// Posts the text now, or adds it to the digest
+    fn send(&mut self, text: String) {
+        if self.settings.digest.is_some() {
+            self.digest.push(text);
+        } else {
+            self.post(text);
+        }
+    }
+
+    fn flush_digest(&mut self) {
+        self.digest_start = Instant::now();
+        if self.digest.is_empty() {
+            return;
+        }
+        let text = format!("Log digest ({} records):\n{}", self.digest.len(), self.digest.join("\n"));
+        self.digest.clear();
+        self.post(text);
+    }
+
+    fn post(&mut self, mut text: String) {
+        if self.window_start.elapsed() >= Duration::from_secs(60) {
+            self.window_start = Instant::now();
+            self.posted_in_window = 0;
+        }
+        if self.posted_in_window >= self.settings.rate_limit {
+            self.suppressed += 1;
+            return;
+        }
+        self.posted_in_window += 1;
+
+        if self.suppressed > 0 {
+            text.push_str(&format!("\n_({} records were not posted because of the rate limit)_", self.suppressed));
+            self.suppressed = 0;
+        }
+
+        // Logged from this thread, so it is not sent to Slack again
+        if let Err(e) = (self.post_message)(&self.settings.channel, &text) {
+            warn!("Failed to post a log record to '{}' ({})", self.settings.channel, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn settings(rate_limit: u32, digest: Option<Duration>) -> Settings {
+        Settings {
+            channel: String::from("#log"),
+            level: Level::Warn,
+            rate_limit: rate_limit,
+            dedup: Duration::from_secs(300),
+            digest: digest,
+        }
+    }
+
+    /// A sink keeping its posts in the returned list instead of sending them to Slack
+    fn sink(settings: Settings) -> (Sink, Rc<RefCell<Vec<String>>>) {
+        let posts = Rc::new(RefCell::new(Vec::new()));
+        let sink = {
+            let posts = posts.clone();
+            Sink::new(settings, Box::new(move |channel, text| {
+                assert_eq!(channel, "#log");
+                posts.borrow_mut().push(text.to_string());
+                Ok(())
+            }))
+        };
+        (sink, posts)
+    }
+
+    fn alert(plugin: &str, message: &str) -> Alert {
+        Alert {
+            level: Level::Warn,
+            plugin: plugin.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn ago(seconds: u64) -> Instant {
+        Instant::now() - Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn posts_a_repeated_record_once() {
+        let (mut sink, posts) = sink(settings(10, None));
+        for _ in 0..3 {
+            sink.receive(alert("karma", "disk full"));
+        }
+        sink.receive(alert("quotes", "disk full"));
+        sink.expire();
+        assert_eq!(*posts.borrow(), vec!["*WARN* [karma] disk full", "*WARN* [quotes] disk full"]);
+
+        for seen in sink.seen.values_mut() {
+            seen.posted = ago(300);
+        }
+        sink.expire();
+        assert_eq!(posts.borrow()[2], "*WARN* [karma] disk full _(repeated 2 more times)_");
+        assert_eq!(posts.borrow().len(), 3);
+
+        // Once the window has passed the record is posted again
+        sink.receive(alert("karma", "disk full"));
+        assert_eq!(posts.borrow()[3], "*WARN* [karma] disk full");
+    }
+
+    #[test]
+    fn counts_the_records_above_the_rate_limit() {
+        let (mut sink, posts) = sink(settings(2, None));
+        for message in &["a", "b", "c", "d"] {
+            sink.receive(alert("karma", message));
+        }
+        assert_eq!(*posts.borrow(), vec!["*WARN* [karma] a", "*WARN* [karma] b"]);
+
+        sink.window_start = ago(60);
+        sink.receive(alert("karma", "e"));
+        assert_eq!(posts.borrow()[2], "*WARN* [karma] e\n_(2 records were not posted because of the rate limit)_");
+    }
+
+    #[test]
+    fn collects_the_records_in_a_digest() {
+        let (mut sink, posts) = sink(settings(10, Some(Duration::from_secs(60))));
+        sink.receive(alert("karma", "a"));
+        sink.receive(alert("karma", "b"));
+        sink.receive(alert("karma", "a"));
+        assert!(posts.borrow().is_empty());
+
+        sink.flush_digest();
+        assert_eq!(*posts.borrow(), vec!["Log digest (2 records):\n*WARN* [karma] a\n*WARN* [karma] b"]);
+
+        // An empty digest is not posted
+        sink.flush_digest();
+        assert_eq!(posts.borrow().len(), 1);
+    }
+
+    #[test]
+    fn posts_from_the_sink_thread() {
+        let (posted, posts) = channel();
+        let sender = start_with(settings(10, None), move |_, text| {
+            // The logger does not send the records logged on this thread back to the sink
+            posted.send((text.to_string(), is_sink_thread())).unwrap();
+            Err(String::from("not connected"))
+        });
+        assert!(!is_sink_thread());
+
+        sender.send(alert("karma", "a")).unwrap();
+        assert_eq!(posts.recv_timeout(Duration::from_secs(5)), Ok((String::from("*WARN* [karma] a"), true)));
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static!(
+    static ref STARTED: Instant = Instant::now();
+    static ref COUNTERS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+);
+
+/// Remembers when BEST-Bot was started, the uptime is counted from the first call
+pub fn start() {
+    ::lazy_static::initialize(&STARTED);
+}
+
+pub fn uptime() -> Duration {
+    STARTED.elapsed()
+}
+
+/// Adds one to the counter, e.g. `count("events")`
+pub fn count(name: &'static str) {
+    *COUNTERS.lock().unwrap().entry(name).or_insert(0) += 1;
+}
+
+pub fn get(name: &str) -> u64 {
+    COUNTERS.lock().unwrap().get(name).cloned().unwrap_or(0)
+}
+
+/// Every counter there has been counted, sorted by name
+pub fn counters() -> Vec<(&'static str, u64)> {
+    COUNTERS.lock().unwrap().iter().map(|(name, value)| (*name, *value)).collect()
+}
+
+/// The uptime as e.g. `2d 3h 4m 5s`
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (days, hours, minutes) = (seconds / 86400, (seconds / 3600) % 24, (seconds / 60) % 60);
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds % 60)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds % 60)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
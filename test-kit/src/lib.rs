@@ -0,0 +1,406 @@
+//! A fake BEST-Bot host for unit testing plugins using api v2.
+//!
+//! `MockHost` gives a plugin the logger and request channels BEST-Bot would give it, answers its
+//! requests from a table of rules and keeps every request and log line for the test to look at.
+//! Events are pushed into the plugin with `message` and `event`.
+//!
+//! A plugin using `best_bot_extension` is loaded with `load_extended`, its requests in that crate
+//! are answered and kept the same way, see `reply_extension` and `take_extension_requests`.
+
+extern crate best_bot_extension as extension;
+extern crate serde_json;
+extern crate template;
+
+use serde_json::{Map, Value};
+
+use template::Name;
+use template::channel_return::{unbounded, ReceiverReturn};
+use template::logger::{Log, LoggerSender};
+use template::plugin_api_v2;
+use template::plugin_api_v2::{Event, Plugin, Reply, Request};
+use template::slack::api::MessageStandard;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Answers a request, or returns `None` to leave it to the next rule.
+/// The table is not locked while a rule runs, so a rule can add rules of its own
+pub type Rule = Arc<Fn(&Request) -> Option<Reply> + Send + Sync>;
+
+/// `Rule` for the requests in `best_bot_extension`
+pub type ExtensionRule = Arc<Fn(&extension::Request) -> Option<extension::Reply> + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// A line the plugin logged through its `LoggerSender`
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLine {
+    pub plugin: String,
+    pub level: Level,
+    pub message: String,
+}
+
+pub struct MockHost {
+    logger_sender: LoggerSender,
+    plugin_sender: plugin_api_v2::Sender,
+    extension_host: extension::Host,
+    rules: Arc<Mutex<Vec<Rule>>>,
+    requests: Arc<Mutex<Vec<Request>>>,
+    extension_rules: Arc<Mutex<Vec<ExtensionRule>>>,
+    extension_requests: Arc<Mutex<Vec<extension::Request>>>,
+    logger_receiver: Mutex<Receiver<(String, Log)>>,
+    logs: Mutex<Vec<LogLine>>,
+}
+
+impl MockHost {
+    /// A host answering every request like BEST-Bot would with a test config, see `default_reply`
+    pub fn new() -> MockHost {
+        let rules: Arc<Mutex<Vec<Rule>>> = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let (plugin_sender, plugin_receiver) = unbounded::<plugin_api_v2::Channel>();
+        {
+            let rules = rules.clone();
+            let requests = requests.clone();
+            thread::spawn(move || loop {
+                let result = ReceiverReturn::recv(&plugin_receiver, |request: Request| {
+                    let reply = answer(&rules, &request).unwrap_or_else(|| default_reply(&request));
+                    requests.lock().unwrap().push(request);
+                    reply
+                });
+                if result.is_err() {
+                    break;
+                }
+            });
+        }
+
+        let extension_rules: Arc<Mutex<Vec<ExtensionRule>>> = Arc::new(Mutex::new(Vec::new()));
+        let extension_requests = Arc::new(Mutex::new(Vec::new()));
+
+        let (extension_host, extension_receiver) = extension::channel();
+        {
+            let rules = extension_rules.clone();
+            let requests = extension_requests.clone();
+            thread::spawn(move || {
+                for envelope in extension_receiver {
+                    let reply = answer(&rules, &envelope.request).unwrap_or_else(|| default_extension_reply(&envelope.request));
+                    requests.lock().unwrap().push(envelope.request);
+                    let _ = envelope.reply.send(reply);
+                }
+            });
+        }
+
+        // Read in `logs`, so a line is there as soon as the plugin has sent it
+        let (logger_sender, logger_receiver) = channel::<(String, Log)>();
+
+        MockHost {
+            logger_sender: logger_sender,
+            plugin_sender: plugin_sender,
+            extension_host: extension_host,
+            rules: rules,
+            requests: requests,
+            extension_rules: extension_rules,
+            extension_requests: extension_requests,
+            logger_receiver: Mutex::new(logger_receiver),
+            logs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds a rule to the reply table. The newest rule is asked first,
+    /// if no rule answers a request it gets the `default_reply`
+    pub fn reply<F>(&self, rule: F) where F: Fn(&Request) -> Option<Reply> + Send + Sync + 'static {
+        self.rules.lock().unwrap().push(Arc::new(rule));
+    }
+
+    /// `reply` for the requests in `best_bot_extension`
+    pub fn reply_extension<F>(&self, rule: F) where F: Fn(&extension::Request) -> Option<extension::Reply> + Send + Sync + 'static {
+        self.extension_rules.lock().unwrap().push(Arc::new(rule));
+    }
+
+    /// Answers `GetChannelName` for `id` with `name`
+    pub fn channel(&self, id: &str, name: &str) {
+        let (id, name) = (id.to_string(), name.to_string());
+        self.reply(move |request| match *request {
+            Request::GetChannelName(ref requested) if *requested == id => Some(Reply::ChannelName(name.clone())),
+            _ => None,
+        });
+    }
+
+    /// Calls `on_plugin_load` with the channels of this host, like BEST-Bot does when it loads a plugin
+    pub fn load<P: Plugin + ?Sized>(&self, plugin: &mut P) {
+        plugin.on_plugin_load(self.logger_sender.clone(), self.plugin_sender.clone());
+    }
+
+    /// Calls `on_plugin_load` and `on_extension_load`, like BEST-Bot does when it loads a plugin exporting `load_extended`
+    pub fn load_extended<P: extension::Plugin + ?Sized>(&self, plugin: &mut P) {
+        self.load(plugin);
+        let sender = self.extension_host.sender(&plugin.name());
+        plugin.on_extension_load(sender);
+    }
+
+    /// Sends a standard message from `user` in `channel` to the plugin
+    pub fn message<P: Plugin + ?Sized>(&self, plugin: &P, user: &str, channel: &str, text: &str) {
+        let message = standard_message(user, channel, text);
+        plugin.event(Event::StandardMessage(&message));
+    }
+
+    pub fn event<P: Plugin + ?Sized>(&self, plugin: &P, event: Event) {
+        plugin.event(event);
+    }
+
+    pub fn extension_event<P: extension::Plugin + ?Sized>(&self, plugin: &P, event: extension::Event) {
+        plugin.extension_event(event);
+    }
+
+    /// Returns the requests received since the last call, oldest first
+    pub fn take_requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().drain(..).collect()
+    }
+
+    /// `take_requests` for the requests in `best_bot_extension`
+    pub fn take_extension_requests(&self) -> Vec<extension::Request> {
+        self.extension_requests.lock().unwrap().drain(..).collect()
+    }
+
+    /// Waits up to `timeout` for at least `count` requests, for plugins sending from their own threads
+    pub fn wait_for_requests(&self, count: usize, timeout: Duration) -> bool {
+        wait_for(|| self.requests.lock().unwrap().len() >= count, timeout)
+    }
+
+    /// `wait_for_requests` for the requests in `best_bot_extension`
+    pub fn wait_for_extension_requests(&self, count: usize, timeout: Duration) -> bool {
+        wait_for(|| self.extension_requests.lock().unwrap().len() >= count, timeout)
+    }
+
+    /// Every line logged so far, oldest first
+    pub fn logs(&self) -> Vec<LogLine> {
+        self.received_logs().clone()
+    }
+
+    /// Whether a line at `level` containing `text` has been logged
+    pub fn logged(&self, level: Level, text: &str) -> bool {
+        self.received_logs().iter().any(|line| line.level == level && line.message.contains(text))
+    }
+
+    /// The logged lines, after moving the ones sent since the last call out of the channel
+    fn received_logs(&self) -> MutexGuard<'_, Vec<LogLine>> {
+        let mut logs = self.logs.lock().unwrap();
+        for (plugin, log) in self.logger_receiver.lock().unwrap().try_iter() {
+            let (level, message) = match log {
+                Log::Error(message) => (Level::Error, message),
+                Log::Warn(message) => (Level::Warn, message),
+                Log::Info(message) => (Level::Info, message),
+                Log::Debug(message) => (Level::Debug, message),
+                Log::Trace(message) => (Level::Trace, message),
+            };
+            logs.push(LogLine {
+                plugin: plugin,
+                level: level,
+                message: message,
+            });
+        }
+        logs
+    }
+}
+
+/// The reply of the newest rule answering `request`. The rules are cloned out of the table first,
+/// so it is not locked while they run
+fn answer<Q, R, F: Fn(&Q) -> Option<R> + ?Sized>(rules: &Mutex<Vec<Arc<F>>>, request: &Q) -> Option<R> {
+    let rules = rules.lock().unwrap().clone();
+    rules.iter().rev().filter_map(|rule| rule(request)).next()
+}
+
+fn wait_for<F: Fn() -> bool>(done: F, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while !done() {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+/// What BEST-Bot answers with a config having only the two tokens `xoxb-test` and `xoxp-test`.
+/// A channel's name is its id
+pub fn default_reply(request: &Request) -> Reply {
+    match *request {
+        Request::ApiToken => Reply::ApiToken(String::from("xoxb-test")),
+        Request::AdminApiToken => Reply::AdminApiToken(String::from("xoxp-test")),
+        Request::WebHooksIncomingToken => Reply::NotConfigured,
+        Request::WebHooksOutgoingToken => Reply::NotConfigured,
+        Request::GetChannelName(ref id) => Reply::ChannelName(id.clone()),
+        Request::ConfigPath => Reply::ConfigPath(PathBuf::from("plugins")),
+    }
+}
+
+/// What BEST-Bot answers to the requests in `best_bot_extension` when no other plugin is loaded.
+/// A message posted or published goes nowhere
+pub fn default_extension_reply(request: &extension::Request) -> extension::Reply {
+    match *request {
+        extension::Request::Publish { .. } => extension::Reply::Published(0),
+        extension::Request::Subscribe(_) => extension::Reply::Subscribed,
+        extension::Request::Unsubscribe(_) => extension::Reply::Unsubscribed,
+        extension::Request::PostMessage { .. } => extension::Reply::MessagePosted(Ok(())),
+    }
+}
+
+/// A `MessageStandard` as Slack would send it
+pub fn standard_message(user: &str, channel: &str, text: &str) -> MessageStandard {
+    let mut object = Map::new();
+    object.insert(String::from("type"), Value::String(String::from("message")));
+    object.insert(String::from("user"), Value::String(user.to_string()));
+    object.insert(String::from("channel"), Value::String(channel.to_string()));
+    object.insert(String::from("text"), Value::String(text.to_string()));
+    object.insert(String::from("ts"), Value::String(String::from("1500000000.000100")));
+    serde_json::from_value(Value::Object(object)).expect("a standard message is always valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use template::channel_return::SenderReturn;
+    use template::plugin_api_v2::EventSubscribe;
+
+    /// Logs the text of every message and asks for the name of its channel
+    #[derive(Default)]
+    struct Probe {
+        logger: Option<LoggerSender>,
+        sender: Option<plugin_api_v2::Sender>,
+    }
+
+    impl Probe {
+        fn ask(&self, request: Request) -> Reply {
+            self.sender.as_ref().unwrap().send(request).ok().expect("the host did not answer")
+        }
+
+        fn channel_name(&self, id: &str) -> String {
+            match self.ask(Request::GetChannelName(id.to_string())) {
+                Reply::ChannelName(name) => name,
+                _ => panic!("not a channel name"),
+            }
+        }
+    }
+
+    impl Name for Probe {
+        fn name(&self) -> String {
+            String::from("probe")
+        }
+    }
+
+    impl Plugin for Probe {
+        fn on_plugin_load(&mut self, logger: LoggerSender, sender: plugin_api_v2::Sender) {
+            let _ = logger.send((self.name(), Log::Info(String::from("loaded"))));
+            self.logger = Some(logger);
+            self.sender = Some(sender);
+        }
+
+        fn event_subscript(&self) -> Vec<EventSubscribe> {
+            vec![EventSubscribe::StandardMessage]
+        }
+
+        fn event(&self, event: Event) {
+            match event {
+                Event::StandardMessage(message) => {
+                    let name = self.channel_name(message.channel.as_ref().unwrap());
+                    let text = format!("{} in #{}", message.text.as_ref().unwrap(), name);
+                    let _ = self.logger.as_ref().unwrap().send((self.name(), Log::Debug(text)));
+                },
+            }
+        }
+    }
+
+    fn loaded() -> (MockHost, Probe) {
+        let host = MockHost::new();
+        let mut plugin = Probe::default();
+        host.load(&mut plugin);
+        (host, plugin)
+    }
+
+    #[test]
+    fn answers_like_best_bot_by_default() {
+        let (host, plugin) = loaded();
+
+        match plugin.ask(Request::ApiToken) {
+            Reply::ApiToken(token) => assert_eq!(token, "xoxb-test"),
+            _ => panic!("not the api token"),
+        }
+        match plugin.ask(Request::WebHooksIncomingToken) {
+            Reply::NotConfigured => (),
+            _ => panic!("the incoming webhooks token is configured"),
+        }
+        match plugin.ask(Request::ConfigPath) {
+            Reply::ConfigPath(path) => assert_eq!(path, PathBuf::from("plugins")),
+            _ => panic!("not the config path"),
+        }
+        assert_eq!(plugin.channel_name("C0TEST"), "C0TEST");
+        assert_eq!(host.take_requests().len(), 4);
+        assert!(host.take_requests().is_empty());
+    }
+
+    #[test]
+    fn asks_the_newest_rule_first() {
+        let (host, plugin) = loaded();
+        host.channel("C0TEST", "old");
+        host.channel("C0TEST", "new");
+        host.channel("C0OTHER", "other");
+
+        assert_eq!(plugin.channel_name("C0TEST"), "new");
+        assert_eq!(plugin.channel_name("C0OTHER"), "other");
+        assert_eq!(plugin.channel_name("C0UNKNOWN"), "C0UNKNOWN");
+    }
+
+    #[test]
+    fn captures_the_log() {
+        let (host, plugin) = loaded();
+        host.channel("C0TEST", "general");
+        host.message(&plugin, "U0TEST", "C0TEST", "hello");
+
+        assert_eq!(host.logs(), vec![
+            LogLine {
+                plugin: String::from("probe"),
+                level: Level::Info,
+                message: String::from("loaded"),
+            },
+            LogLine {
+                plugin: String::from("probe"),
+                level: Level::Debug,
+                message: String::from("hello in #general"),
+            },
+        ]);
+        assert!(host.logged(Level::Debug, "#general"));
+        assert!(!host.logged(Level::Info, "#general"));
+    }
+
+    #[test]
+    fn the_rules_are_not_locked_while_one_runs() {
+        let (host, plugin) = loaded();
+        let (started, wait_for_start) = channel();
+        let (go_on, wait) = channel::<()>();
+        let (started, wait) = (Mutex::new(started), Mutex::new(wait));
+        host.reply(move |_| {
+            started.lock().unwrap().send(()).unwrap();
+            wait.lock().unwrap().recv().unwrap();
+            None
+        });
+
+        let asking = thread::spawn(move || plugin.channel_name("C0TEST"));
+        wait_for_start.recv_timeout(Duration::from_secs(5)).unwrap();
+        // A test or a rule adding a rule while a request is answered used to wait forever
+        host.channel("C0TEST", "general");
+        go_on.send(()).unwrap();
+
+        assert_eq!(asking.join().unwrap(), "C0TEST");
+        assert_eq!(host.take_requests().len(), 1);
+    }
+}
@@ -0,0 +1,188 @@
+//! Runs the BEST-Bot binary against a fake Slack, see `mock_slack`.
+//!
+//! The plugin the tests load is made with `best-bot new-plugin` and built with cargo,
+//! so the first run takes a while.
+
+#[macro_use]
+extern crate serde_json;
+extern crate tungstenite;
+
+mod mock_slack;
+
+use mock_slack::MockSlack;
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static ADMIN: &'static str = "U0ADMIN";
+static ADMIN_DM: &'static str = "D0ADMIN";
+
+/// How long BEST-Bot gets to answer
+fn timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// A running BEST-Bot with its own config, plugin and log folders. It is killed when dropped
+struct Bot {
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Bot {
+    fn start(name: &str, slack: &MockSlack, plugins: &[PathBuf]) -> Bot {
+        let dir = env::temp_dir().join(format!("best-bot-e2e-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("libs")).unwrap();
+        fs::create_dir_all(dir.join("plugins")).unwrap();
+        for plugin in plugins {
+            fs::copy(plugin, dir.join("libs").join(plugin.file_name().unwrap())).unwrap();
+        }
+
+        let config = dir.join("config.toml");
+        write!(File::create(&config).unwrap(), "\
+plugin_path = {:?}
+plugin_config_path = {:?}
+admins = [{:?}]
+
+[slack]
+api_token = \"xoxb-test\"
+admin_api_token = \"xoxp-test\"
+api_url = {:?}
+
+[log]
+level = \"debug\"
+to_file = false
+to_terminal = true
+color = \"never\"
+",
+            dir.join("libs").display().to_string(),
+            dir.join("plugins").display().to_string(),
+            ADMIN,
+            slack.api_url()
+        ).unwrap();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_best-bot"))
+            .arg("--config").arg(&config)
+            .arg("run")
+            .env_remove("BEST_BOT_SLACK_API_URL")
+            .stdin(Stdio::null())
+            .stdout(File::create(dir.join("stdout.log")).unwrap())
+            .stderr(File::create(dir.join("stderr.log")).unwrap())
+            .spawn()
+            .unwrap();
+
+        Bot {
+            child: child,
+            dir: dir,
+        }
+    }
+
+    /// Waits for BEST-Bot to stop and returns its exit code
+    fn wait(&mut self) -> Option<i32> {
+        let start = Instant::now();
+        while start.elapsed() < timeout() {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status.code();
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+
+    /// What BEST-Bot logged, to see why a test failed
+    fn output(&self) -> String {
+        let stdout = fs::read_to_string(self.dir.join("stdout.log")).unwrap_or_default();
+        let stderr = fs::read_to_string(self.dir.join("stderr.log")).unwrap_or_default();
+        format!("{}{}", stdout, stderr)
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A plugin made by `new-plugin` (it answers `!ping` with `pong`), built once and kept in the target folder
+fn fixture_plugin() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("e2e-plugin");
+    fs::create_dir_all(&dir).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_best-bot"))
+        .args(&["new-plugin", "e2e-ping", "--force"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "new-plugin failed");
+
+    // The same versions as BEST-Bot, or the ABI fingerprints differ
+    let crate_dir = dir.join("e2e-ping");
+    let lock = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.lock");
+    if lock.is_file() {
+        fs::copy(&lock, crate_dir.join("Cargo.lock")).unwrap();
+    }
+
+    let output = Command::new(env::var_os("CARGO").unwrap_or("cargo".into()))
+        .arg("build")
+        .current_dir(&crate_dir)
+        .env("CARGO_TARGET_DIR", dir.join("target"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "the plugin did not build:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    dir.join("target").join("debug").join(format!("{}e2e_ping{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX))
+}
+
+#[test]
+fn connects_and_answers_admin_commands() {
+    let mut slack = MockSlack::start(&[("C0GENERAL", "general"), ("G0PRIVATE", "private"), (ADMIN_DM, "admin")]);
+    let mut bot = Bot::start("admin", &slack, &[]);
+
+    slack.message(ADMIN, ADMIN_DM, "help");
+    let reply = slack.wait_for_post(timeout(), |post| post.channel == ADMIN_DM);
+    assert!(reply.map_or(false, |reply| reply.text.contains("channels refresh")), "no help reply:\n{}", bot.output());
+
+    // Only admins get an answer
+    slack.message("U0SOMEONE", ADMIN_DM, "status");
+    slack.message(ADMIN, ADMIN_DM, "status");
+    let reply = slack.wait_for_post(timeout(), |post| post.text.contains("uptime"));
+    assert!(reply.is_some(), "no status reply:\n{}", bot.output());
+    assert_eq!(slack.posts().len(), 2);
+
+    let calls = slack.calls();
+    assert_eq!(calls[0], "rtm.start");
+    assert!(calls.iter().filter(|call| *call == "conversations.info").count() >= 2, "calls: {:?}", calls);
+
+    slack.close();
+    assert_eq!(bot.wait(), Some(0), "BEST-Bot did not stop:\n{}", bot.output());
+}
+
+#[test]
+fn loads_and_runs_a_plugin() {
+    let plugin = fixture_plugin();
+
+    let mut slack = MockSlack::start(&[("C0GENERAL", "general"), (ADMIN_DM, "admin")]);
+    let mut bot = Bot::start("plugins", &slack, &[plugin]);
+
+    slack.message(ADMIN, ADMIN_DM, "plugins");
+    let reply = slack.wait_for_post(timeout(), |post| post.channel == ADMIN_DM);
+    let reply = reply.expect(&format!("no reply to 'plugins':\n{}", bot.output()));
+    assert!(reply.text.contains("e2e-ping"), "the plugin was not loaded:\n{}\n{}", reply.text, bot.output());
+
+    // The plugin posts through the extension crate, which has to reach Slack's chat.postMessage
+    slack.message("U0SOMEONE", "C0GENERAL", "!ping");
+    let reply = slack.wait_for_post(timeout(), |post| post.channel == "C0GENERAL");
+    assert_eq!(reply.map(|post| post.text), Some(String::from("pong")), "no pong:\n{}", bot.output());
+    assert!(slack.calls().iter().any(|call| call == "chat.postMessage"), "calls: {:?}", slack.calls());
+
+    slack.close();
+    assert_eq!(bot.wait(), Some(0), "BEST-Bot did not stop:\n{}", bot.output());
+}
@@ -0,0 +1,67 @@
+/*
+ * A plugin using api v3 for the tests of plugin_api_c: it posts "created" from
+ * best_bot_plugin_new and answers every message with its text and the channel's name.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "best_bot_plugin.h"
+
+typedef struct {
+    const BestBotHostApi *host;
+} EchoPlugin;
+
+uint32_t api_version(void) {
+    return BEST_BOT_PLUGIN_API;
+}
+
+const char *best_bot_plugin_name(void) {
+    return "c-echo";
+}
+
+uint32_t best_bot_plugin_subscriptions(void) {
+    return (1 << BEST_BOT_EVENT_STANDARD_MESSAGE) | (1 << BEST_BOT_EVENT_CONFIG_CHANGED);
+}
+
+void *best_bot_plugin_new(const BestBotHostApi *host) {
+    EchoPlugin *plugin = malloc(sizeof(EchoPlugin));
+    char buffer[64];
+    if (plugin == NULL) {
+        return NULL;
+    }
+    plugin->host = host;
+
+    /* BEST-Bot does not answer requests yet */
+    if (host->request(host->host, BEST_BOT_REQUEST_API_TOKEN, NULL, buffer, sizeof(buffer)) != -1) {
+        host->log(host->host, BEST_BOT_LOG_ERROR, "request answered in best_bot_plugin_new");
+    }
+    host->post_message(host->host, "C0TEST", "created");
+    return plugin;
+}
+
+void best_bot_plugin_event(void *plugin, const BestBotEvent *event) {
+    const BestBotHostApi *host = ((EchoPlugin *) plugin)->host;
+    char name[64];
+    char text[256];
+
+    switch (event->kind) {
+    case BEST_BOT_EVENT_STANDARD_MESSAGE:
+        if (event->message->channel == NULL || event->message->text == NULL) {
+            return;
+        }
+        if (host->request(host->host, BEST_BOT_REQUEST_CHANNEL_NAME, event->message->channel, name, sizeof(name)) < 0) {
+            return;
+        }
+        snprintf(text, sizeof(text), "%s in #%s", event->message->text, name);
+        host->post_message(host->host, event->message->channel, text);
+        break;
+    case BEST_BOT_EVENT_CONFIG_CHANGED:
+        host->log(host->host, BEST_BOT_LOG_INFO, "config changed");
+        break;
+    }
+}
+
+void best_bot_plugin_free(void *plugin) {
+    free(plugin);
+}
@@ -0,0 +1,279 @@
+//! A fake Slack for the end-to-end tests: the Web API methods BEST-Bot calls,
+//! and an RTM websocket sending the events a test scripts.
+
+use serde_json::Value;
+use tungstenite;
+use tungstenite::Message;
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A message BEST-Bot posted with `chat.postMessage`
+#[derive(Clone, Debug)]
+pub struct Post {
+    pub channel: String,
+    pub text: String,
+}
+
+/// What the fake Slack has seen, shared with its threads
+#[derive(Default)]
+struct State {
+    /// Channel ids and their names, served by `rtm.start` and `conversations.info`
+    channels: BTreeMap<String, String>,
+    /// Every Web API method called, in order
+    calls: Vec<String>,
+    posts: Vec<Post>,
+}
+
+pub struct MockSlack {
+    state: Arc<Mutex<State>>,
+    events: Option<Sender<String>>,
+    http_port: u16,
+}
+
+impl MockSlack {
+    /// Starts the fake Slack with `channels` (id, name) on two free ports, one for the Web API
+    /// and one for the websocket
+    pub fn start(channels: &[(&str, &str)]) -> MockSlack {
+        let mut state = State::default();
+        for &(id, name) in channels {
+            state.channels.insert(id.to_string(), name.to_string());
+        }
+        let state = Arc::new(Mutex::new(state));
+
+        let http = TcpListener::bind("127.0.0.1:0").unwrap();
+        let websocket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_port = http.local_addr().unwrap().port();
+        let websocket_url = format!("ws://127.0.0.1:{}/", websocket.local_addr().unwrap().port());
+
+        {
+            let state = state.clone();
+            thread::spawn(move || {
+                for stream in http.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    let state = state.clone();
+                    let websocket_url = websocket_url.clone();
+                    thread::spawn(move || {
+                        let _ = serve_http(stream, &state, &websocket_url);
+                    });
+                }
+            });
+        }
+
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            if let Ok((stream, _)) = websocket.accept() {
+                serve_websocket(stream, receiver);
+            }
+        });
+
+        MockSlack {
+            state: state,
+            events: Some(sender),
+            http_port: http_port,
+        }
+    }
+
+    /// The value for `slack.api_url`
+    pub fn api_url(&self) -> String {
+        format!("http://127.0.0.1:{}/api", self.http_port)
+    }
+
+    /// Sends an event over the websocket, once BEST-Bot is connected
+    pub fn send(&self, event: Value) {
+        if let Some(ref events) = self.events {
+            events.send(event.to_string()).unwrap();
+        }
+    }
+
+    /// Sends a standard message from `user` in `channel`
+    pub fn message(&self, user: &str, channel: &str, text: &str) {
+        self.send(json!({
+            "type": "message",
+            "user": user,
+            "channel": channel,
+            "text": text,
+            "ts": "1500000000.000100",
+        }));
+    }
+
+    /// Closes the websocket, BEST-Bot stops after that
+    pub fn close(&mut self) {
+        self.events = None;
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    pub fn posts(&self) -> Vec<Post> {
+        self.state.lock().unwrap().posts.clone()
+    }
+
+    /// Waits up to `timeout` for a post matching `matches`
+    pub fn wait_for_post<F: Fn(&Post) -> bool>(&self, timeout: Duration, matches: F) -> Option<Post> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if let Some(post) = self.posts().into_iter().find(|post| matches(post)) {
+                return Some(post);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+}
+
+/// Answers a single Web API request, the parameters can be in the query or a form body
+fn serve_http(stream: TcpStream, state: &Mutex<State>, websocket_url: &str) -> ::std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let header = header.to_lowercase();
+        if header.starts_with("content-length:") {
+            content_length = header["content-length:".len()..].trim().parse().unwrap_or(0);
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("").to_string();
+    let (path, query) = match target.find('?') {
+        Some(i) => (target[..i].to_string(), target[i + 1..].to_string()),
+        None => (target.clone(), String::new()),
+    };
+    let mut params = parse_form(&query);
+    params.extend(parse_form(&String::from_utf8_lossy(&body)));
+
+    let method = path.trim_start_matches("/api/").to_string();
+    let reply = answer(&method, &params, state, websocket_url);
+
+    let reply = reply.to_string();
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.len(),
+        reply
+    )?;
+    stream.flush()
+}
+
+fn answer(method: &str, params: &BTreeMap<String, String>, state: &Mutex<State>, websocket_url: &str) -> Value {
+    let mut state = state.lock().unwrap();
+    state.calls.push(method.to_string());
+
+    match method {
+        "rtm.start" | "rtm.connect" => {
+            let channels: Vec<Value> = state.channels.iter()
+                .filter(|&(id, _)| id.starts_with('C'))
+                .map(|(id, name)| json!({"id": id, "name": name, "is_channel": true}))
+                .collect();
+            let groups: Vec<Value> = state.channels.iter()
+                .filter(|&(id, _)| id.starts_with('G'))
+                .map(|(id, name)| json!({"id": id, "name": name, "is_group": true}))
+                .collect();
+            json!({
+                "ok": true,
+                "url": websocket_url,
+                "self": {"id": "U0BOT", "name": "best-bot"},
+                "team": {"id": "T0TEST", "name": "Test", "domain": "test"},
+                "channels": channels,
+                "groups": groups,
+                "ims": [],
+                "users": [],
+            })
+        },
+        "conversations.info" => {
+            let id = params.get("channel").cloned().unwrap_or_default();
+            match state.channels.get(&id) {
+                Some(name) => json!({"ok": true, "channel": {"id": id, "name": name}}),
+                None => json!({"ok": false, "error": "channel_not_found"}),
+            }
+        },
+        "chat.postMessage" => {
+            let channel = params.get("channel").cloned().unwrap_or_default();
+            state.posts.push(Post {
+                channel: channel.clone(),
+                text: params.get("text").cloned().unwrap_or_default(),
+            });
+            json!({"ok": true, "channel": channel, "ts": "1500000000.000200"})
+        },
+        _ => json!({"ok": false, "error": "unknown_method"}),
+    }
+}
+
+/// Sends `hello` like Slack does, then every scripted event until the test closes the websocket
+fn serve_websocket(stream: TcpStream, events: Receiver<String>) {
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    if socket.send(Message::text(String::from(r#"{"type": "hello"}"#))).is_err() {
+        return;
+    }
+
+    loop {
+        match events.recv_timeout(Duration::from_millis(100)) {
+            Ok(event) => {
+                if socket.send(Message::text(event)).is_err() {
+                    return;
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+/// Parses `a=1&b=two%20words`
+fn parse_form(data: &str) -> BTreeMap<String, String> {
+    data.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(i) => (decode(&pair[..i]), decode(&pair[i + 1..])),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    },
+                    None => decoded.push(b'%'),
+                }
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}